@@ -0,0 +1,100 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use glam::{IVec3, UVec3};
+use rayon::prelude::*;
+
+use graphics_test::rendering::vertex::Vertex;
+use graphics_test::voxels::voxel_scene::VoxelScene;
+
+const CHUNK_SIZES: [u32; 4] = [8, 16, 32, 64];
+
+// Every chunk size generates this same region, so the results are comparable
+const WORLD_SIZE: UVec3 = glam::const_uvec3!([128, 64, 128]);
+
+fn generate_scene(chunk_size: u32) -> VoxelScene {
+    let mut scene = VoxelScene::with_chunk_size(chunk_size);
+    let chunks = WORLD_SIZE / chunk_size;
+    for x in 0..chunks.x {
+        for y in 0..chunks.y {
+            for z in 0..chunks.z {
+                scene.initialize_chunk(&IVec3::new(x as i32, y as i32, z as i32));
+            }
+        }
+    }
+
+    pollster::block_on(scene.process_initialization_queue());
+    scene
+}
+
+fn voxel_count() -> u64 {
+    (WORLD_SIZE.x * WORLD_SIZE.y * WORLD_SIZE.z) as u64
+}
+
+fn bench_generation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generation");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(voxel_count()));
+
+    for chunk_size in CHUNK_SIZES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(chunk_size),
+            &chunk_size,
+            |b, &chunk_size| b.iter(|| generate_scene(chunk_size)),
+        );
+    }
+
+    group.finish();
+}
+
+fn bench_meshing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("meshing");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(voxel_count()));
+
+    for chunk_size in CHUNK_SIZES {
+        let mut scene = generate_scene(chunk_size);
+        group.bench_function(BenchmarkId::from_parameter(chunk_size), |b| {
+            b.iter(|| {
+                scene
+                    .chunks
+                    .par_iter_mut()
+                    .for_each(|(_, chunk)| chunk.generate_mesh())
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Criterion only measures time, so memory is reported alongside the timings.
+fn report_memory(_c: &mut Criterion) {
+    println!(
+        "{:>10} {:>8} {:>14} {:>12} {:>12}",
+        "chunk size", "chunks", "storage bytes", "bytes/voxel", "mesh bytes"
+    );
+
+    for chunk_size in CHUNK_SIZES {
+        let scene = generate_scene(chunk_size);
+        let storage = scene.storage_memory_usage();
+        let mesh: usize = scene
+            .chunks
+            .values()
+            .flat_map(|chunk| [&chunk.mesh, &chunk.transparent_mesh])
+            .map(|mesh| {
+                mesh.vertices.len() * std::mem::size_of::<Vertex>()
+                    + mesh.indices.len() * std::mem::size_of::<u32>()
+            })
+            .sum();
+
+        println!(
+            "{:>10} {:>8} {:>14} {:>12.3} {:>12}",
+            chunk_size,
+            scene.chunks.len(),
+            storage,
+            storage as f64 / voxel_count() as f64,
+            mesh,
+        );
+    }
+}
+
+criterion_group!(benches, report_memory, bench_generation, bench_meshing);
+criterion_main!(benches);
@@ -0,0 +1,135 @@
+//! Meshes a small world on both the CPU and the GPU and checks that they produce the same faces.
+//! Runs headless, and prefers a software adapter so it also works without a GPU.
+
+use glam::{IVec3, Vec3};
+
+use graphics_test::rendering::gpu_mesher::GpuMesher;
+use graphics_test::rendering::mesh::Mesh;
+use graphics_test::voxels::brush::{Brush, BrushMode, BrushShape};
+use graphics_test::voxels::voxel_data::{VoxelData, VoxelShape};
+use graphics_test::voxels::voxel_profile::VOXEL_PROFILES;
+use graphics_test::voxels::voxel_scene::VoxelScene;
+
+/// Every triangle as the bytes of its three vertices, sorted, since the GPU emits faces in
+/// whatever order its invocations finish.
+fn sorted_triangles(mesh: &Mesh) -> Vec<Vec<u8>> {
+    let mut triangles = mesh
+        .indices
+        .chunks(3)
+        .map(|triangle| {
+            triangle
+                .iter()
+                .flat_map(|&index| bytemuck::bytes_of(&mesh.vertices[index as usize]).to_vec())
+                .collect::<Vec<u8>>()
+        })
+        .collect::<Vec<_>>();
+    triangles.sort();
+    triangles
+}
+
+fn main() {
+    env_logger::init();
+
+    let instance = wgpu::Instance::new(wgpu::Backends::all());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+        power_preference: wgpu::PowerPreference::default(),
+        compatible_surface: None,
+        force_fallback_adapter: true,
+    }))
+    .or_else(|| {
+        pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+    })
+    .expect("No adapter available");
+    println!("Using {:?}", adapter.get_info());
+
+    let (device, queue) = pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: None,
+            features: wgpu::Features::empty(),
+            // The mesher needs more storage buffers than the downlevel defaults allow
+            limits: adapter.limits(),
+        },
+        None,
+    ))
+    .unwrap();
+
+    let mut scene = VoxelScene::new();
+    for x in 0..4 {
+        for z in 0..4 {
+            scene.initialize_chunk(&IVec3::new(x, 0, z));
+        }
+    }
+    pollster::block_on(scene.process_initialization_queue());
+
+    let dirt = VOXEL_PROFILES.id_of("dirt").unwrap();
+    let glass = VOXEL_PROFILES.id_of("glass").unwrap();
+
+    // Carve and smooth the terrain so partially filled voxels sit next to full ones
+    let mut edit = scene.begin_edit();
+    for (mode, shape, center) in [
+        (
+            BrushMode::Subtract,
+            BrushShape::Sphere,
+            Vec3::new(6.3, 4.6, 7.2),
+        ),
+        (
+            BrushMode::Add,
+            BrushShape::Cylinder,
+            Vec3::new(17.5, 5.2, 9.8),
+        ),
+        (
+            BrushMode::Subtract,
+            BrushShape::Box,
+            Vec3::new(24.0, 6.5, 22.4),
+        ),
+        (
+            BrushMode::Smooth,
+            BrushShape::Sphere,
+            Vec3::new(11.0, 4.0, 18.0),
+        ),
+    ] {
+        Brush::new(shape, mode, 2.7, dirt).dab(&mut edit, center);
+    }
+    // Every shape once in glass, packed together so their sides hide each other too
+    for bits in 0..=u8::MAX {
+        let position = IVec3::new(bits as i32 % 16, 7, bits as i32 / 16);
+        let voxel = VoxelData {
+            shape: VoxelShape::from_bits(bits),
+            profile: glass,
+            fluid_level: 0,
+        };
+        edit.set_voxel(&position, voxel);
+    }
+    edit.commit();
+    let dirty = scene.take_dirty_chunks();
+    scene.remesh_chunks(&dirty);
+
+    let mesher = GpuMesher::new(&device);
+    let mut mismatches = 0;
+    for (position, chunk) in scene.chunks.iter() {
+        let gpu_mesh = mesher.mesh_chunk(&device, &queue, chunk);
+        let (opaque, transparent) = GpuMesher::read_back(&device, &queue, &gpu_mesh);
+
+        for (name, cpu, gpu) in [
+            ("opaque", &chunk.mesh, &opaque),
+            ("transparent", &chunk.transparent_mesh, &transparent),
+        ] {
+            if sorted_triangles(cpu) != sorted_triangles(gpu) {
+                mismatches += 1;
+                println!(
+                    "Chunk {} {} mesh differs: CPU has {} indices, GPU has {}",
+                    position,
+                    name,
+                    cpu.indices.len(),
+                    gpu.indices.len()
+                );
+            }
+        }
+    }
+
+    if mismatches > 0 {
+        println!("{} meshes differ", mismatches);
+        std::process::exit(1);
+    }
+    println!("All {} chunks match", scene.chunks.len());
+}
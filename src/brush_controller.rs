@@ -0,0 +1,95 @@
+use cgmath::InnerSpace;
+use glam::{IVec3, Vec3};
+use winit::event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+
+use crate::rendering::camera::Camera;
+use crate::voxels::brush::{Brush, BrushMode, BrushShape};
+use crate::voxels::voxel_edit::EditTransaction;
+use crate::voxels::voxel_scene::VoxelScene;
+
+// How far in front of the camera the brush is applied
+const BRUSH_DISTANCE: f32 = 8.0;
+const MIN_RADIUS: f32 = 0.5;
+const MAX_RADIUS: f32 = 16.0;
+
+pub struct BrushController {
+    pub brush: Brush,
+    is_stroking: bool,
+    // The stroke in progress, committed as a single undo step once the button is released
+    stroke: Option<EditTransaction>,
+}
+
+impl BrushController {
+    pub fn new(brush: Brush) -> Self {
+        Self {
+            brush,
+            is_stroking: false,
+            stroke: None,
+        }
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.is_stroking = *state == ElementState::Pressed;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => {
+                match keycode {
+                    VirtualKeyCode::Key1 => self.brush.mode = BrushMode::Add,
+                    VirtualKeyCode::Key2 => self.brush.mode = BrushMode::Subtract,
+                    VirtualKeyCode::Key3 => self.brush.mode = BrushMode::Paint,
+                    VirtualKeyCode::Key4 => self.brush.mode = BrushMode::Flatten,
+                    VirtualKeyCode::Key5 => self.brush.mode = BrushMode::Smooth,
+                    VirtualKeyCode::Key6 => self.brush.shape = BrushShape::Sphere,
+                    VirtualKeyCode::Key7 => self.brush.shape = BrushShape::Box,
+                    VirtualKeyCode::Key8 => self.brush.shape = BrushShape::Cylinder,
+                    VirtualKeyCode::LBracket => {
+                        self.brush.radius = (self.brush.radius - 0.5).max(MIN_RADIUS)
+                    }
+                    VirtualKeyCode::RBracket => {
+                        self.brush.radius = (self.brush.radius + 0.5).min(MAX_RADIUS)
+                    }
+                    _ => return false,
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Dabs the brush in front of the camera while the button is held and commits the stroke
+    /// once it is released. Returns the voxels changed by this dab, so fluids around them can
+    /// start moving during the stroke.
+    pub fn update(&mut self, scene: &mut VoxelScene, camera: &Camera) -> Vec<IVec3> {
+        if !self.is_stroking {
+            if let Some(stroke) = self.stroke.take() {
+                scene.resume_edit(stroke).commit();
+            }
+            return Vec::new();
+        }
+
+        let forward = (camera.target - camera.eye).normalize();
+        let center = camera.eye + forward * BRUSH_DISTANCE;
+
+        let mut edit = scene.resume_edit(self.stroke.take().unwrap_or_default());
+        let changed = self
+            .brush
+            .dab(&mut edit, Vec3::new(center.x, center.y, center.z));
+        self.stroke = Some(edit.suspend());
+
+        changed
+    }
+}
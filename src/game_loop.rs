@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+/// Accumulates frame time and hands it out in fixed size simulation ticks.
+///
+/// Rendering happens once per frame, in between ticks, using `alpha` to interpolate
+/// between the previous and the current simulation state.
+pub struct FixedTimestep {
+    tick_duration: Duration,
+    max_catch_up_steps: u32,
+    accumulator: Duration,
+    last_frame: Instant,
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f64, max_catch_up_steps: u32) -> Self {
+        Self {
+            tick_duration: Duration::from_secs_f64(1.0 / tick_rate),
+            max_catch_up_steps,
+            accumulator: Duration::ZERO,
+            last_frame: Instant::now(),
+        }
+    }
+
+    /// Adds the time since the last frame and returns how many ticks should run now.
+    pub fn advance(&mut self) -> u32 {
+        let now = Instant::now();
+        self.accumulator += now - self.last_frame;
+        self.last_frame = now;
+
+        let mut steps = 0;
+        while self.accumulator >= self.tick_duration {
+            if steps == self.max_catch_up_steps {
+                // We can't keep up, drop the backlog instead of spiraling further behind
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+            self.accumulator -= self.tick_duration;
+            steps += 1;
+        }
+        steps
+    }
+
+    /// How far the current frame is between the last tick and the next one, in [0, 1).
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.tick_duration.as_secs_f32()
+    }
+}
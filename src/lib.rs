@@ -0,0 +1,9 @@
+#![feature(int_roundings)]
+
+pub mod brush_controller;
+pub mod camera_controller;
+pub mod game_loop;
+pub mod profiler;
+pub mod rendering;
+pub mod state;
+pub mod voxels;
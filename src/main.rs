@@ -1,23 +1,30 @@
-#![feature(int_roundings)]
+use graphics_test::brush_controller::BrushController;
+use graphics_test::game_loop::FixedTimestep;
+use graphics_test::state::*;
 
-mod rendering;
-mod camera_controller;
-mod state;
-mod voxels;
+use std::collections::HashSet;
 
-use state::*;
-use voxels::voxel_scene::CHUNK_SIZE;
-
-use glam::{IVec3, UVec3};
-use rayon::iter::{IntoParallelRefMutIterator, ParallelIterator};
+use glam::{IVec3, UVec3, Vec3};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
-use crate::rendering::mesh::Mesh;
+use graphics_test::rendering::mesh::Mesh;
+use graphics_test::rendering::render_pass_data::RenderPassKind;
+use graphics_test::rendering::vertex::Vertex;
+
+use graphics_test::voxels::brush::{Brush, BrushMode, BrushShape};
+use graphics_test::voxels::fluid_simulation::FluidSimulation;
+use graphics_test::voxels::voxel_scene::{VoxelChunk, VoxelScene};
 
-use crate::voxels::voxel_scene::VoxelScene;
+// Simulation ticks per second, independent of the display refresh rate
+const TICK_RATE: f64 = 60.0;
+// Ticks allowed per frame before the simulation gives up on catching up
+const MAX_CATCH_UP_STEPS: u32 = 5;
+// Voxels further away than this aren't shown as the target in the overlay
+const TARGET_DISTANCE: f32 = 64.0;
 
 fn main() -> Result<(), ()> {
     env_logger::init(); // Tells WGPU to inform us of errors, rather than failing silently
@@ -26,10 +33,21 @@ fn main() -> Result<(), ()> {
     let window = WindowBuilder::new().build(&event_loop).unwrap(); // Create a window
     let mut scene = VoxelScene::new();
     let mut state = pollster::block_on(State::new(&window));
+    state.set_chunk_size(scene.chunk_size());
+    // Captures world generation and everything after it until F7 is pressed
+    if std::env::args().any(|arg| arg == "--trace") {
+        state.profiler.start_capture();
+    }
+    let mut fluids = FluidSimulation::new();
 
     pollster::block_on(
         generate_world(&mut scene, &mut state, UVec3::new(50, 1, 50))
     );
+    fluids.activate_unsettled(&scene);
+
+    let mut timestep = FixedTimestep::new(TICK_RATE, MAX_CATCH_UP_STEPS);
+    let mut modifiers = ModifiersState::empty();
+    let mut brushes = BrushController::new(Brush::new(BrushShape::Sphere, BrushMode::Add, 2.0, 0));
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -37,7 +55,8 @@ fn main() -> Result<(), ()> {
                 ref event,
                 window_id,
             } if window_id == window.id() => {
-                if !state.input(event) {
+                let scope = state.profiler.begin("input");
+                if !state.input(event) && !brushes.process_events(event) {
                     match event {
                         WindowEvent::CloseRequested
                         | WindowEvent::KeyboardInput {
@@ -55,13 +74,78 @@ fn main() -> Result<(), ()> {
                         WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                             state.resize(**new_inner_size);
                         }
+                        WindowEvent::ModifiersChanged(new_modifiers) => {
+                            modifiers = *new_modifiers;
+                        }
+                        WindowEvent::KeyboardInput {
+                            input:
+                                KeyboardInput {
+                                    state: ElementState::Pressed,
+                                    virtual_keycode: Some(keycode @ (VirtualKeyCode::Z | VirtualKeyCode::Y)),
+                                    ..
+                                },
+                            ..
+                        } if modifiers.ctrl() => {
+                            let applied = match keycode {
+                                VirtualKeyCode::Z if !modifiers.shift() => scene.undo(),
+                                _ => scene.redo(),
+                            };
+
+                            // Edited voxels may have freed up space for fluids or added new ones
+                            for change in applied.iter().flat_map(|transaction| transaction.changes()) {
+                                fluids.activate_around(change.position);
+                            }
+                        }
                         _ => {}
                     }
                 }
+                state.profiler.end(scope);
             }
 
             Event::RedrawRequested(window_id) if window_id == window.id() => {
-                state.update();
+                let frame = state.profiler.begin("frame");
+                let mut changed_chunks = HashSet::new();
+                let scope = state.profiler.begin("simulation");
+                for _ in 0..timestep.advance() {
+                    state.tick();
+
+                    for position in brushes.update(&mut scene, &state.camera) {
+                        fluids.activate_around(position);
+                    }
+
+                    changed_chunks.extend(fluids.tick(&mut scene));
+                }
+                state.profiler.end(scope);
+                changed_chunks.extend(scene.take_dirty_chunks());
+
+                let eye = state.camera.eye;
+                changed_chunks.extend(scene.update_lod_levels(Vec3::new(eye.x, eye.y, eye.z)));
+
+                if !changed_chunks.is_empty() {
+                    let scope = state.profiler.begin("meshing");
+                    let remeshed = scene.remesh_chunks(&changed_chunks);
+                    state.profiler.end(scope);
+
+                    let scope = state.profiler.begin("uploads");
+                    upload_chunks(&scene, &mut state, &remeshed);
+                    state.profiler.end(scope);
+                }
+
+                if state.overlay.visible {
+                    let forward = state.camera.target - state.camera.eye;
+                    state.overlay.loaded_chunks = scene.chunks.len();
+                    state.overlay.target = scene.raycast(
+                        Vec3::new(eye.x, eye.y, eye.z),
+                        Vec3::new(forward.x, forward.y, forward.z),
+                        TARGET_DISTANCE,
+                    );
+                }
+
+                let scope = state.profiler.begin("update");
+                state.update(timestep.alpha());
+                state.profiler.end(scope);
+
+                let scope = state.profiler.begin("submit");
                 match state.render() {
                     Ok(_) => {}
                     // Reconfigure the surface if lost
@@ -71,6 +155,8 @@ fn main() -> Result<(), ()> {
                     // All other errors (Outdated, Timeout) should be resolved by the next frame
                     Err(e) => eprintln!("{:?}", e),
                 }
+                state.profiler.end(scope);
+                state.profiler.end(frame);
             }
             Event::MainEventsCleared => {
                 // RedrawRequested will only trigger once, unless we manually
@@ -83,15 +169,13 @@ fn main() -> Result<(), ()> {
 }
 
 pub async fn generate_world(scene: &mut VoxelScene, state: &mut State, size: UVec3) {
-    state.render_passes.clear();
-    state.add_render_pass();
-
     // Start timer
     let total_chunk_count = size.x * size.y * size.z;
 
     use std::time::Instant;
     let now = Instant::now();
 
+    let scope = state.profiler.begin("generation");
     for x in 0..size.x {
         for y in 0..size.y {
             for z in 0..size.z {
@@ -101,36 +185,12 @@ pub async fn generate_world(scene: &mut VoxelScene, state: &mut State, size: UVe
     }
 
     scene.process_initialization_queue().await;
+    state.profiler.end(scope);
 
-    let pass = state.render_passes.last_mut().unwrap();
-
-    let meshes = scene.chunks.par_iter_mut().map(|(position, chunk)| {
-        let mesh = &mut chunk.mesh;
-
-        mesh.vertices.iter_mut().for_each(|vert| {
-            vert.position = [
-                vert.position[0] + (position.x as f32 * CHUNK_SIZE as f32),
-                vert.position[1] + (position.y as f32 * CHUNK_SIZE as f32),
-                vert.position[2] + (position.z as f32 * CHUNK_SIZE as f32),
-            ]
-        });
-
-        mesh
-    }).collect::<Vec<&mut Mesh>>();
-
-    let mut combined_verts = Vec::new();
-    let mut combined_indices = Vec::new();
-    meshes.into_iter().map(|mesh| (&mut mesh.vertices, &mut mesh.indices)).for_each(|(verts, indics)| {
-        let offset = combined_verts.len() as u32;
-
-        combined_verts.append(verts);
-
-        combined_indices.reserve(indics.len());
-        combined_indices.extend(indics.iter().map(|&x| x + offset));
-    });
-
-    pass.set_vertices(&state.device, &mut combined_verts);
-    pass.set_indices(&state.device, &mut combined_indices);
+    let scope = state.profiler.begin("uploads");
+    let positions = scene.chunks.keys().copied().collect::<HashSet<IVec3>>();
+    upload_chunks(scene, state, &positions);
+    state.profiler.end(scope);
 
     // End timer
     let elapsed = now.elapsed();
@@ -140,4 +200,68 @@ pub async fn generate_world(scene: &mut VoxelScene, state: &mut State, size: UVe
         elapsed / total_chunk_count,
         1.0 / (elapsed / total_chunk_count).as_secs_f32(),
     );
-}
\ No newline at end of file
+
+    let storage_bytes = scene.storage_memory_usage();
+    println!(
+        "Voxel storage uses {:.2} MiB\nWhich is {} bytes per chunk",
+        storage_bytes as f32 / (1024.0 * 1024.0),
+        storage_bytes / scene.chunks.len().max(1),
+    );
+}
+
+/// Uploads the meshes of the chunks at `positions` into the render passes,
+/// removing the ones that are no longer in the scene.
+pub fn upload_chunks(scene: &VoxelScene, state: &mut State, positions: &HashSet<IVec3>) {
+    for pass in state.render_passes.iter_mut() {
+        let select: fn(&VoxelChunk) -> &Mesh = match pass.kind {
+            RenderPassKind::Opaque => |chunk| &chunk.mesh,
+            RenderPassKind::Transparent => |chunk| &chunk.transparent_mesh,
+        };
+
+        for position in positions {
+            if !scene.chunks.contains_key(position) {
+                pass.arena.remove_chunk(position);
+            }
+        }
+
+        for (position, center, vertices, indices) in offset_chunk_meshes(scene, positions, select) {
+            pass.arena.upload_chunk(&state.device, &state.queue, position, center, &vertices, indices);
+        }
+    }
+
+    // Removed chunks take their lights with them
+    for position in positions {
+        let emitters = scene.chunks.get(position).map_or(&[][..], |chunk| &chunk.emitters[..]);
+        state.set_chunk_emitters(*position, emitters);
+    }
+
+    state.debug_views.set_chunks(scene.chunks.keys().copied());
+}
+
+/// Moves the vertices of every chunk at `positions` into scene space.
+fn offset_chunk_meshes<'a>(
+    scene: &'a VoxelScene,
+    positions: &HashSet<IVec3>,
+    select: fn(&VoxelChunk) -> &Mesh,
+) -> Vec<(IVec3, Vec3, Vec<Vertex>, &'a Vec<u32>)> {
+    let chunk_size = scene.chunk_size();
+    positions.par_iter().filter_map(|position| {
+        let chunk = scene.chunks.get(position)?;
+        let offset = chunk.scenespace_pos().as_vec3();
+        let mesh = select(chunk);
+
+        let vertices = mesh.vertices.iter().map(|vert| Vertex {
+            position: [
+                vert.position[0] + offset.x,
+                vert.position[1] + offset.y,
+                vert.position[2] + offset.z,
+            ],
+            ..*vert
+        }).collect::<Vec<Vertex>>();
+
+        // Chunks at a lower level of detail hold the mesh of their whole group
+        let (_, group_size) = scene.lod_group(position);
+        let center = offset + Vec3::splat((chunk_size * group_size as u32) as f32 / 2.0);
+        Some((*position, center, vertices, &mesh.indices))
+    }).collect()
+}
@@ -0,0 +1,160 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+// Thread ids the events are grouped under in the trace viewer
+const CPU_THREAD: u32 = 1;
+const GPU_THREAD: u32 = 2;
+
+/// A span of time in the Chrome tracing format, as a complete ("X") event.
+#[derive(Serialize, Clone, Debug)]
+pub struct TraceEvent {
+    name: &'static str,
+    #[serde(rename = "cat")]
+    category: &'static str,
+    #[serde(rename = "ph")]
+    phase: &'static str,
+    // Microseconds since the profiler was created
+    ts: f64,
+    dur: f64,
+    pid: u32,
+    tid: u32,
+}
+
+#[derive(Serialize)]
+struct ThreadName {
+    name: &'static str,
+    ph: &'static str,
+    pid: u32,
+    tid: u32,
+    args: ThreadNameArgs,
+}
+
+#[derive(Serialize)]
+struct ThreadNameArgs {
+    name: &'static str,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace {
+    trace_events: Vec<serde_json::Value>,
+    display_time_unit: &'static str,
+}
+
+/// Returned by `Profiler::begin` and handed back to `Profiler::end` to close the scope.
+#[must_use]
+pub struct Scope {
+    name: &'static str,
+    // None when the profiler wasn't capturing as the scope began
+    start: Option<Instant>,
+}
+
+/// Records CPU scopes and GPU pass timings while capturing, for viewing in a trace viewer
+/// such as chrome://tracing or Perfetto.
+pub struct Profiler {
+    capturing: bool,
+    origin: Instant,
+    events: Vec<TraceEvent>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self {
+            capturing: false,
+            origin: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capturing
+    }
+
+    /// Starts recording, dropping the events of an earlier capture.
+    pub fn start_capture(&mut self) {
+        self.capturing = true;
+        self.events.clear();
+    }
+
+    /// Stops recording and returns the captured events.
+    pub fn stop_capture(&mut self) -> Vec<TraceEvent> {
+        self.capturing = false;
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn begin(&self, name: &'static str) -> Scope {
+        Scope {
+            name,
+            start: self.capturing.then(Instant::now),
+        }
+    }
+
+    pub fn end(&mut self, scope: Scope) {
+        if let Some(start) = scope.start {
+            self.record(scope.name, "cpu", CPU_THREAD, start, start.elapsed());
+        }
+    }
+
+    /// Records a render pass that ran on the GPU from `start` for `duration`,
+    /// with `start` converted to the CPU's clock.
+    pub fn record_gpu(&mut self, name: &'static str, start: Instant, duration: Duration) {
+        if self.capturing {
+            self.record(name, "gpu", GPU_THREAD, start, duration);
+        }
+    }
+
+    fn record(
+        &mut self,
+        name: &'static str,
+        category: &'static str,
+        thread: u32,
+        start: Instant,
+        duration: Duration,
+    ) {
+        let since_origin = start.saturating_duration_since(self.origin);
+        self.events.push(TraceEvent {
+            name,
+            category,
+            phase: "X",
+            ts: since_origin.as_secs_f64() * 1e6,
+            dur: duration.as_secs_f64() * 1e6,
+            pid: 1,
+            tid: thread,
+        });
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes `events` as a JSON trace that chrome://tracing and Perfetto can open.
+pub fn write_chrome_trace(path: &Path, events: &[TraceEvent]) -> io::Result<()> {
+    let thread_names = [(CPU_THREAD, "CPU"), (GPU_THREAD, "GPU")].map(|(tid, name)| ThreadName {
+        name: "thread_name",
+        ph: "M",
+        pid: 1,
+        tid,
+        args: ThreadNameArgs { name },
+    });
+
+    let trace_events = thread_names
+        .iter()
+        .map(serde_json::to_value)
+        .chain(events.iter().map(serde_json::to_value))
+        .collect::<Result<Vec<_>, _>>()?;
+    let trace = Trace {
+        trace_events,
+        display_time_unit: "ms",
+    };
+
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(writer, &trace)?;
+    Ok(())
+}
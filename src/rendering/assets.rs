@@ -0,0 +1,175 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use hotwatch::{Event, Hotwatch};
+
+// Assets are read from the source tree in development builds, so they can be edited while running
+const ASSET_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+/// Whether assets are loaded from disk and watched instead of using the copies baked into the binary.
+pub fn dev_mode() -> bool {
+    cfg!(debug_assertions)
+}
+
+/// A shader or texture, embedded in the binary and optionally loaded from disk.
+/// Assets are identified by their path.
+#[derive(Copy, Clone, Debug)]
+pub struct Asset {
+    // Relative to `src/`
+    pub path: &'static str,
+    embedded: &'static [u8],
+}
+
+impl Asset {
+    pub const fn new(path: &'static str, embedded: &'static [u8]) -> Self {
+        Self { path, embedded }
+    }
+
+    pub fn disk_path(&self) -> PathBuf {
+        Path::new(ASSET_DIR).join(self.path)
+    }
+
+    /// The file on disk in dev mode, falling back to the embedded copy if it can't be read.
+    pub fn load(&self) -> Cow<'static, [u8]> {
+        if dev_mode() {
+            match std::fs::read(self.disk_path()) {
+                Ok(bytes) => return Cow::Owned(bytes),
+                Err(error) => eprintln!("Using embedded {}: {}", self.path, error),
+            }
+        }
+        Cow::Borrowed(self.embedded)
+    }
+
+    pub fn load_string(&self) -> String {
+        String::from_utf8_lossy(&self.load()).into_owned()
+    }
+}
+
+impl PartialEq for Asset {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for Asset {}
+
+impl std::hash::Hash for Asset {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.path.hash(state);
+    }
+}
+
+/// Watches the directories of assets and collects the ones that changed since the last check.
+pub struct AssetWatcher {
+    _hotwatch: Hotwatch,
+    assets: Vec<(PathBuf, Asset)>,
+    changed_paths: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+impl AssetWatcher {
+    pub fn new(assets: &[Asset]) -> Result<Self, hotwatch::Error> {
+        let mut hotwatch = Hotwatch::new()?;
+        let changed_paths = Arc::new(Mutex::new(HashSet::new()));
+
+        let assets = assets
+            .iter()
+            .map(|asset| {
+                let path = asset.disk_path();
+                (path.canonicalize().unwrap_or(path), *asset)
+            })
+            .collect::<Vec<_>>();
+
+        // Editors often save by replacing the file, so the directories are watched instead
+        let directories = assets
+            .iter()
+            .filter_map(|(path, _)| path.parent().map(Path::to_path_buf))
+            .collect::<HashSet<PathBuf>>();
+        for directory in directories {
+            let changed_paths = changed_paths.clone();
+            hotwatch.watch(&directory, move |event: Event| {
+                let path = match event {
+                    Event::Create(path) | Event::Write(path) | Event::Rename(_, path) => path,
+                    _ => return,
+                };
+                changed_paths.lock().unwrap().insert(path);
+            })?;
+        }
+
+        Ok(Self {
+            _hotwatch: hotwatch,
+            assets,
+            changed_paths,
+        })
+    }
+
+    /// The watched assets that were written to since the last call.
+    pub fn take_changed(&self) -> Vec<Asset> {
+        let changed_paths = std::mem::take(&mut *self.changed_paths.lock().unwrap());
+        self.assets
+            .iter()
+            .filter(|(path, _)| changed_paths.contains(path))
+            .map(|(_, asset)| *asset)
+            .collect()
+    }
+}
+
+/// Compiles WGSL, returning the compiler's error message instead of panicking on invalid code.
+pub fn create_shader_module(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+) -> Result<wgpu::ShaderModule, String> {
+    let module =
+        naga::front::wgsl::parse_str(source).map_err(|error| error.emit_to_string(source))?;
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(&module)
+    .map_err(|error| error_chain(&error))?;
+
+    capture_validation_errors(device, || {
+        device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        })
+    })
+}
+
+/// Builds something from the asset as loaded, falling back to the embedded copy if that fails.
+/// This way a broken file on disk at startup still leaves a working renderer.
+pub fn build_with_fallback<T>(asset: &Asset, build: impl Fn(&[u8]) -> Result<T, String>) -> T {
+    let loaded = asset.load();
+    build(&loaded).unwrap_or_else(|error| {
+        eprintln!(
+            "Failed to load {}, using the embedded copy:\n{}",
+            asset.path, error
+        );
+        build(asset.embedded).expect("embedded assets should always be valid")
+    })
+}
+
+/// Runs `create`, turning validation errors such as mismatched bindings into an `Err`.
+pub fn capture_validation_errors<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> T,
+) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error_chain(&error)),
+        None => Ok(value),
+    }
+}
+
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message += &format!(": {}", cause);
+        source = cause.source();
+    }
+    message
+}
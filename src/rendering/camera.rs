@@ -6,6 +6,19 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+// Layout of the bind group holding the `CameraUniform`
+pub const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+#[derive(Copy, Clone)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -23,6 +36,18 @@ impl Camera {
 
         return OPENGL_TO_WGPU_MATRIX * proj * view;
     }
+
+    /// Blends from `self` towards `other`, used to render in between simulation ticks.
+    pub fn interpolate(&self, other: &Camera, alpha: f32) -> Camera {
+        use cgmath::EuclideanSpace;
+        Camera {
+            eye: cgmath::Point3::from_vec(self.eye.to_vec() + (other.eye - self.eye) * alpha),
+            target: cgmath::Point3::from_vec(
+                self.target.to_vec() + (other.target - self.target) * alpha,
+            ),
+            ..*other
+        }
+    }
 }
 
 // We need this for Rust to store our data correctly for the shaders
@@ -33,20 +58,34 @@ pub struct CameraUniform {
     // We can't use cgmath with bytemuck directly so we have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
+    // Used by the sky and SSAO passes to turn screen positions back into scene positions
+    inv_view_proj: [[f32; 4]; 4],
     view_pos: [f32; 4],
 }
 
 impl CameraUniform {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         use cgmath::SquareMatrix;
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
             view_pos: [0.0; 4],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+        use cgmath::SquareMatrix;
+        let view_proj = camera.build_view_projection_matrix();
+        self.view_proj = view_proj.into();
+        self.inv_view_proj = view_proj
+            .invert()
+            .unwrap_or(cgmath::Matrix4::identity())
+            .into();
         self.view_pos = [camera.eye.x, camera.eye.y, camera.eye.z, 0.0];
     }
+
+    pub fn view_proj(&self) -> glam::Mat4 {
+        glam::Mat4::from_cols_array_2d(&self.view_proj)
+    }
 }
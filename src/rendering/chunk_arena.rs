@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use glam::{IVec3, Vec3};
+
+use super::vertex::Vertex;
+
+const INITIAL_VERTEX_CAPACITY: u32 = 1 << 16;
+const INITIAL_INDEX_CAPACITY: u32 = 1 << 17;
+const INITIAL_DRAW_CAPACITY: u32 = 256;
+
+/// Arguments of a single `draw_indexed_indirect` call, as laid out on the GPU.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawIndexedIndirectArgs {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub base_vertex: i32,
+    pub first_instance: u32,
+}
+
+/// Where a chunk's mesh lives in the arena. Indices are local to the chunk's vertices.
+struct ArenaSlot {
+    first_vertex: u32,
+    vertex_capacity: u32,
+    first_index: u32,
+    index_capacity: u32,
+    vertex_count: u32,
+    index_count: u32,
+    center: Vec3,
+}
+
+/// One vertex and one index buffer shared by every chunk, plus a buffer of per-chunk draw
+/// arguments so all chunks can be drawn with a single `multi_draw_indexed_indirect`.
+///
+/// Chunks are updated in place when their new mesh fits in their slot. Otherwise they move to
+/// the end, and the space they leave behind is reclaimed the next time the arena runs full.
+pub struct ChunkArena {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    draw_args_buffer: wgpu::Buffer,
+    vertex_capacity: u32,
+    index_capacity: u32,
+    draw_capacity: u32,
+    next_vertex: u32,
+    next_index: u32,
+    slots: HashMap<IVec3, ArenaSlot>,
+    // CPU copy of the draw arguments, in the order they were last written to the GPU
+    draws: Vec<DrawIndexedIndirectArgs>,
+    draws_dirty: bool,
+}
+
+impl ChunkArena {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            vertex_buffer: create_vertex_buffer(device, INITIAL_VERTEX_CAPACITY),
+            index_buffer: create_index_buffer(device, INITIAL_INDEX_CAPACITY),
+            draw_args_buffer: create_draw_args_buffer(device, INITIAL_DRAW_CAPACITY),
+            vertex_capacity: INITIAL_VERTEX_CAPACITY,
+            index_capacity: INITIAL_INDEX_CAPACITY,
+            draw_capacity: INITIAL_DRAW_CAPACITY,
+            next_vertex: 0,
+            next_index: 0,
+            slots: HashMap::new(),
+            draws: Vec::new(),
+            draws_dirty: false,
+        }
+    }
+
+    pub fn vertex_count(&self) -> u32 {
+        self.slots.values().map(|slot| slot.vertex_count).sum()
+    }
+
+    pub fn index_count(&self) -> u32 {
+        self.slots.values().map(|slot| slot.index_count).sum()
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Replaces the mesh of the chunk at `position`. An empty mesh removes the chunk.
+    pub fn upload_chunk(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        position: IVec3,
+        center: Vec3,
+        vertices: &[Vertex],
+        indices: &[u32],
+    ) {
+        if indices.is_empty() {
+            self.remove_chunk(&position);
+            return;
+        }
+
+        let vertex_count = vertices.len() as u32;
+        let index_count = indices.len() as u32;
+        let fits = self.slots.get(&position).is_some_and(|slot| {
+            slot.vertex_capacity >= vertex_count && slot.index_capacity >= index_count
+        });
+
+        if !fits {
+            self.slots.remove(&position);
+            if self.next_vertex + vertex_count > self.vertex_capacity
+                || self.next_index + index_count > self.index_capacity
+            {
+                self.repack(device, queue, vertex_count, index_count);
+            }
+
+            self.slots.insert(
+                position,
+                ArenaSlot {
+                    first_vertex: self.next_vertex,
+                    vertex_capacity: vertex_count,
+                    first_index: self.next_index,
+                    index_capacity: index_count,
+                    vertex_count: 0,
+                    index_count: 0,
+                    center,
+                },
+            );
+            self.next_vertex += vertex_count;
+            self.next_index += index_count;
+        }
+
+        let slot = self.slots.get_mut(&position).unwrap();
+        slot.vertex_count = vertex_count;
+        slot.index_count = index_count;
+        slot.center = center;
+        queue.write_buffer(
+            &self.vertex_buffer,
+            (slot.first_vertex as usize * std::mem::size_of::<Vertex>()) as u64,
+            bytemuck::cast_slice(vertices),
+        );
+        queue.write_buffer(
+            &self.index_buffer,
+            (slot.first_index as usize * std::mem::size_of::<u32>()) as u64,
+            bytemuck::cast_slice(indices),
+        );
+        self.draws_dirty = true;
+    }
+
+    pub fn remove_chunk(&mut self, position: &IVec3) {
+        if self.slots.remove(position).is_some() {
+            self.draws_dirty = true;
+        }
+    }
+
+    /// Moves every live slot to the front of new buffers that have room for `extra_vertices`
+    /// and `extra_indices` more, dropping the space of moved and removed chunks.
+    fn repack(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        extra_vertices: u32,
+        extra_indices: u32,
+    ) {
+        let used_vertices = self
+            .slots
+            .values()
+            .map(|slot| slot.vertex_capacity)
+            .sum::<u32>();
+        let used_indices = self
+            .slots
+            .values()
+            .map(|slot| slot.index_capacity)
+            .sum::<u32>();
+        self.vertex_capacity = self
+            .vertex_capacity
+            .max(((used_vertices + extra_vertices) * 2).next_power_of_two());
+        self.index_capacity = self
+            .index_capacity
+            .max(((used_indices + extra_indices) * 2).next_power_of_two());
+
+        let vertex_buffer = create_vertex_buffer(device, self.vertex_capacity);
+        let index_buffer = create_index_buffer(device, self.index_capacity);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Chunk Arena Repack Encoder"),
+        });
+        let vertex_size = std::mem::size_of::<Vertex>() as u64;
+        let index_size = std::mem::size_of::<u32>() as u64;
+        let mut next_vertex = 0;
+        let mut next_index = 0;
+        for slot in self.slots.values_mut() {
+            encoder.copy_buffer_to_buffer(
+                &self.vertex_buffer,
+                slot.first_vertex as u64 * vertex_size,
+                &vertex_buffer,
+                next_vertex as u64 * vertex_size,
+                slot.vertex_capacity as u64 * vertex_size,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.index_buffer,
+                slot.first_index as u64 * index_size,
+                &index_buffer,
+                next_index as u64 * index_size,
+                slot.index_capacity as u64 * index_size,
+            );
+            slot.first_vertex = next_vertex;
+            slot.first_index = next_index;
+            next_vertex += slot.vertex_capacity;
+            next_index += slot.index_capacity;
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffer = index_buffer;
+        self.next_vertex = next_vertex;
+        self.next_index = next_index;
+        self.draws_dirty = true;
+    }
+
+    /// Writes the draw arguments of every chunk to the GPU, if anything changed since last time.
+    pub fn flush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.draws_dirty {
+            let draws = self.slots.values().map(draw_args).collect();
+            self.write_draws(device, queue, draws);
+        }
+    }
+
+    /// Writes the draw arguments ordered from the furthest chunk to the closest one,
+    /// as needed for blending. This changes with the camera, so it is done every frame.
+    pub fn flush_back_to_front(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, eye: Vec3) {
+        let mut slots = self.slots.values().collect::<Vec<&ArenaSlot>>();
+        slots.sort_by(|a, b| {
+            b.center
+                .distance_squared(eye)
+                .total_cmp(&a.center.distance_squared(eye))
+        });
+        let draws = slots.into_iter().map(draw_args).collect();
+        self.write_draws(device, queue, draws);
+    }
+
+    fn write_draws(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        draws: Vec<DrawIndexedIndirectArgs>,
+    ) {
+        if draws.len() as u32 > self.draw_capacity {
+            self.draw_capacity = (draws.len() as u32).next_power_of_two();
+            self.draw_args_buffer = create_draw_args_buffer(device, self.draw_capacity);
+        }
+        if !draws.is_empty() {
+            queue.write_buffer(&self.draw_args_buffer, 0, bytemuck::cast_slice(&draws));
+        }
+        self.draws = draws;
+        self.draws_dirty = false;
+    }
+
+    /// Draws every chunk, with one indirect call if `multi_draw_indirect` is supported and
+    /// one regular draw per chunk otherwise.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, multi_draw_indirect: bool) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+        if multi_draw_indirect {
+            render_pass.multi_draw_indexed_indirect(
+                &self.draw_args_buffer,
+                0,
+                self.draws.len() as u32,
+            );
+        } else {
+            for draw in self.draws.iter() {
+                render_pass.draw_indexed(
+                    draw.first_index..draw.first_index + draw.index_count,
+                    draw.base_vertex,
+                    0..draw.instance_count,
+                );
+            }
+        }
+    }
+}
+
+fn draw_args(slot: &ArenaSlot) -> DrawIndexedIndirectArgs {
+    DrawIndexedIndirectArgs {
+        index_count: slot.index_count,
+        instance_count: 1,
+        first_index: slot.first_index,
+        base_vertex: slot.first_vertex as i32,
+        first_instance: 0,
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Chunk Arena Vertex Buffer"),
+        size: (capacity as usize * std::mem::size_of::<Vertex>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_index_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Chunk Arena Index Buffer"),
+        size: (capacity as usize * std::mem::size_of::<u32>()) as u64,
+        usage: wgpu::BufferUsages::INDEX
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_draw_args_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Chunk Arena Draw Args Buffer"),
+        size: (capacity as usize * std::mem::size_of::<DrawIndexedIndirectArgs>()) as u64,
+        usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
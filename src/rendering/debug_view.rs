@@ -0,0 +1,331 @@
+use std::collections::HashSet;
+
+use glam::{IVec3, Vec3};
+use wgpu::util::DeviceExt;
+
+use super::assets::Asset;
+use super::camera;
+use super::pipeline::{PipelineCache, PipelineDescriptor, PipelineId};
+use super::render_graph::{Attachments, RenderNode, SURFACE};
+use super::render_pass_data::RenderPassKind;
+use super::texture;
+use super::vertex::Vertex;
+use crate::state::State;
+
+pub const SHADER: Asset = Asset::new(
+    "shaders/debug.wgsl",
+    include_bytes!("../shaders/debug.wgsl"),
+);
+
+// The debug views are drawn onto the surface, which doesn't share the scene's multisampled depth
+pub const DEBUG_DEPTH: &str = "debug_depth";
+
+// Keeps the color that is already there, for drawing into the depth buffer alone
+const KEEP_COLOR: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+const BACKGROUND: wgpu::Color = wgpu::Color {
+    r: 0.02,
+    g: 0.02,
+    b: 0.03,
+    a: 1.0,
+};
+
+/// Replaces the lit scene with a view of the raw chunk meshes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    Off,
+    Wireframe,
+    Normals,
+    UvChecker,
+    // Every chunk in a random color
+    ChunkTint,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugUniform {
+    // x is the edge length of a chunk in voxels, the rest is padding
+    chunk_size: [f32; 4],
+}
+
+// Layout of the bind group holding the `DebugUniform`
+const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+const BOUNDS_VERTEX_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+    array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+    step_mode: wgpu::VertexStepMode::Vertex,
+    attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+};
+
+/// The debug view that is switched on, if any, and the outlines of every chunk.
+pub struct DebugViews {
+    pub view: DebugView,
+    pub chunk_bounds: bool,
+
+    chunk_size: u32,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    wireframe_pipeline: PipelineId,
+    normals_pipeline: PipelineId,
+    uv_checker_pipeline: PipelineId,
+    chunk_tint_pipeline: PipelineId,
+    // Draws the opaque chunks into the debug depth, so the outlines alone are hidden by them
+    depth_only_pipeline: PipelineId,
+    bounds_pipeline: PipelineId,
+
+    // Positions of the chunks the outlines are drawn for, rebuilt only while they are shown
+    chunks: HashSet<IVec3>,
+    bounds_dirty: bool,
+    bounds_buffer: Option<wgpu::Buffer>,
+    bounds_vertex_count: u32,
+}
+
+impl DebugViews {
+    /// `polygon_mode_line` is whether the device was created with `Features::POLYGON_MODE_LINE`.
+    /// Without it the wireframe only outlines quads.
+    pub fn new(
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        surface_format: wgpu::TextureFormat,
+        polygon_mode_line: bool,
+        chunk_size: u32,
+    ) -> Self {
+        let uniform = DebugUniform {
+            chunk_size: [chunk_size as f32, 0.0, 0.0, 0.0],
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug View Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &BIND_GROUP_ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("debug_view_bind_group"),
+        });
+
+        let mesh_pipeline = |fragment_entry| {
+            PipelineDescriptor::new("Debug View Pipeline", SHADER, surface_format)
+                .entry_points("vs_main", fragment_entry)
+                .vertex_layout(Vertex::desc())
+                .bind_group(&camera::BIND_GROUP_ENTRIES)
+                .bind_group(&BIND_GROUP_ENTRIES)
+                .depth(
+                    texture::Texture::DEPTH_FORMAT,
+                    true,
+                    wgpu::CompareFunction::Less,
+                )
+        };
+        // Back faces are kept so the wireframe also shows the far side of the meshes
+        let wireframe = if polygon_mode_line {
+            mesh_pipeline("fs_wireframe").polygon_mode(wgpu::PolygonMode::Line)
+        } else {
+            mesh_pipeline("fs_wireframe_fallback")
+        }
+        .cull_mode(None);
+        let bounds = PipelineDescriptor::new("Chunk Bounds Pipeline", SHADER, surface_format)
+            .entry_points("vs_bounds", "fs_bounds")
+            .vertex_layout(BOUNDS_VERTEX_LAYOUT)
+            .bind_group(&camera::BIND_GROUP_ENTRIES)
+            .topology(wgpu::PrimitiveTopology::LineList)
+            .cull_mode(None)
+            .depth(
+                texture::Texture::DEPTH_FORMAT,
+                false,
+                wgpu::CompareFunction::LessEqual,
+            );
+
+        Self {
+            view: DebugView::Off,
+            chunk_bounds: false,
+            chunk_size,
+            buffer,
+            bind_group,
+            wireframe_pipeline: pipelines.get_or_create(device, &wireframe),
+            normals_pipeline: pipelines.get_or_create(device, &mesh_pipeline("fs_normals")),
+            uv_checker_pipeline: pipelines.get_or_create(device, &mesh_pipeline("fs_uv_checker")),
+            chunk_tint_pipeline: pipelines.get_or_create(device, &mesh_pipeline("fs_chunk_tint")),
+            depth_only_pipeline: pipelines.get_or_create(
+                device,
+                &mesh_pipeline("fs_depth_only").blend(Some(KEEP_COLOR)),
+            ),
+            bounds_pipeline: pipelines.get_or_create(device, &bounds),
+            chunks: HashSet::new(),
+            bounds_dirty: false,
+            bounds_buffer: None,
+            bounds_vertex_count: 0,
+        }
+    }
+
+    /// Shows `view`, or turns it off again if it is already shown.
+    pub fn toggle_view(&mut self, view: DebugView) {
+        self.view = if self.view == view {
+            DebugView::Off
+        } else {
+            view
+        };
+    }
+
+    pub fn set_chunk_size(&mut self, queue: &wgpu::Queue, chunk_size: u32) {
+        self.chunk_size = chunk_size;
+        self.bounds_dirty = true;
+        let uniform = DebugUniform {
+            chunk_size: [chunk_size as f32, 0.0, 0.0, 0.0],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Sets the chunks whose outlines are drawn, such as the keys of `VoxelScene::chunks`.
+    pub fn set_chunks(&mut self, chunks: impl Iterator<Item = IVec3>) {
+        let chunks = chunks.collect::<HashSet<_>>();
+        if chunks != self.chunks {
+            self.chunks = chunks;
+            self.bounds_dirty = true;
+        }
+    }
+
+    /// Rebuilds the chunk outlines if they are shown and out of date.
+    pub fn update(&mut self, device: &wgpu::Device) {
+        if !self.chunk_bounds || !self.bounds_dirty {
+            return;
+        }
+
+        let size = self.chunk_size as f32;
+        let mut vertices = Vec::with_capacity(self.chunks.len() * 24);
+        for chunk in self.chunks.iter() {
+            let min = chunk.as_vec3() * size;
+            let corner = |x: usize, y: usize, z: usize| {
+                (min + Vec3::new(x as f32, y as f32, z as f32) * size).to_array()
+            };
+            // The 12 edges of the box, 4 along each axis
+            for axis in 0..3 {
+                for edge in 0..4 {
+                    // Spread the two bits of the edge over the other two axes
+                    let mut from = [0; 3];
+                    from[(axis + 1) % 3] = edge & 1;
+                    from[(axis + 2) % 3] = (edge >> 1) & 1;
+                    let mut to = from;
+                    to[axis] = 1;
+                    vertices.push(corner(from[0], from[1], from[2]));
+                    vertices.push(corner(to[0], to[1], to[2]));
+                }
+            }
+        }
+
+        self.bounds_vertex_count = vertices.len() as u32;
+        self.bounds_buffer = (!vertices.is_empty()).then(|| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Chunk Bounds Buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+        });
+        self.bounds_dirty = false;
+    }
+
+    fn view_pipeline(&self) -> Option<PipelineId> {
+        match self.view {
+            DebugView::Off => None,
+            DebugView::Wireframe => Some(self.wireframe_pipeline),
+            DebugView::Normals => Some(self.normals_pipeline),
+            DebugView::UvChecker => Some(self.uv_checker_pipeline),
+            DebugView::ChunkTint => Some(self.chunk_tint_pipeline),
+        }
+    }
+}
+
+/// Draws the debug view over the post processed frame, followed by the chunk outlines.
+pub struct DebugViewNode;
+
+impl RenderNode<State> for DebugViewNode {
+    fn name(&self) -> &'static str {
+        "debug_view"
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SURFACE, DEBUG_DEPTH]
+    }
+
+    fn run(&self, state: &State, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder) {
+        let debug = &state.debug_views;
+        let view_pipeline = debug.view_pipeline();
+        let bounds = debug.bounds_buffer.as_ref().filter(|_| debug.chunk_bounds);
+        if view_pipeline.is_none() && bounds.is_none() {
+            return;
+        }
+
+        // The view replaces the frame, the outlines alone are drawn on top of it
+        let load = match view_pipeline {
+            Some(_) => wgpu::LoadOp::Clear(BACKGROUND),
+            None => wgpu::LoadOp::Load,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Debug View Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: attachments.view(SURFACE),
+                resolve_target: None,
+                ops: wgpu::Operations { load, store: true },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: attachments.view(DEBUG_DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: false,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_bind_group(0, &state.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &debug.bind_group, &[]);
+        if let Some(pipeline) = view_pipeline {
+            render_pass.set_pipeline(state.pipelines.get(pipeline));
+            for pass_data in state.render_passes.iter() {
+                pass_data
+                    .arena
+                    .draw(&mut render_pass, state.multi_draw_indirect);
+            }
+        } else {
+            // Over the lit frame the outlines are hidden by the same geometry as the scene is,
+            // transparent chunks don't write depth there either
+            render_pass.set_pipeline(state.pipelines.get(debug.depth_only_pipeline));
+            for pass_data in state.render_passes.iter() {
+                if pass_data.kind == RenderPassKind::Opaque {
+                    pass_data
+                        .arena
+                        .draw(&mut render_pass, state.multi_draw_indirect);
+                }
+            }
+        }
+
+        if let Some(buffer) = bounds {
+            render_pass.set_pipeline(state.pipelines.get(debug.bounds_pipeline));
+            render_pass.set_vertex_buffer(0, buffer.slice(..));
+            render_pass.draw(0..debug.bounds_vertex_count, 0..1);
+        }
+    }
+}
@@ -0,0 +1,452 @@
+use glam::{IVec3, Vec3};
+use wgpu::util::DeviceExt;
+
+use super::chunk_arena::DrawIndexedIndirectArgs;
+use super::mesh::Mesh;
+use super::vertex::Vertex;
+use crate::voxels::shape_faces::shape_faces;
+use crate::voxels::voxel_data::VoxelShape;
+use crate::voxels::voxel_profile::VOXEL_PROFILES;
+use crate::voxels::voxel_scene::VoxelChunk;
+
+// Matches `workgroup_size` in mesher.wgsl
+const WORKGROUP_SIZE: u32 = 4;
+
+const PROFILE_TRANSPARENT: u32 = 1;
+const PROFILE_FLUID: u32 = 2;
+
+// Same order as the faces in mesher.wgsl
+const FACE_OFFSETS: [IVec3; 6] = [
+    IVec3::Z,
+    glam::const_ivec3!([0, 0, -1]),
+    IVec3::X,
+    glam::const_ivec3!([-1, 0, 0]),
+    IVec3::Y,
+    glam::const_ivec3!([0, -1, 0]),
+];
+// Side of shape faces that cut through the voxel
+const NO_SIDE: u32 = 6;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MesherParams {
+    chunk_size: u32,
+    max_vertices: u32,
+    max_indices: u32,
+    // Uniform buffers are padded to 16 bytes
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuVoxelProfile {
+    color: [f32; 4],
+    flags: u32,
+    material: u32,
+    _padding: [u32; 2],
+}
+
+/// One face of a partially filled voxel, see `shape_faces`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuShapeFace {
+    // Only xyz is used, vec3 arrays are padded to 16 bytes
+    corners: [[f32; 4]; 4],
+    uvs: [[f32; 2]; 4],
+    normal: [f32; 4],
+    tangent: [f32; 4],
+    corner_count: u32,
+    // Index into `FACE_OFFSETS` of the side the face lies on, or `NO_SIDE`
+    side: u32,
+    _padding: [u32; 2],
+}
+
+/// Written by the mesher next to the draw args, so the vertex buffers can be read back.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct MesherCounts {
+    draw_args: [DrawIndexedIndirectArgs; 2],
+    vertex_counts: [u32; 2],
+}
+
+/// The output of meshing one chunk on the GPU. Both meshes can be drawn straight from these
+/// buffers with `draw_indexed_indirect`, the opaque args come first and the transparent second.
+/// The vertex counts of both meshes follow the args.
+pub struct GpuChunkMesh {
+    pub opaque_vertices: wgpu::Buffer,
+    pub opaque_indices: wgpu::Buffer,
+    pub transparent_vertices: wgpu::Buffer,
+    pub transparent_indices: wgpu::Buffer,
+    pub draw_args: wgpu::Buffer,
+    // Capacity of each of the two meshes
+    pub max_vertices: u32,
+    pub max_indices: u32,
+}
+
+impl GpuChunkMesh {
+    pub const OPAQUE_ARGS_OFFSET: wgpu::BufferAddress = 0;
+    pub const TRANSPARENT_ARGS_OFFSET: wgpu::BufferAddress =
+        std::mem::size_of::<DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+}
+
+/// Compute pipeline that builds chunk meshes on the GPU, producing the same faces as
+/// `VoxelChunk::generate_mesh` at full detail, in whatever order the invocations finish.
+/// Needs 8 storage buffers per shader stage, more than `Limits::downlevel_defaults` allows.
+pub struct GpuMesher {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    profile_buffer: wgpu::Buffer,
+    shape_buffer: wgpu::Buffer,
+    // Most vertices and indices a single voxel can produce
+    max_voxel_vertices: u32,
+    max_voxel_indices: u32,
+}
+
+impl GpuMesher {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Mesher Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../shaders/mesher.wgsl").into()),
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                storage_entry(6, false),
+                storage_entry(7, false),
+                storage_entry(8, true),
+            ],
+            label: Some("mesher_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mesher Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Mesher Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "main",
+        });
+
+        let profiles = VOXEL_PROFILES
+            .iter()
+            .map(|profile| GpuVoxelProfile {
+                color: profile.color,
+                flags: (PROFILE_TRANSPARENT * profile.transparent as u32)
+                    | (PROFILE_FLUID * profile.fluid as u32),
+                material: profile.material_id as u32,
+                _padding: [0; 2],
+            })
+            .collect::<Vec<GpuVoxelProfile>>();
+
+        let profile_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesher Profile Buffer"),
+            contents: bytemuck::cast_slice(&profiles),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        // The face ranges of all 256 shapes come first, followed by the faces themselves
+        let mut shape_ranges = Vec::new();
+        let mut faces = Vec::new();
+        // Full voxels are meshed as six quads
+        let (mut max_voxel_vertices, mut max_voxel_indices) = (24, 36);
+        for bits in 0..=u8::MAX {
+            let shape_faces = shape_faces(VoxelShape::from_bits(bits));
+            shape_ranges.push(faces.len() as u32 | (shape_faces.len() as u32) << 16);
+
+            let corner_counts = shape_faces.iter().map(|face| face.corners.len() as u32);
+            max_voxel_vertices = max_voxel_vertices.max(corner_counts.clone().sum());
+            max_voxel_indices =
+                max_voxel_indices.max(corner_counts.map(|count| (count - 2) * 3).sum());
+
+            faces.extend(shape_faces.iter().map(|face| {
+                let mut gpu_face = GpuShapeFace {
+                    normal: Vec3::from(face.normal).extend(0.0).into(),
+                    tangent: face.tangent,
+                    corner_count: face.corners.len() as u32,
+                    side: face.side.map_or(NO_SIDE, |(offset, _)| {
+                        FACE_OFFSETS
+                            .iter()
+                            .position(|&side| side == offset)
+                            .unwrap() as u32
+                    }),
+                    ..bytemuck::Zeroable::zeroed()
+                };
+                for (i, (corner, uv)) in face.corners.iter().enumerate() {
+                    gpu_face.corners[i] = Vec3::from(*corner).extend(0.0).into();
+                    gpu_face.uvs[i] = *uv;
+                }
+                gpu_face
+            }));
+        }
+
+        let mut shape_data = bytemuck::cast_slice::<u32, u8>(&shape_ranges).to_vec();
+        shape_data.extend_from_slice(bytemuck::cast_slice(&faces));
+        let shape_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesher Shape Buffer"),
+            contents: &shape_data,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            profile_buffer,
+            shape_buffer,
+            max_voxel_vertices,
+            max_voxel_indices,
+        }
+    }
+
+    /// Records and submits the meshing of `chunk`. The result is ready once the queue gets to it.
+    pub fn mesh_chunk(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        chunk: &VoxelChunk,
+    ) -> GpuChunkMesh {
+        let size = chunk.size();
+        // Every voxel can show all of its faces, e.g. alternating glass and water
+        let max_vertices = size * size * size * self.max_voxel_vertices;
+        let max_indices = size * size * size * self.max_voxel_indices;
+
+        let mut voxels = Vec::with_capacity((size * size * size) as usize);
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let voxel = chunk.voxel_at(&glam::UVec3::new(x, y, z));
+                    voxels.push(
+                        voxel.shape.bits() as u32
+                            | (voxel.fluid_level as u32) << 8
+                            | (voxel.profile as u32) << 16,
+                    );
+                }
+            }
+        }
+
+        let params = MesherParams {
+            chunk_size: size,
+            max_vertices,
+            max_indices,
+            _padding: 0,
+        };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesher Params Buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let voxel_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Mesher Voxel Buffer"),
+            contents: bytemuck::cast_slice(&voxels),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let vertex_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (max_vertices as usize * std::mem::size_of::<Vertex>()) as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::VERTEX
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+        let index_buffer = |label| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (max_indices as usize * std::mem::size_of::<u32>()) as u64,
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::INDEX
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        };
+
+        let empty_args = DrawIndexedIndirectArgs {
+            instance_count: 1,
+            ..Default::default()
+        };
+        let counts = MesherCounts {
+            draw_args: [empty_args, empty_args],
+            ..Default::default()
+        };
+        let mesh = GpuChunkMesh {
+            opaque_vertices: vertex_buffer("Mesher Opaque Vertex Buffer"),
+            opaque_indices: index_buffer("Mesher Opaque Index Buffer"),
+            transparent_vertices: vertex_buffer("Mesher Transparent Vertex Buffer"),
+            transparent_indices: index_buffer("Mesher Transparent Index Buffer"),
+            draw_args: device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Mesher Draw Args Buffer"),
+                contents: bytemuck::cast_slice(&[counts]),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::INDIRECT
+                    | wgpu::BufferUsages::COPY_SRC,
+            }),
+            max_vertices,
+            max_indices,
+        };
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: voxel_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.profile_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: mesh.opaque_vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: mesh.opaque_indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: mesh.transparent_vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: mesh.transparent_indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: mesh.draw_args.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.shape_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("mesher_bind_group"),
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mesher Encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Mesher Pass"),
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            let workgroups = size.div_ceil(WORKGROUP_SIZE);
+            compute_pass.dispatch(workgroups, workgroups, workgroups);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        mesh
+    }
+
+    /// Copies a GPU built mesh back into CPU meshes, opaque first. Blocks until the GPU is done,
+    /// so this is meant for checking the GPU mesher against the CPU one, not for every frame.
+    pub fn read_back(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mesh: &GpuChunkMesh,
+    ) -> (Mesh, Mesh) {
+        let counts: Vec<MesherCounts> = read_buffer(
+            device,
+            queue,
+            &mesh.draw_args,
+            std::mem::size_of::<MesherCounts>() as u64,
+        );
+        let counts = counts[0];
+
+        let read_mesh = |vertices: &wgpu::Buffer, indices: &wgpu::Buffer, mesh_index: usize| {
+            let index_count = counts.draw_args[mesh_index]
+                .index_count
+                .min(mesh.max_indices);
+            let vertex_count = counts.vertex_counts[mesh_index].min(mesh.max_vertices);
+            Mesh {
+                vertices: read_buffer(
+                    device,
+                    queue,
+                    vertices,
+                    (vertex_count as usize * std::mem::size_of::<Vertex>()) as u64,
+                ),
+                indices: read_buffer(
+                    device,
+                    queue,
+                    indices,
+                    (index_count as usize * std::mem::size_of::<u32>()) as u64,
+                ),
+            }
+        };
+
+        (
+            read_mesh(&mesh.opaque_vertices, &mesh.opaque_indices, 0),
+            read_mesh(&mesh.transparent_vertices, &mesh.transparent_indices, 1),
+        )
+    }
+}
+
+fn read_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    size: wgpu::BufferAddress,
+) -> Vec<T> {
+    if size == 0 {
+        return Vec::new();
+    }
+
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Read Back Buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Read Back Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging.slice(..);
+    let mapping = slice.map_async(wgpu::MapMode::Read);
+    device.poll(wgpu::Maintain::Wait);
+    pollster::block_on(mapping).expect("Failed to map read back buffer");
+
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    staging.unmap();
+    data
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+/// How long a node of the render graph took on the GPU.
+#[derive(Copy, Clone, Debug)]
+pub struct GpuSpan {
+    pub name: &'static str,
+    // From the first timestamp of the frame
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Timestamp queries written before and after each node of the render graph.
+/// Needs a device created with `Features::TIMESTAMP_QUERY`.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    // The queries are resolved straight into this
+    read_back_buffer: wgpu::Buffer,
+    // Number of queries, two per node
+    capacity: u32,
+    // Nanoseconds per timestamp tick
+    period: f32,
+}
+
+impl GpuTimer {
+    /// A timer for up to `node_count` nodes.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, node_count: u32) -> Self {
+        let capacity = node_count * 2;
+        let size = capacity as u64 * std::mem::size_of::<u64>() as u64;
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Render Graph Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: capacity,
+        });
+        let read_back_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Read Back Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            query_set,
+            read_back_buffer,
+            capacity,
+            period: queue.get_timestamp_period(),
+        }
+    }
+
+    /// Whether every one of `node_count` nodes can be timed.
+    pub fn fits(&self, node_count: usize) -> bool {
+        node_count * 2 <= self.capacity as usize
+    }
+
+    /// Writes the timestamp before (`end` false) or after the node at `index`.
+    pub fn write_timestamp(&self, encoder: &mut wgpu::CommandEncoder, index: usize, end: bool) {
+        let query = index as u32 * 2 + end as u32;
+        encoder.write_timestamp(&self.query_set, query);
+    }
+
+    /// Copies the timestamps of `node_count` nodes to where `read` can map them.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder, node_count: usize) {
+        let count = node_count as u32 * 2;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.read_back_buffer, 0);
+    }
+
+    /// Waits for the resolved timestamps of the nodes called `names` and turns them into spans.
+    /// Nodes that didn't record anything are left out.
+    pub fn read(&self, device: &wgpu::Device, names: &[&'static str]) -> Vec<GpuSpan> {
+        let size = (names.len() * 2 * std::mem::size_of::<u64>()) as u64;
+        let slice = self.read_back_buffer.slice(..size);
+        let mapping = slice.map_async(wgpu::MapMode::Read);
+        device.poll(wgpu::Maintain::Wait);
+        pollster::block_on(mapping).expect("Failed to map timestamp buffer");
+
+        let timestamps: Vec<u64> = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+        self.read_back_buffer.unmap();
+
+        let first = timestamps.iter().copied().min().unwrap_or(0);
+        let to_duration =
+            |ticks: u64| Duration::from_nanos((ticks as f64 * self.period as f64) as u64);
+        names
+            .iter()
+            .zip(timestamps.chunks_exact(2))
+            .filter(|(_, pair)| pair[1] > pair[0])
+            .map(|(&name, pair)| GpuSpan {
+                name,
+                start: to_duration(pair[0] - first),
+                duration: to_duration(pair[1] - pair[0]),
+            })
+            .collect()
+    }
+}
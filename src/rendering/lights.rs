@@ -0,0 +1,624 @@
+use std::collections::HashMap;
+
+use glam::{IVec3, Mat4, Vec3, Vec4Swizzles};
+
+use super::pipeline::PipelineCache;
+use super::sky::Sky;
+
+// Edge length of the screen tiles lights are culled against, in pixels
+const TILE_SIZE: u32 = 16;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightKind {
+    Point,
+    // Shines along `direction`, fading out between the inner and outer angle (radians from the axis)
+    Spot {
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Vec3,
+    // Linear RGB
+    pub color: Vec3,
+    // Distance in voxels at which the light has faded out completely
+    pub radius: f32,
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn point(position: Vec3, color: Vec3, radius: f32, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Point,
+            position,
+            color,
+            radius,
+            intensity,
+        }
+    }
+
+    /// A spot light whose cone is `angle` radians wide on either side of `direction`,
+    /// with a soft edge over the outer quarter.
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        angle: f32,
+        color: Vec3,
+        radius: f32,
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                direction,
+                inner_angle: angle * 0.75,
+                outer_angle: angle,
+            },
+            position,
+            color,
+            radius,
+            intensity,
+        }
+    }
+
+    fn to_gpu(self) -> GpuLight {
+        let (direction, cone) = match self.kind {
+            LightKind::Point => ([0.0; 4], [-1.0, 0.0, 0.0, 0.0]),
+            LightKind::Spot {
+                direction,
+                inner_angle,
+                outer_angle,
+            } => (
+                direction
+                    .normalize_or_zero()
+                    .extend(inner_angle.cos())
+                    .into(),
+                [outer_angle.cos(), 1.0, 0.0, 0.0],
+            ),
+        };
+
+        GpuLight {
+            position: self.position.extend(self.radius).into(),
+            color: self.color.extend(self.intensity).into(),
+            direction,
+            cone,
+        }
+    }
+}
+
+/// Refers to a light for as long as it hasn't been removed. Slots are reused, so the id also
+/// holds the generation of its slot, which keeps it from reaching a light added later.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LightId {
+    index: usize,
+    generation: u32,
+}
+
+struct LightSlot {
+    // Bumped whenever the light in this slot is removed
+    generation: u32,
+    light: Option<Light>,
+}
+
+/// Lights stored in reusable slots, handed out as `LightId`s.
+#[derive(Default)]
+struct LightSlots {
+    slots: Vec<LightSlot>,
+    // Slots of removed lights, reused by the next ones added
+    free: Vec<usize>,
+}
+
+impl LightSlots {
+    fn add(&mut self, light: Light) -> LightId {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index].light = Some(light);
+                index
+            }
+            None => {
+                self.slots.push(LightSlot {
+                    generation: 0,
+                    light: Some(light),
+                });
+                self.slots.len() - 1
+            }
+        };
+
+        LightId {
+            index,
+            generation: self.slots[index].generation,
+        }
+    }
+
+    fn get(&self, id: LightId) -> Option<&Light> {
+        self.slots
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .light
+            .as_ref()
+    }
+
+    fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)?
+            .light
+            .as_mut()
+    }
+
+    fn remove(&mut self, id: LightId) -> Option<Light> {
+        let slot = self
+            .slots
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)?;
+        let light = slot.light.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        Some(light)
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Light> {
+        self.slots.iter().filter_map(|slot| slot.light.as_ref())
+    }
+}
+
+// Must match `Light` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuLight {
+    // w is the radius
+    position: [f32; 4],
+    // w is the intensity
+    color: [f32; 4],
+    // Axis of a spot light, w is the cosine of its inner angle
+    direction: [f32; 4],
+    // x is the cosine of the outer angle, y is 1 for spot lights
+    cone: [f32; 4],
+}
+
+// Must match `LightGrid` in shader.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightGrid {
+    tile_size: u32,
+    tiles_x: u32,
+    tiles_y: u32,
+    light_count: u32,
+}
+
+// Layout of the bind group with everything the scene is lit by. The sky uniform shares it with
+// the lights, since pipelines only get four bind groups
+pub const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 4] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+    storage_entry(1),
+    storage_entry(2),
+    wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+];
+
+const fn storage_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+/// Every point and spot light in the scene, culled against screen tiles each frame so a
+/// fragment only shades with the lights that can reach its tile.
+pub struct Lights {
+    lights: LightSlots,
+    // Lights added for the emissive voxels of each chunk
+    chunk_lights: HashMap<IVec3, Vec<LightId>>,
+
+    light_buffer: GrowableBuffer,
+    // The offset and count of every tile's lights, followed by the light indices they point into
+    tile_buffer: GrowableBuffer,
+    grid_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Lights {
+    pub fn new(device: &wgpu::Device, pipelines: &mut PipelineCache, sky: &Sky) -> Self {
+        // Resized to fit on the first update
+        let light_buffer = GrowableBuffer::new(
+            device,
+            "Light Buffer",
+            std::mem::size_of::<GpuLight>() as wgpu::BufferAddress,
+        );
+        let tile_buffer = GrowableBuffer::new(
+            device,
+            "Light Tile Buffer",
+            std::mem::size_of::<[u32; 2]>() as wgpu::BufferAddress,
+        );
+        let grid_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Grid Buffer"),
+            size: std::mem::size_of::<LightGrid>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = create_bind_group(
+            device,
+            pipelines,
+            sky,
+            &light_buffer.buffer,
+            &tile_buffer.buffer,
+            &grid_buffer,
+        );
+
+        Self {
+            lights: LightSlots::default(),
+            chunk_lights: HashMap::new(),
+            light_buffer,
+            tile_buffer,
+            grid_buffer,
+            bind_group,
+        }
+    }
+
+    pub fn add(&mut self, light: Light) -> LightId {
+        self.lights.add(light)
+    }
+
+    pub fn get(&self, id: LightId) -> Option<&Light> {
+        self.lights.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.lights.get_mut(id)
+    }
+
+    pub fn remove(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Replaces the lights registered for the emissive voxels of `chunk`.
+    pub fn set_chunk_lights(&mut self, chunk: IVec3, lights: impl IntoIterator<Item = Light>) {
+        for id in self.chunk_lights.remove(&chunk).unwrap_or_default() {
+            self.remove(id);
+        }
+
+        let ids = lights
+            .into_iter()
+            .map(|light| self.add(light))
+            .collect::<Vec<_>>();
+        if !ids.is_empty() {
+            self.chunk_lights.insert(chunk, ids);
+        }
+    }
+
+    /// Culls the lights against the tiles of a `width` x `height` target seen through
+    /// `view_proj`, and uploads them along with the light list of every tile.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+        sky: &Sky,
+        view_proj: Mat4,
+        (width, height): (u32, u32),
+    ) {
+        let lights = self
+            .lights
+            .iter()
+            .map(|light| light.to_gpu())
+            .collect::<Vec<_>>();
+        let grid = LightGrid {
+            tile_size: TILE_SIZE,
+            tiles_x: width.div_ceil(TILE_SIZE),
+            tiles_y: height.div_ceil(TILE_SIZE),
+            light_count: lights.len() as u32,
+        };
+        let tiles = cull(&lights, view_proj, &grid, width, height);
+
+        let light_grew = self.light_buffer.write(device, queue, &lights);
+        let tile_grew = self.tile_buffer.write(device, queue, &tiles);
+        if light_grew || tile_grew {
+            self.bind_group = create_bind_group(
+                device,
+                pipelines,
+                sky,
+                &self.light_buffer.buffer,
+                &self.tile_buffer.buffer,
+                &self.grid_buffer,
+            );
+        }
+        queue.write_buffer(&self.grid_buffer, 0, bytemuck::cast_slice(&[grid]));
+    }
+}
+
+// Bins every light into the tiles its bounding sphere covers on screen. The result starts with
+// an (offset, count) pair per tile, the offsets point at the light indices after them
+fn cull(
+    lights: &[GpuLight],
+    view_proj: Mat4,
+    grid: &LightGrid,
+    width: u32,
+    height: u32,
+) -> Vec<u32> {
+    let tile_count = (grid.tiles_x * grid.tiles_y) as usize;
+    let last_tile = (grid.tiles_x as i32 - 1, grid.tiles_y as i32 - 1);
+
+    let mut counts = vec![0u32; tile_count];
+    let mut covered = Vec::with_capacity(lights.len());
+    for (index, light) in lights.iter().enumerate() {
+        let center = Vec3::from_slice(&light.position[..3]);
+        let radius = light.position[3];
+        let (min, max) = match screen_bounds(center, radius, view_proj) {
+            Some(bounds) => bounds,
+            None => continue,
+        };
+
+        let to_tile = |ndc: glam::Vec2| {
+            (
+                ((ndc.x * 0.5 + 0.5) * width as f32) as i32 / TILE_SIZE as i32,
+                ((0.5 - ndc.y * 0.5) * height as f32) as i32 / TILE_SIZE as i32,
+            )
+        };
+        // Screen y points down while NDC y points up
+        let (x0, y1) = to_tile(min);
+        let (x1, y0) = to_tile(max);
+        let x_range = x0.clamp(0, last_tile.0)..=x1.clamp(0, last_tile.0);
+        let y_range = y0.clamp(0, last_tile.1)..=y1.clamp(0, last_tile.1);
+
+        for y in y_range.clone() {
+            for x in x_range.clone() {
+                counts[(y as u32 * grid.tiles_x + x as u32) as usize] += 1;
+            }
+        }
+        covered.push((index as u32, x_range, y_range));
+    }
+
+    let mut tiles = vec![0u32; tile_count * 2];
+    let mut offset = tile_count as u32 * 2;
+    for (tile, &count) in counts.iter().enumerate() {
+        tiles[tile * 2] = offset;
+        offset += count;
+    }
+    tiles.resize(offset as usize, 0);
+
+    for (index, x_range, y_range) in covered {
+        for y in y_range {
+            for x in x_range.clone() {
+                let tile = (y as u32 * grid.tiles_x + x as u32) as usize;
+                let slot = tiles[tile * 2] + tiles[tile * 2 + 1];
+                tiles[slot as usize] = index;
+                tiles[tile * 2 + 1] += 1;
+            }
+        }
+    }
+    tiles
+}
+
+// Normalized device coordinates of the rectangle covering the sphere, or None if it's off screen.
+// Spheres reaching behind the camera are given the whole screen
+fn screen_bounds(center: Vec3, radius: f32, view_proj: Mat4) -> Option<(glam::Vec2, glam::Vec2)> {
+    let mut min = glam::Vec2::splat(f32::MAX);
+    let mut max = glam::Vec2::splat(f32::MIN);
+    let mut behind = 0;
+    let mut beyond_far = 0;
+    for corner in 0..8 {
+        let offset = Vec3::new(
+            if corner & 1 == 0 { -radius } else { radius },
+            if corner & 2 == 0 { -radius } else { radius },
+            if corner & 4 == 0 { -radius } else { radius },
+        );
+        let clip = view_proj * (center + offset).extend(1.0);
+        if clip.w <= 0.0 {
+            behind += 1;
+            continue;
+        }
+        if clip.z > clip.w {
+            beyond_far += 1;
+        }
+        let ndc = clip.xy() / clip.w;
+        min = min.min(ndc);
+        max = max.max(ndc);
+    }
+
+    if behind == 8 || beyond_far == 8 {
+        return None;
+    }
+    if behind > 0 {
+        return Some((glam::Vec2::splat(-1.0), glam::Vec2::splat(1.0)));
+    }
+    if max.x < -1.0 || max.y < -1.0 || min.x > 1.0 || min.y > 1.0 {
+        return None;
+    }
+    Some((min, max))
+}
+
+// A storage buffer that is reallocated with room to spare when its contents outgrow it
+struct GrowableBuffer {
+    label: &'static str,
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+}
+
+impl GrowableBuffer {
+    fn new(device: &wgpu::Device, label: &'static str, size: wgpu::BufferAddress) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            label,
+            buffer,
+            size,
+        }
+    }
+
+    // Returns true if the buffer had to be reallocated, which invalidates bind groups using it
+    fn write<T: bytemuck::Pod>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[T],
+    ) -> bool {
+        let size = std::mem::size_of_val(data) as wgpu::BufferAddress;
+        let grew = size > self.size;
+        if grew {
+            *self = Self::new(device, self.label, size.next_power_of_two());
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(data));
+        grew
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    pipelines: &mut PipelineCache,
+    sky: &Sky,
+    light_buffer: &wgpu::Buffer,
+    tile_buffer: &wgpu::Buffer,
+    grid_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: pipelines.bind_group_layout(device, &BIND_GROUP_ENTRIES),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: sky.buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: tile_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: grid_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("lights_bind_group"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(intensity: f32) -> Light {
+        Light::point(Vec3::ZERO, Vec3::ONE, 4.0, intensity)
+    }
+
+    #[test]
+    fn removed_slots_are_reused() {
+        let mut lights = LightSlots::default();
+        let first = lights.add(light(1.0));
+        let second = lights.add(light(2.0));
+        assert_eq!(lights.len(), 2);
+
+        assert_eq!(lights.remove(first), Some(light(1.0)));
+        assert_eq!(lights.len(), 1);
+        assert_eq!(lights.get(first), None);
+        assert_eq!(lights.remove(first), None);
+
+        let third = lights.add(light(3.0));
+        assert_eq!(third.index, first.index);
+        assert_eq!(lights.len(), 2);
+        assert_eq!(lights.get(second), Some(&light(2.0)));
+        assert_eq!(lights.get(third), Some(&light(3.0)));
+    }
+
+    #[test]
+    fn stale_ids_miss_the_reused_slot() {
+        let mut lights = LightSlots::default();
+        let stale = lights.add(light(1.0));
+        lights.remove(stale);
+        let current = lights.add(light(2.0));
+
+        assert_eq!(lights.get(stale), None);
+        assert_eq!(lights.get_mut(stale), None);
+        assert_eq!(lights.remove(stale), None);
+        assert_eq!(lights.get(current), Some(&light(2.0)));
+        assert_eq!(lights.iter().count(), 1);
+    }
+
+    /// The lights binned into the tile at (x, y).
+    fn tile_lights<'a>(tiles: &'a [u32], grid: &LightGrid, x: u32, y: u32) -> &'a [u32] {
+        let tile = ((y * grid.tiles_x + x) * 2) as usize;
+        let offset = tiles[tile] as usize;
+        &tiles[offset..offset + tiles[tile + 1] as usize]
+    }
+
+    #[test]
+    fn cull_bins_lights_into_the_tiles_they_cover() {
+        // Seen through the identity, positions are already in normalized device coordinates
+        let lights = [
+            Light::point(Vec3::new(0.0, 0.0, 0.5), Vec3::ONE, 0.1, 1.0),
+            Light::point(Vec3::new(-0.9, 0.9, 0.5), Vec3::ONE, 0.05, 1.0),
+            Light::point(Vec3::new(5.0, 0.0, 0.5), Vec3::ONE, 0.1, 1.0),
+        ]
+        .map(Light::to_gpu);
+        let (width, height) = (TILE_SIZE * 4, TILE_SIZE * 4);
+        let grid = LightGrid {
+            tile_size: TILE_SIZE,
+            tiles_x: 4,
+            tiles_y: 4,
+            light_count: lights.len() as u32,
+        };
+        let tiles = cull(&lights, Mat4::IDENTITY, &grid, width, height);
+
+        // The first light covers the four center tiles, the second sits in the top left corner
+        // and the third is off screen
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected: &[u32] = match (x, y) {
+                    (1..=2, 1..=2) => &[0],
+                    (0, 0) => &[1],
+                    _ => &[],
+                };
+                assert_eq!(
+                    tile_lights(&tiles, &grid, x, y),
+                    expected,
+                    "tile {} {}",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+}
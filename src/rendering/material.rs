@@ -0,0 +1,512 @@
+use std::collections::HashMap;
+use std::num::NonZeroU8;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use wgpu::util::DeviceExt;
+
+use super::assets::{build_with_fallback, Asset};
+use super::pipeline::PipelineCache;
+use super::texture;
+
+pub type MaterialId = u16;
+
+// Every texture a material can use, looked up by the path in its definition
+pub const TEXTURES: [Asset; 5] = [
+    Asset::new(
+        "textures/lapis_block.png",
+        include_bytes!("../textures/lapis_block.png"),
+    ),
+    Asset::new(
+        "textures/lapis_block_normal.png",
+        include_bytes!("../textures/lapis_block_normal.png"),
+    ),
+    Asset::new(
+        "textures/lapis_block_roughness.png",
+        include_bytes!("../textures/lapis_block_roughness.png"),
+    ),
+    Asset::new("textures/lamp.png", include_bytes!("../textures/lamp.png")),
+    Asset::new(
+        "textures/lamp_emissive.png",
+        include_bytes!("../textures/lamp_emissive.png"),
+    ),
+];
+
+// Must match the size of the arrays in shader.wgsl
+const MAX_MATERIALS: usize = 64;
+const MAX_SAMPLERS: usize = 4;
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureFilter {
+    // Keeps the texels sharp up close, the mip levels are still used in the distance
+    Nearest,
+    Linear,
+}
+
+/// How the faces of a voxel profile are textured, profiles refer to it by name.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Material {
+    // Path of one of the `TEXTURES`
+    pub texture: String,
+    #[serde(default = "default_filter")]
+    pub filter: TextureFilter,
+    // Maximum number of samples for surfaces seen at a grazing angle, only used with linear filtering
+    #[serde(default = "default_anisotropy")]
+    pub anisotropy: u8,
+    // Tangent space normals with green pointing down the image, faces are flat without one
+    #[serde(default)]
+    pub normal_map: Option<String>,
+    // Perceptual roughness in the red channel, scaled by `roughness`
+    #[serde(default)]
+    pub roughness_map: Option<String>,
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+    // Light given off regardless of the lighting, scaled by `emissive_strength`
+    #[serde(default)]
+    pub emissive_map: Option<String>,
+    #[serde(default = "default_emissive_strength")]
+    pub emissive_strength: f32,
+}
+
+fn default_filter() -> TextureFilter {
+    TextureFilter::Nearest
+}
+
+fn default_anisotropy() -> u8 {
+    1
+}
+
+fn default_roughness() -> f32 {
+    0.8
+}
+
+fn default_emissive_strength() -> f32 {
+    1.0
+}
+
+impl Material {
+    /// Path of every texture the material uses, starting with the base color.
+    pub fn textures(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(&self.texture)
+            .chain(&self.normal_map)
+            .chain(&self.roughness_map)
+            .chain(&self.emissive_map)
+            .map(String::as_str)
+    }
+
+    fn sampler_key(&self) -> (TextureFilter, u8) {
+        match self.filter {
+            TextureFilter::Nearest => (TextureFilter::Nearest, 1),
+            // wgpu only accepts powers of two up to 16
+            TextureFilter::Linear => (
+                TextureFilter::Linear,
+                self.anisotropy.clamp(1, 16).next_power_of_two(),
+            ),
+        }
+    }
+}
+
+pub struct Materials {
+    materials: Vec<Material>,
+    ids: HashMap<String, MaterialId>,
+}
+
+impl Materials {
+    pub fn new() -> Self {
+        Self {
+            materials: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, name: &str, material: Material) -> MaterialId {
+        assert!(
+            self.materials.len() < MAX_MATERIALS,
+            "at most {} materials are supported",
+            MAX_MATERIALS
+        );
+        let id = self.materials.len() as MaterialId;
+        self.materials.push(material);
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, id: MaterialId) -> &Material {
+        &self.materials[id as usize]
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<MaterialId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Every material, ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = &Material> {
+        self.materials.iter()
+    }
+
+    fn load_builtin() -> Self {
+        // The first entry is the default material (id 0)
+        let sources = [
+            (
+                "voxels/dirt",
+                include_str!("../resources/materials/dirt.json"),
+            ),
+            (
+                "voxels/glass",
+                include_str!("../resources/materials/glass.json"),
+            ),
+            (
+                "voxels/water",
+                include_str!("../resources/materials/water.json"),
+            ),
+            (
+                "voxels/leaves",
+                include_str!("../resources/materials/leaves.json"),
+            ),
+            (
+                "voxels/lamp",
+                include_str!("../resources/materials/lamp.json"),
+            ),
+        ];
+
+        let mut materials = Self::new();
+        for (name, source) in sources {
+            let material: Material = serde_json::from_str(source)
+                .unwrap_or_else(|e| panic!("Invalid material '{}': {}", name, e));
+            for path in material.textures() {
+                assert!(
+                    TEXTURES.iter().any(|texture| texture.path == path),
+                    "Material '{}' uses unknown texture '{}'",
+                    name,
+                    path
+                );
+            }
+            materials.register(name, material);
+        }
+        materials
+    }
+}
+
+impl Default for Materials {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub static MATERIALS: Lazy<Materials> = Lazy::new(Materials::load_builtin);
+
+// Layout of the bind group used by `fs_main` in shader.wgsl: the texture arrays with one layer per
+// material, every sampler the materials need and which of them each material uses
+pub const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 5 + MAX_SAMPLERS] = [
+    texture_entry(0),
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+    sampler_entry(2),
+    sampler_entry(3),
+    sampler_entry(4),
+    sampler_entry(5),
+    // Normal, roughness and emissive maps
+    texture_entry(6),
+    texture_entry(7),
+    texture_entry(8),
+];
+
+const fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2Array,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+const fn sampler_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MaterialUniform {
+    // x is the index of the sampler each material is drawn with, the rest is padding
+    samplers: [[u32; 4]; MAX_MATERIALS],
+    // x scales the roughness map and y the emissive map, the rest is padding
+    properties: [[f32; 4]; MAX_MATERIALS],
+}
+
+// Every texture of a material, with neutral stand-ins for the maps it doesn't have
+struct MaterialImages {
+    base_color: image::RgbaImage,
+    normal: image::RgbaImage,
+    roughness: image::RgbaImage,
+    emissive: image::RgbaImage,
+}
+
+impl MaterialImages {
+    fn load(
+        material: &Material,
+        load: impl Fn(&'static Asset) -> Result<image::RgbaImage, String>,
+    ) -> Result<Self, String> {
+        let load_map = |path: &Option<String>, neutral: [u8; 4]| match path {
+            Some(path) => load(texture_asset(path)),
+            None => Ok(image::RgbaImage::from_pixel(1, 1, image::Rgba(neutral))),
+        };
+
+        Ok(Self {
+            base_color: load(texture_asset(&material.texture))?,
+            normal: load_map(&material.normal_map, [128, 128, 255, 255])?,
+            roughness: load_map(&material.roughness_map, [255, 255, 255, 255])?,
+            emissive: load_map(&material.emissive_map, [0, 0, 0, 255])?,
+        })
+    }
+}
+
+/// The textures of every material on the GPU, with a full mip chain and the samplers they ask for.
+///
+/// Each material gets its own layer of a texture array per kind of map instead of a region of
+/// an atlas, so lower mip levels never mix texels of neighbouring materials.
+pub struct MaterialTextures {
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl MaterialTextures {
+    /// Loads every material's texture, falling back to the embedded copy of any that fail to load.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, pipelines: &mut PipelineCache) -> Self {
+        let images = MATERIALS
+            .iter()
+            .map(|material| {
+                MaterialImages::load(material, |asset| Ok(build_with_fallback(asset, decode)))
+            })
+            .collect::<Result<Vec<_>, String>>()
+            .expect("falling back to the embedded textures can't fail");
+        Self::from_images(device, queue, pipelines, &images)
+    }
+
+    /// Rebuilds the textures from disk. If any of them fails to load, nothing changes.
+    pub fn reload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+    ) -> Result<(), String> {
+        let images = MATERIALS
+            .iter()
+            .map(|material| MaterialImages::load(material, |asset| decode(&asset.load())))
+            .collect::<Result<Vec<_>, String>>()?;
+        *self = Self::from_images(device, queue, pipelines, &images);
+        Ok(())
+    }
+
+    fn from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+        images: &[MaterialImages],
+    ) -> Self {
+        let base_color = create_texture_array(
+            device,
+            queue,
+            "Material Base Color",
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            images.iter().map(|images| &images.base_color),
+        );
+        // Normals and roughness are data rather than colors, so they are left linear
+        let normal = create_texture_array(
+            device,
+            queue,
+            "Material Normals",
+            wgpu::TextureFormat::Rgba8Unorm,
+            images.iter().map(|images| &images.normal),
+        );
+        let roughness = create_texture_array(
+            device,
+            queue,
+            "Material Roughness",
+            wgpu::TextureFormat::Rgba8Unorm,
+            images.iter().map(|images| &images.roughness),
+        );
+        let emissive = create_texture_array(
+            device,
+            queue,
+            "Material Emissive",
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+            images.iter().map(|images| &images.emissive),
+        );
+
+        // One sampler per distinct filter setting
+        let mut sampler_keys = Vec::new();
+        let mut uniform = MaterialUniform {
+            samplers: [[0; 4]; MAX_MATERIALS],
+            properties: [[0.0; 4]; MAX_MATERIALS],
+        };
+        for (index, material) in MATERIALS.iter().enumerate() {
+            let key = material.sampler_key();
+            let sampler = match sampler_keys.iter().position(|&other| other == key) {
+                Some(sampler) => sampler,
+                None if sampler_keys.len() < MAX_SAMPLERS => {
+                    sampler_keys.push(key);
+                    sampler_keys.len() - 1
+                }
+                None => {
+                    eprintln!(
+                        "Materials use more than {} different filters, using the first one instead of {:?}",
+                        MAX_SAMPLERS, key
+                    );
+                    0
+                }
+            };
+            uniform.samplers[index][0] = sampler as u32;
+            uniform.properties[index] = [material.roughness, material.emissive_strength, 0.0, 0.0];
+        }
+        if sampler_keys.is_empty() {
+            sampler_keys.push((TextureFilter::Nearest, 1));
+        }
+        let samplers = sampler_keys
+            .iter()
+            .map(|&(filter, anisotropy)| create_sampler(device, filter, anisotropy))
+            .collect::<Vec<_>>();
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&base_color),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: buffer.as_entire_binding(),
+            },
+        ];
+        // Slots no material needs repeat the first sampler
+        for slot in 0..MAX_SAMPLERS {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2 + slot as u32,
+                resource: wgpu::BindingResource::Sampler(
+                    samplers.get(slot).unwrap_or(&samplers[0]),
+                ),
+            });
+        }
+        for (binding, view) in [(6, &normal), (7, &roughness), (8, &emissive)] {
+            entries.push(wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(view),
+            });
+        }
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &BIND_GROUP_ENTRIES),
+            entries: &entries,
+            label: Some("material_bind_group"),
+        });
+
+        Self { bind_group }
+    }
+}
+
+// One layer per material, every layer is scaled to the size of the largest one. Maps a material
+// doesn't have are 1x1 stand-ins, so they must not decide the size
+fn create_texture_array<'a>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    format: wgpu::TextureFormat,
+    images: impl ExactSizeIterator<Item = &'a image::RgbaImage>,
+) -> wgpu::TextureView {
+    let images = images.collect::<Vec<&image::RgbaImage>>();
+    let layers = images.len();
+    let (width, height) = images.iter().fold((1, 1), |(width, height), image| {
+        (width.max(image.width()), height.max(image.height()))
+    });
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers.max(1) as u32,
+        },
+        mip_level_count: texture::mip_level_count(width, height),
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+
+    for (layer, image) in images.into_iter().enumerate() {
+        if image.dimensions() == (width, height) {
+            texture::write_mip_chain(queue, &texture, image, layer as u32);
+        } else {
+            let resized = image::imageops::resize(
+                image,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            );
+            texture::write_mip_chain(queue, &texture, &resized, layer as u32);
+        }
+    }
+
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::D2Array),
+        ..Default::default()
+    })
+}
+
+fn texture_asset(path: &str) -> &'static Asset {
+    TEXTURES
+        .iter()
+        .find(|texture| texture.path == path)
+        .expect("materials are checked for unknown textures when they are loaded")
+}
+
+fn decode(bytes: &[u8]) -> Result<image::RgbaImage, String> {
+    image::load_from_memory(bytes)
+        .map(|image| image.to_rgba8())
+        .map_err(|error| error.to_string())
+}
+
+fn create_sampler(device: &wgpu::Device, filter: TextureFilter, anisotropy: u8) -> wgpu::Sampler {
+    let (filter_mode, anisotropy_clamp) = match filter {
+        TextureFilter::Nearest => (wgpu::FilterMode::Nearest, None),
+        // Anisotropic filtering is skipped by wgpu on adapters that don't support it
+        TextureFilter::Linear => (
+            wgpu::FilterMode::Linear,
+            NonZeroU8::new(anisotropy).filter(|clamp| clamp.get() > 1),
+        ),
+    };
+
+    device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Material Sampler"),
+        // Each material has a layer of its own, clamping keeps the other edge of the texture out
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: filter_mode,
+        min_filter: filter_mode,
+        mipmap_filter: filter_mode,
+        anisotropy_clamp,
+        ..Default::default()
+    })
+}
@@ -6,6 +6,7 @@ pub struct Mesh {
 }
 
 impl Mesh {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Mesh {
         Mesh {
             vertices: Vec::new(),
@@ -2,4 +2,20 @@ pub mod render_pass_data;
 pub mod texture;
 pub mod camera;
 pub mod vertex;
-pub mod mesh;
\ No newline at end of file
+pub mod mesh;
+pub mod sky;
+pub mod gpu_mesher;
+pub mod chunk_arena;
+pub mod packed_vertex;
+pub mod assets;
+pub mod pipeline;
+pub mod render_graph;
+pub mod scene_nodes;
+pub mod post_process;
+pub mod material;
+pub mod ssao;
+pub mod lights;
+pub mod debug_view;
+pub mod overlay;
+pub mod text;
+pub mod gpu_timer;
\ No newline at end of file
@@ -0,0 +1,126 @@
+use std::time::{Duration, Instant};
+
+use super::pipeline::PipelineCache;
+use super::render_graph::{Attachments, RenderNode, SURFACE};
+use super::text::TextRenderer;
+use crate::state::State;
+use crate::voxels::voxel_scene::RayHit;
+
+// Frame times are averaged over this long, so the readout stays legible
+const FRAME_STATS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Frames per second and the average time between frames.
+pub struct FrameStats {
+    pub fps: f32,
+    pub frame_time: Duration,
+    last_frame: Option<Instant>,
+    // Frames and their total time since the readout last changed
+    frames: u32,
+    elapsed: Duration,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self {
+            fps: 0.0,
+            frame_time: Duration::ZERO,
+            last_frame: None,
+            frames: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    /// Records the start of a new frame.
+    pub fn frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            self.frames += 1;
+            self.elapsed += now - last_frame;
+        }
+
+        if self.elapsed >= FRAME_STATS_INTERVAL {
+            self.frame_time = self.elapsed / self.frames;
+            self.fps = self.frames as f32 / self.elapsed.as_secs_f32();
+            self.frames = 0;
+            self.elapsed = Duration::ZERO;
+        }
+    }
+}
+
+impl Default for FrameStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Text with frame timings, camera and scene statistics over the top left of the window.
+pub struct DebugOverlay {
+    pub visible: bool,
+    pub frame_stats: FrameStats,
+    // The renderer only knows the chunks that have meshes, so these are set by the scene's owner
+    pub loaded_chunks: usize,
+    // The voxel the camera looks at
+    pub target: Option<RayHit>,
+    text: TextRenderer,
+}
+
+impl DebugOverlay {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        Self {
+            visible: false,
+            frame_stats: FrameStats::new(),
+            loaded_chunks: 0,
+            target: None,
+            text: TextRenderer::new(device, queue, pipelines, color_format),
+        }
+    }
+
+    /// Replaces the text shown while the overlay is visible.
+    pub fn set_lines(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lines: &[String],
+        size: (u32, u32),
+    ) {
+        self.text.set_lines(device, queue, lines, size);
+    }
+}
+
+/// Draws the overlay on top of everything else.
+pub struct OverlayNode;
+
+impl RenderNode<State> for OverlayNode {
+    fn name(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SURFACE]
+    }
+
+    fn run(&self, state: &State, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder) {
+        if !state.overlay.visible {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Overlay Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: attachments.view(SURFACE),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        state.overlay.text.draw(&state.pipelines, &mut render_pass);
+    }
+}
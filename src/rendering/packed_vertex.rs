@@ -0,0 +1,196 @@
+use glam::{IVec3, Vec3};
+
+use super::vertex::Vertex;
+
+// Positions are stored in eighths of a voxel so fluid surfaces keep their height
+pub const POSITION_SCALE: f32 = 8.0;
+const POSITION_BITS: u32 = 10;
+const POSITION_MASK: u32 = (1 << POSITION_BITS) - 1;
+
+// Largest position along any axis. Enough for a LOD group of 8 chunks of the default size
+pub const MAX_POSITION: f32 = POSITION_MASK as f32 / POSITION_SCALE;
+
+const NORMAL_SHIFT: u32 = 0;
+const UV_SHIFT: u32 = 3;
+const AO_SHIFT: u32 = 5;
+const LAYER_SHIFT: u32 = 16;
+
+// Ambient occlusion ranges from 0 (fully occluded) to MAX_AO (not occluded at all)
+pub const MAX_AO: u32 = 3;
+
+// Indexed by the normal bits, same order as the faces in the mesher
+pub const NORMALS: [IVec3; 6] = [
+    IVec3::Z,
+    glam::const_ivec3!([0, 0, -1]),
+    IVec3::X,
+    glam::const_ivec3!([-1, 0, 0]),
+    IVec3::Y,
+    glam::const_ivec3!([0, -1, 0]),
+];
+
+/// An 8 byte voxel vertex, for meshes whose faces are all axis aligned.
+///
+/// The first word holds the position inside the chunk, 10 bits per axis.
+/// The second word holds the normal index (bits 0-2), the corner of the UV square (bits 3-4),
+/// ambient occlusion (bits 5-6) and the material layer (bits 16-31).
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedVertex {
+    pub data: [u32; 2],
+}
+
+impl PackedVertex {
+    /// `position` is relative to the chunk and `uv` is one of the corners of the unit square.
+    /// Returns `None` if the position is outside `0..=MAX_POSITION` or the normal isn't one of
+    /// `NORMALS`, like the sloped faces of partial voxels.
+    pub fn new(position: Vec3, normal: IVec3, uv: [f32; 2], ao: u32, layer: u16) -> Option<Self> {
+        if position.min_element() < 0.0 || position.max_element() > MAX_POSITION {
+            return None;
+        }
+        let fixed = (position * POSITION_SCALE).round().as_uvec3();
+        let normal_index = NORMALS.iter().position(|&axis| axis == normal)? as u32;
+        let uv_corner = (uv[0] > 0.5) as u32 | ((uv[1] > 0.5) as u32) << 1;
+
+        Some(Self {
+            data: [
+                (fixed.x & POSITION_MASK)
+                    | (fixed.y & POSITION_MASK) << POSITION_BITS
+                    | (fixed.z & POSITION_MASK) << (POSITION_BITS * 2),
+                normal_index << NORMAL_SHIFT
+                    | uv_corner << UV_SHIFT
+                    | ao.min(MAX_AO) << AO_SHIFT
+                    | (layer as u32) << LAYER_SHIFT,
+            ],
+        })
+    }
+
+    /// Packs a float vertex of a voxel mesh, moving it into the space of the chunk at `origin`.
+    /// Returns `None` for vertices `new` can't pack.
+    pub fn from_vertex(vertex: &Vertex, origin: Vec3, layer: u16) -> Option<Self> {
+        let normal = Vec3::from(vertex.normal);
+        if !normal.abs_diff_eq(normal.round(), 1e-4) {
+            return None;
+        }
+        Self::new(
+            Vec3::from(vertex.position) - origin,
+            normal.round().as_ivec3(),
+            vertex.uv,
+            MAX_AO,
+            layer,
+        )
+    }
+
+    pub fn position(&self) -> Vec3 {
+        let word = self.data[0];
+        Vec3::new(
+            (word & POSITION_MASK) as f32,
+            (word >> POSITION_BITS & POSITION_MASK) as f32,
+            (word >> (POSITION_BITS * 2) & POSITION_MASK) as f32,
+        ) / POSITION_SCALE
+    }
+
+    pub fn normal(&self) -> IVec3 {
+        NORMALS[(self.data[1] >> NORMAL_SHIFT & 0b111) as usize]
+    }
+
+    pub fn uv(&self) -> [f32; 2] {
+        let corner = self.data[1] >> UV_SHIFT;
+        [(corner & 1) as f32, (corner >> 1 & 1) as f32]
+    }
+
+    pub fn ao(&self) -> u32 {
+        self.data[1] >> AO_SHIFT & MAX_AO
+    }
+
+    pub fn layer(&self) -> u16 {
+        (self.data[1] >> LAYER_SHIFT) as u16
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PackedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                // Both words, unpacked in the shader
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Uint32x2,
+                },
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_round_trip() {
+        for (i, &normal) in NORMALS.iter().enumerate() {
+            let position = Vec3::new(i as f32, 63.5, 0.125);
+            let uv = [(i % 2) as f32, (i / 2 % 2) as f32];
+            let ao = i as u32 % (MAX_AO + 1);
+            let layer = 1000 + i as u16;
+            let vertex = PackedVertex::new(position, normal, uv, ao, layer).unwrap();
+
+            assert_eq!(vertex.position(), position);
+            assert_eq!(vertex.normal(), normal);
+            assert_eq!(vertex.uv(), uv);
+            assert_eq!(vertex.ao(), ao);
+            assert_eq!(vertex.layer(), layer);
+        }
+    }
+
+    #[test]
+    fn extremes_round_trip() {
+        let vertex = PackedVertex::new(
+            Vec3::splat(MAX_POSITION),
+            IVec3::Y,
+            [1.0, 1.0],
+            MAX_AO,
+            u16::MAX,
+        )
+        .unwrap();
+        assert_eq!(vertex.position(), Vec3::splat(MAX_POSITION));
+        assert_eq!(vertex.ao(), MAX_AO);
+        assert_eq!(vertex.layer(), u16::MAX);
+
+        let vertex = PackedVertex::new(Vec3::ZERO, -IVec3::Z, [0.0, 0.0], 0, 0).unwrap();
+        assert_eq!(vertex.position(), Vec3::ZERO);
+        assert_eq!(vertex.normal(), -IVec3::Z);
+        assert_eq!(vertex.uv(), [0.0, 0.0]);
+        assert_eq!(vertex.ao(), 0);
+    }
+
+    #[test]
+    fn unpackable_vertices_are_rejected() {
+        let new = |position, normal| PackedVertex::new(position, normal, [0.0, 0.0], MAX_AO, 0);
+        assert!(new(Vec3::splat(MAX_POSITION + 1.0), IVec3::Y).is_none());
+        assert!(new(Vec3::new(-1.0, 0.0, 0.0), IVec3::Y).is_none());
+        assert!(new(Vec3::ZERO, IVec3::ONE).is_none());
+    }
+
+    #[test]
+    fn from_vertex_moves_into_chunk_space() {
+        let origin = Vec3::new(16.0, -8.0, 32.0);
+        let vertex = Vertex {
+            normal: [1.0, 0.0, 0.0],
+            uv: [1.0, 0.0],
+            ..Vertex::new((origin + Vec3::new(1.0, 2.0, 3.0)).into())
+        };
+        let packed = PackedVertex::from_vertex(&vertex, origin, 7).unwrap();
+        assert_eq!(packed.position(), Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(packed.normal(), IVec3::X);
+        assert_eq!(packed.uv(), [1.0, 0.0]);
+        assert_eq!(packed.ao(), MAX_AO);
+        assert_eq!(packed.layer(), 7);
+
+        let sloped = Vertex {
+            normal: Vec3::ONE.normalize().into(),
+            ..vertex
+        };
+        assert!(PackedVertex::from_vertex(&sloped, origin, 7).is_none());
+    }
+}
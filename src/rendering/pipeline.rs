@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use super::assets::{build_with_fallback, capture_validation_errors, create_shader_module, Asset};
+
+/// How a pipeline tests against and writes to the depth buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DepthState {
+    pub format: wgpu::TextureFormat,
+    pub write_enabled: bool,
+    pub compare: wgpu::CompareFunction,
+}
+
+/// Everything needed to build a render pipeline, doubling as the key it is cached under.
+///
+/// Built up from `new` with the setters below, which start from an opaque, back face culled
+/// pipeline without depth testing.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineDescriptor {
+    pub label: &'static str,
+    pub shader: Asset,
+    pub vertex_entry: &'static str,
+    pub fragment_entry: &'static str,
+    pub vertex_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
+    // One list of entries per bind group, in group order
+    pub bind_group_layouts: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    pub color_format: wgpu::TextureFormat,
+    pub blend: Option<wgpu::BlendState>,
+    pub topology: wgpu::PrimitiveTopology,
+    pub cull_mode: Option<wgpu::Face>,
+    pub polygon_mode: wgpu::PolygonMode,
+    pub depth: Option<DepthState>,
+    pub sample_count: u32,
+}
+
+impl PipelineDescriptor {
+    pub fn new(label: &'static str, shader: Asset, color_format: wgpu::TextureFormat) -> Self {
+        Self {
+            label,
+            shader,
+            vertex_entry: "vs_main",
+            fragment_entry: "fs_main",
+            vertex_layouts: Vec::new(),
+            bind_group_layouts: Vec::new(),
+            color_format,
+            blend: Some(wgpu::BlendState::REPLACE),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            depth: None,
+            sample_count: 1,
+        }
+    }
+
+    pub fn entry_points(mut self, vertex: &'static str, fragment: &'static str) -> Self {
+        self.vertex_entry = vertex;
+        self.fragment_entry = fragment;
+        self
+    }
+
+    pub fn vertex_layout(mut self, layout: wgpu::VertexBufferLayout<'static>) -> Self {
+        self.vertex_layouts.push(layout);
+        self
+    }
+
+    /// Adds the layout of the next bind group.
+    pub fn bind_group(mut self, entries: &[wgpu::BindGroupLayoutEntry]) -> Self {
+        self.bind_group_layouts.push(entries.to_vec());
+        self
+    }
+
+    pub fn blend(mut self, blend: Option<wgpu::BlendState>) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn topology(mut self, topology: wgpu::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn depth(
+        mut self,
+        format: wgpu::TextureFormat,
+        write_enabled: bool,
+        compare: wgpu::CompareFunction,
+    ) -> Self {
+        self.depth = Some(DepthState {
+            format,
+            write_enabled,
+            compare,
+        });
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+}
+
+/// Handle to a pipeline in a `PipelineCache`. Stays valid when the pipeline is rebuilt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineId(usize);
+
+/// Builds render pipelines and bind group layouts once per unique descriptor.
+pub struct PipelineCache {
+    bind_group_layouts: HashMap<Vec<wgpu::BindGroupLayoutEntry>, wgpu::BindGroupLayout>,
+    ids: HashMap<PipelineDescriptor, PipelineId>,
+    pipelines: Vec<(PipelineDescriptor, wgpu::RenderPipeline)>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self {
+            bind_group_layouts: HashMap::new(),
+            ids: HashMap::new(),
+            pipelines: Vec::new(),
+        }
+    }
+
+    /// The layout for a bind group with these entries, shared by every pipeline that uses it.
+    pub fn bind_group_layout(
+        &mut self,
+        device: &wgpu::Device,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> &wgpu::BindGroupLayout {
+        self.bind_group_layouts
+            .entry(entries.to_vec())
+            .or_insert_with(|| {
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: None,
+                    entries,
+                })
+            })
+    }
+
+    /// Finds the pipeline for `descriptor`, building it the first time it is asked for.
+    /// Falls back to the embedded shader if the one on disk doesn't compile.
+    pub fn get_or_create(
+        &mut self,
+        device: &wgpu::Device,
+        descriptor: &PipelineDescriptor,
+    ) -> PipelineId {
+        if let Some(id) = self.ids.get(descriptor) {
+            return *id;
+        }
+
+        for entries in descriptor.bind_group_layouts.iter() {
+            self.bind_group_layout(device, entries);
+        }
+        let pipeline = build_with_fallback(&descriptor.shader, |bytes| {
+            self.create_pipeline(device, descriptor, &String::from_utf8_lossy(bytes))
+        });
+
+        let id = PipelineId(self.pipelines.len());
+        self.pipelines.push((descriptor.clone(), pipeline));
+        self.ids.insert(descriptor.clone(), id);
+        id
+    }
+
+    pub fn get(&self, id: PipelineId) -> &wgpu::RenderPipeline {
+        &self.pipelines[id.0].1
+    }
+
+    /// Rebuilds every pipeline that uses `shader` from the file on disk.
+    /// If any of them fails to build, all of them keep their current pipeline.
+    pub fn reload_shader(&mut self, device: &wgpu::Device, shader: &Asset) -> Result<(), String> {
+        let source = shader.load_string();
+        let rebuilt = self
+            .pipelines
+            .iter()
+            .enumerate()
+            .filter(|(_, (descriptor, _))| descriptor.shader == *shader)
+            .map(|(index, (descriptor, _))| {
+                Ok((index, self.create_pipeline(device, descriptor, &source)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        for (index, pipeline) in rebuilt {
+            self.pipelines[index].1 = pipeline;
+        }
+        Ok(())
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        descriptor: &PipelineDescriptor,
+        source: &str,
+    ) -> Result<wgpu::RenderPipeline, String> {
+        let shader = create_shader_module(device, descriptor.label, source)?;
+
+        let bind_group_layouts = descriptor
+            .bind_group_layouts
+            .iter()
+            .map(|entries| &self.bind_group_layouts[entries])
+            .collect::<Vec<&wgpu::BindGroupLayout>>();
+
+        capture_validation_errors(device, || {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(descriptor.label),
+                bind_group_layouts: &bind_group_layouts,
+                push_constant_ranges: &[],
+            });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(descriptor.label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: descriptor.vertex_entry,
+                    buffers: &descriptor.vertex_layouts,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: descriptor.fragment_entry,
+                    targets: &[wgpu::ColorTargetState {
+                        format: descriptor.color_format,
+                        blend: descriptor.blend,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: descriptor.topology,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw, // <- Polygons are wound counter-clockwise
+                    cull_mode: descriptor.cull_mode,
+                    polygon_mode: descriptor.polygon_mode,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: descriptor.depth.map(|depth| wgpu::DepthStencilState {
+                    format: depth.format,
+                    depth_write_enabled: depth.write_enabled,
+                    depth_compare: depth.compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: descriptor.sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+        })
+    }
+}
+
+impl Default for PipelineCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
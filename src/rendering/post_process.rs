@@ -0,0 +1,402 @@
+use wgpu::util::DeviceExt;
+
+use super::assets::Asset;
+use super::pipeline::{PipelineCache, PipelineDescriptor, PipelineId};
+use super::render_graph::{draw_fullscreen, Attachments, RenderNode, SURFACE};
+use super::scene_nodes::SCENE_COLOR;
+use super::texture::{self, Texture};
+use crate::state::State;
+
+pub const SHADER: Asset = Asset::new(
+    "shaders/post_process.wgsl",
+    include_bytes!("../shaders/post_process.wgsl"),
+);
+
+// Number of times the bright parts of the scene are halved in size before being blurred back up
+const BLOOM_LEVELS: usize = 5;
+
+/// How HDR colors are brought into the range the surface can display.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMapping {
+    // Colors above 1 are clipped
+    None,
+    Reinhard,
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn next(self) -> Self {
+        match self {
+            ToneMapping::None => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::None,
+        }
+    }
+
+    // Must match the constants in post_process.wgsl
+    fn shader_index(self) -> u32 {
+        match self {
+            ToneMapping::None => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::Aces => 2,
+        }
+    }
+}
+
+/// The stages of the post-process chain and their parameters, each of which can be turned off.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PostProcessSettings {
+    pub bloom: bool,
+    // How much of the blurred highlights is added back onto the scene
+    pub bloom_intensity: f32,
+    // Brightness a color needs to have before it starts to glow
+    pub bloom_threshold: f32,
+    pub exposure_enabled: bool,
+    // Multiplier applied to the scene before tone mapping
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+}
+
+impl PostProcessSettings {
+    pub fn new() -> Self {
+        Self {
+            bloom: true,
+            bloom_intensity: 0.3,
+            bloom_threshold: 1.0,
+            exposure_enabled: true,
+            exposure: 1.0,
+            tone_mapping: ToneMapping::Aces,
+        }
+    }
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessUniform {
+    // x is the exposure multiplier, y the bloom intensity, z the bloom threshold
+    params: [f32; 4],
+    // x is 1 when bloom is enabled, y the tone mapping operator
+    flags: [u32; 4],
+}
+
+impl PostProcessUniform {
+    fn new(settings: &PostProcessSettings) -> Self {
+        let exposure = if settings.exposure_enabled {
+            settings.exposure
+        } else {
+            1.0
+        };
+
+        Self {
+            params: [
+                exposure,
+                settings.bloom_intensity,
+                settings.bloom_threshold,
+                0.0,
+            ],
+            flags: [
+                settings.bloom as u32,
+                settings.tone_mapping.shader_index(),
+                0,
+                0,
+            ],
+        }
+    }
+}
+
+// Layout of the bind group holding the `PostProcessUniform`
+const UNIFORM_BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+// The blurred highlights, sampled by the composite pass with the scene's sampler
+const BLOOM_BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Texture {
+        multisampled: false,
+        view_dimension: wgpu::TextureViewDimension::D2,
+        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+    },
+    count: None,
+}];
+
+struct BloomLevel {
+    view: wgpu::TextureView,
+    // Samples this level, for blurring it into the next one
+    bind_group: wgpu::BindGroup,
+}
+
+/// Turns the HDR scene into the final image: bloom, then exposure, then tone mapping.
+pub struct PostProcess {
+    pub settings: PostProcessSettings,
+    buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    sampler: wgpu::Sampler,
+
+    prefilter_pipeline: PipelineId,
+    downsample_pipeline: PipelineId,
+    upsample_pipeline: PipelineId,
+    composite_pipeline: PipelineId,
+
+    // Recreated with the scene color on resize
+    scene_bind_group: Option<wgpu::BindGroup>,
+    bloom_bind_group: Option<wgpu::BindGroup>,
+    // Each level is half the size of the one before, starting at half the size of the scene
+    bloom_levels: Vec<BloomLevel>,
+}
+
+impl PostProcess {
+    pub fn new(
+        device: &wgpu::Device,
+        surface_format: wgpu::TextureFormat,
+        pipelines: &mut PipelineCache,
+    ) -> Self {
+        let settings = PostProcessSettings::new();
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Process Buffer"),
+            contents: bytemuck::cast_slice(&[PostProcessUniform::new(&settings)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &UNIFORM_BIND_GROUP_ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("post_process_bind_group"),
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        // Every pass draws a fullscreen triangle generated from the vertex index
+        let pass = |label, entry, format| {
+            PipelineDescriptor::new(label, SHADER, format)
+                .entry_points("vs_main", entry)
+                .bind_group(&texture::BIND_GROUP_ENTRIES)
+                .bind_group(&UNIFORM_BIND_GROUP_ENTRIES)
+                .cull_mode(None)
+        };
+        let prefilter = pass(
+            "Bloom Prefilter Pipeline",
+            "fs_prefilter",
+            Texture::HDR_FORMAT,
+        );
+        let downsample = pass(
+            "Bloom Downsample Pipeline",
+            "fs_downsample",
+            Texture::HDR_FORMAT,
+        );
+        // Each blurred level is added onto the larger one it came from
+        let upsample = pass(
+            "Bloom Upsample Pipeline",
+            "fs_upsample",
+            Texture::HDR_FORMAT,
+        )
+        .blend(Some(wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        }));
+        let composite = pass("Composite Pipeline", "fs_composite", surface_format)
+            .bind_group(&BLOOM_BIND_GROUP_ENTRIES);
+
+        Self {
+            settings,
+            buffer,
+            uniform_bind_group,
+            sampler,
+            prefilter_pipeline: pipelines.get_or_create(device, &prefilter),
+            downsample_pipeline: pipelines.get_or_create(device, &downsample),
+            upsample_pipeline: pipelines.get_or_create(device, &upsample),
+            composite_pipeline: pipelines.get_or_create(device, &composite),
+            scene_bind_group: None,
+            bloom_bind_group: None,
+            bloom_levels: Vec::new(),
+        }
+    }
+
+    /// Uploads `settings`, call this after changing them.
+    pub fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = PostProcessUniform::new(&self.settings);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Recreates the bloom textures for a scene of this size, sampling `scene` as its input.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        scene: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        self.scene_bind_group = Some(self.create_source_bind_group(device, pipelines, scene));
+
+        self.bloom_levels.clear();
+        for level in 0..BLOOM_LEVELS {
+            let width = (width >> (level + 1)).max(1);
+            let height = (height >> (level + 1)).max(1);
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Bloom Texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Texture::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            let bind_group = self.create_source_bind_group(device, pipelines, &view);
+            self.bloom_levels.push(BloomLevel { view, bind_group });
+
+            // Nothing is gained from blurring a single pixel any further
+            if width == 1 && height == 1 {
+                break;
+            }
+        }
+
+        self.bloom_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &BLOOM_BIND_GROUP_ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&self.bloom_levels[0].view),
+            }],
+            label: Some("bloom_bind_group"),
+        }));
+    }
+
+    fn create_source_bind_group(
+        &self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        view: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &texture::BIND_GROUP_ENTRIES),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("post_process_source_bind_group"),
+        })
+    }
+
+    /// Blurs the bright parts of the scene down the chain of bloom levels and back up into the first.
+    fn run_bloom(
+        &self,
+        pipelines: &PipelineCache,
+        encoder: &mut wgpu::CommandEncoder,
+        scene: &wgpu::BindGroup,
+    ) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let uniform = &self.uniform_bind_group;
+
+        draw_fullscreen(
+            encoder,
+            "Bloom Prefilter Pass",
+            pipelines.get(self.prefilter_pipeline),
+            &[scene, uniform],
+            &self.bloom_levels[0].view,
+            clear,
+        );
+        for pair in self.bloom_levels.windows(2) {
+            draw_fullscreen(
+                encoder,
+                "Bloom Downsample Pass",
+                pipelines.get(self.downsample_pipeline),
+                &[&pair[0].bind_group, uniform],
+                &pair[1].view,
+                clear,
+            );
+        }
+        for pair in self.bloom_levels.windows(2).rev() {
+            draw_fullscreen(
+                encoder,
+                "Bloom Upsample Pass",
+                pipelines.get(self.upsample_pipeline),
+                &[&pair[1].bind_group, uniform],
+                &pair[0].view,
+                wgpu::LoadOp::Load,
+            );
+        }
+    }
+}
+
+/// Runs the post-process chain on the scene color and writes the result to the surface.
+pub struct PostProcessNode;
+
+impl RenderNode<State> for PostProcessNode {
+    fn name(&self) -> &'static str {
+        "post_process"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[SCENE_COLOR]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SURFACE]
+    }
+
+    fn run(&self, state: &State, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder) {
+        let post_process = &state.post_process;
+        let (scene, bloom) = match (
+            &post_process.scene_bind_group,
+            &post_process.bloom_bind_group,
+        ) {
+            (Some(scene), Some(bloom)) => (scene, bloom),
+            _ => panic!("post processing should be resized before the first frame"),
+        };
+
+        if post_process.settings.bloom {
+            post_process.run_bloom(&state.pipelines, encoder, scene);
+        }
+
+        draw_fullscreen(
+            encoder,
+            "Composite Pass",
+            state.pipelines.get(post_process.composite_pipeline),
+            &[scene, &post_process.uniform_bind_group, bloom],
+            attachments.view(SURFACE),
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use super::gpu_timer::{GpuSpan, GpuTimer};
+
+// The texture of the current frame, provided when the graph is executed
+pub const SURFACE: &str = "surface";
+
+/// A texture the graph allocates itself, recreated at the size of the surface whenever it changes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AttachmentDescriptor {
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+    pub sample_count: u32,
+}
+
+pub struct Attachment {
+    pub descriptor: AttachmentDescriptor,
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+/// The attachments of a single frame, looked up by name.
+pub struct Attachments<'a> {
+    transient: &'a HashMap<&'static str, Attachment>,
+    surface: &'a wgpu::TextureView,
+}
+
+impl<'a> Attachments<'a> {
+    pub fn view(&self, name: &str) -> &'a wgpu::TextureView {
+        if name == SURFACE {
+            return self.surface;
+        }
+        &self.get(name).view
+    }
+
+    pub fn get(&self, name: &str) -> &'a Attachment {
+        self.try_get(name)
+            .unwrap_or_else(|| panic!("render graph has no attachment called {}", name))
+    }
+
+    /// The attachment, if the graph currently has one with this name.
+    pub fn try_get(&self, name: &str) -> Option<&'a Attachment> {
+        self.transient.get(name)
+    }
+}
+
+/// A step of the frame, recording its commands with the data in `C`.
+///
+/// Passes that read an attachment run after every pass that writes it. Passes writing the same
+/// attachment run in the order they were added, so the first one should clear it.
+pub trait RenderNode<C> {
+    fn name(&self) -> &'static str;
+
+    /// Attachments whose contents this pass uses without changing them.
+    fn reads(&self) -> &[&'static str] {
+        &[]
+    }
+
+    /// Attachments this pass renders into.
+    fn writes(&self) -> &[&'static str];
+
+    fn run(&self, context: &C, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder);
+}
+
+pub struct RenderGraph<C> {
+    nodes: Vec<Box<dyn RenderNode<C>>>,
+    // Indices into `nodes` in the order they run
+    order: Vec<usize>,
+    descriptors: HashMap<&'static str, AttachmentDescriptor>,
+    attachments: HashMap<&'static str, Attachment>,
+    size: (u32, u32),
+}
+
+impl<C> RenderGraph<C> {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            order: Vec::new(),
+            descriptors: HashMap::new(),
+            attachments: HashMap::new(),
+            size: (0, 0),
+        }
+    }
+
+    /// Declares a transient attachment, allocated on the next `resize`.
+    pub fn add_attachment(&mut self, name: &'static str, descriptor: AttachmentDescriptor) {
+        assert_ne!(name, SURFACE, "the surface is provided by the swapchain");
+        self.descriptors.insert(name, descriptor);
+    }
+
+    /// Changes an attachment, reallocating it right away if the graph already has a size.
+    pub fn set_attachment(
+        &mut self,
+        device: &wgpu::Device,
+        name: &'static str,
+        descriptor: AttachmentDescriptor,
+    ) {
+        self.add_attachment(name, descriptor);
+        if self.size != (0, 0) {
+            let attachment = create_attachment(device, name, descriptor, self.size);
+            self.attachments.insert(name, attachment);
+        }
+    }
+
+    /// Frees an attachment that is no longer rendered to, such as one only needed for some settings.
+    pub fn remove_attachment(&mut self, name: &str) {
+        self.descriptors.remove(name);
+        self.attachments.remove(name);
+    }
+
+    pub fn add_node(&mut self, node: impl RenderNode<C> + 'static) {
+        self.nodes.push(Box::new(node));
+        self.order = self.sort_nodes();
+    }
+
+    pub fn node_names(&self) -> Vec<&'static str> {
+        self.order
+            .iter()
+            .map(|&index| self.nodes[index].name())
+            .collect()
+    }
+
+    pub fn attachment(&self, name: &str) -> Option<&Attachment> {
+        self.attachments.get(name)
+    }
+
+    /// Reallocates every transient attachment at the new size.
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.size = (width.max(1), height.max(1));
+        self.attachments = self
+            .descriptors
+            .iter()
+            .map(|(&name, &descriptor)| {
+                (name, create_attachment(device, name, descriptor, self.size))
+            })
+            .collect();
+    }
+
+    /// Records every pass in dependency order and submits them as a single command buffer.
+    /// With a `timer` this waits for the GPU to finish and returns how long each pass took.
+    pub fn execute(
+        &self,
+        context: &C,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        surface: &wgpu::TextureView,
+        timer: Option<&GpuTimer>,
+    ) -> Vec<GpuSpan> {
+        let attachments = Attachments {
+            transient: &self.attachments,
+            surface,
+        };
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        }); // The encoder is responsible for sending commands to the GPU via a command buffer.
+
+        let timer = timer.filter(|timer| timer.fits(self.order.len()));
+        for (position, &index) in self.order.iter().enumerate() {
+            if let Some(timer) = timer {
+                timer.write_timestamp(&mut encoder, position, false);
+            }
+            self.nodes[index].run(context, &attachments, &mut encoder);
+            if let Some(timer) = timer {
+                timer.write_timestamp(&mut encoder, position, true);
+            }
+        }
+        if let Some(timer) = timer {
+            timer.resolve(&mut encoder, self.order.len());
+        }
+
+        // submit will accept anything that implements IntoIter
+        queue.submit(std::iter::once(encoder.finish()));
+
+        match timer {
+            Some(timer) => timer.read(device, &self.node_names()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Orders the nodes so that writers of an attachment run before its readers, keeping
+    /// the order they were added in wherever that is free to choose.
+    fn sort_nodes(&self) -> Vec<usize> {
+        let count = self.nodes.len();
+        let mut dependencies = vec![Vec::new(); count];
+        for (index, node) in self.nodes.iter().enumerate() {
+            for (other, other_node) in self.nodes.iter().enumerate() {
+                if other == index {
+                    continue;
+                }
+                let writes_read = node.reads().iter().any(|name| {
+                    other_node.writes().contains(name) && !node.writes().contains(name)
+                });
+                let writes_written_earlier = other < index
+                    && node
+                        .writes()
+                        .iter()
+                        .any(|name| other_node.writes().contains(name));
+                if writes_read || writes_written_earlier {
+                    dependencies[index].push(other);
+                }
+            }
+        }
+
+        let mut order = Vec::with_capacity(count);
+        let mut done = vec![false; count];
+        while order.len() < count {
+            let next = (0..count)
+                .find(|&index| {
+                    !done[index]
+                        && dependencies[index]
+                            .iter()
+                            .all(|&dependency| done[dependency])
+                })
+                .unwrap_or_else(|| {
+                    let stuck = (0..count)
+                        .filter(|&index| !done[index])
+                        .map(|index| self.nodes[index].name())
+                        .collect::<Vec<_>>();
+                    panic!("render graph has a dependency cycle between {:?}", stuck)
+                });
+            done[next] = true;
+            order.push(next);
+        }
+        order
+    }
+}
+
+impl<C> Default for RenderGraph<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn create_attachment(
+    device: &wgpu::Device,
+    name: &str,
+    descriptor: AttachmentDescriptor,
+    (width, height): (u32, u32),
+) -> Attachment {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(name),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: descriptor.sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: descriptor.format,
+        usage: descriptor.usage,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    Attachment {
+        descriptor,
+        texture,
+        view,
+    }
+}
+
+/// Records a render pass drawing a single triangle over the whole of `target`, for passes whose
+/// vertex shader generates it from the vertex index.
+pub fn draw_fullscreen(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::RenderPipeline,
+    bind_groups: &[&wgpu::BindGroup],
+    target: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations { load, store: true },
+        }],
+        depth_stencil_attachment: None,
+    });
+
+    render_pass.set_pipeline(pipeline);
+    for (index, bind_group) in bind_groups.iter().enumerate() {
+        render_pass.set_bind_group(index as u32, bind_group, &[]);
+    }
+    render_pass.draw(0..3, 0..1);
+}
@@ -1,33 +1,17 @@
-use super::vertex::Vertex;
-use wgpu::util::DeviceExt;
+use super::chunk_arena::ChunkArena;
+use super::pipeline::PipelineId;
 
-pub struct RenderPassData {
-    pub render_pipeline: wgpu::RenderPipeline,
-
-    pub vertex_buffer: wgpu::Buffer,
-    pub vertex_count: u32,
-    pub index_buffer: wgpu::Buffer,
-    pub index_count: u32,
-
-    pub diffuse_bind_group: wgpu::BindGroup,
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum RenderPassKind {
+    Opaque,
+    // Alpha blended without depth writes, drawn back to front after all opaque passes
+    Transparent,
 }
 
-impl RenderPassData {
-    pub fn set_vertices(&mut self, device: &wgpu::Device, vertices: &Vec<Vertex>) {
-        self.vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Vertex Buffer"),
-            contents: bytemuck::cast_slice(vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-        self.vertex_count = vertices.len() as u32;
-    }
+pub struct RenderPassData {
+    pub kind: RenderPassKind,
+    pub pipeline: PipelineId,
 
-    pub fn set_indices(&mut self, device: &wgpu::Device, indices: &Vec<u32>) {
-        self.index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Index Buffer"),
-            contents: bytemuck::cast_slice(indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-        self.index_count = indices.len() as u32;
-    }
+    // The meshes of every chunk drawn by this pass
+    pub arena: ChunkArena,
 }
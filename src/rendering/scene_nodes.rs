@@ -0,0 +1,185 @@
+use super::render_graph::{Attachments, RenderNode};
+use super::render_pass_data::{RenderPassData, RenderPassKind};
+use super::ssao::{GBUFFER_DEPTH, GBUFFER_NORMALS, OCCLUSION};
+use crate::state::State;
+
+pub const DEPTH: &str = "depth";
+// The lit scene in HDR, before post processing brings it onto the surface
+pub const SCENE_COLOR: &str = "scene_color";
+// Only exists while multisampling is on, the scene is drawn into it and resolved into `SCENE_COLOR`
+pub const SCENE_COLOR_MULTISAMPLED: &str = "scene_color_multisampled";
+
+/// Draws the normals and depth of every opaque chunk for SSAO, without multisampling.
+pub struct GBufferNode;
+
+impl RenderNode<State> for GBufferNode {
+    fn name(&self) -> &'static str {
+        "gbuffer"
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[GBUFFER_NORMALS, GBUFFER_DEPTH]
+    }
+
+    fn run(&self, state: &State, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder) {
+        if !state.ssao.enabled() {
+            return;
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("G-Buffer Pass"),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view: attachments.view(GBUFFER_NORMALS),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: attachments.view(GBUFFER_DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(state.pipelines.get(state.ssao.gbuffer_pipeline));
+        for pass_data in state.render_passes.iter() {
+            if pass_data.kind == RenderPassKind::Opaque {
+                draw_arena(state, &mut render_pass, pass_data);
+            }
+        }
+    }
+}
+
+/// Clears the frame, then draws the sky and every opaque chunk.
+pub struct OpaqueNode;
+
+impl RenderNode<State> for OpaqueNode {
+    fn name(&self) -> &'static str {
+        "opaque"
+    }
+
+    // Ambient light is darkened by the occlusion computed from the G-buffer
+    fn reads(&self) -> &[&'static str] {
+        &[OCCLUSION]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SCENE_COLOR, SCENE_COLOR_MULTISAMPLED, DEPTH]
+    }
+
+    fn run(&self, state: &State, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Opaque Pass"),
+            color_attachments: &[
+                // This is what [[location(0)]] in the fragment shader targets
+                scene_color_attachment(
+                    attachments,
+                    wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 1.0,
+                    }),
+                ),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: attachments.view(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        render_pass.set_pipeline(state.pipelines.get(state.sky.pipeline));
+        render_pass.set_bind_group(0, &state.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &state.sky.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+
+        draw_chunks(state, &mut render_pass, RenderPassKind::Opaque);
+    }
+}
+
+/// Blends the transparent chunks on top of the opaque ones, testing against their depth.
+pub struct TransparentNode;
+
+impl RenderNode<State> for TransparentNode {
+    fn name(&self) -> &'static str {
+        "transparent"
+    }
+
+    // All opaque geometry has to be in the depth buffer before anything is blended on top
+    fn reads(&self) -> &[&'static str] {
+        &[DEPTH]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[SCENE_COLOR, SCENE_COLOR_MULTISAMPLED]
+    }
+
+    fn run(&self, state: &State, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparent Pass"),
+            color_attachments: &[scene_color_attachment(attachments, wgpu::LoadOp::Load)],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: attachments.view(DEPTH),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+
+        draw_chunks(state, &mut render_pass, RenderPassKind::Transparent);
+    }
+}
+
+// Renders into the multisampled scene color when there is one, resolving it at the end of the pass
+fn scene_color_attachment<'a>(
+    attachments: &Attachments<'a>,
+    load: wgpu::LoadOp<wgpu::Color>,
+) -> wgpu::RenderPassColorAttachment<'a> {
+    let (view, resolve_target) = match attachments.try_get(SCENE_COLOR_MULTISAMPLED) {
+        Some(multisampled) => (&multisampled.view, Some(attachments.view(SCENE_COLOR))),
+        None => (attachments.view(SCENE_COLOR), None),
+    };
+
+    wgpu::RenderPassColorAttachment {
+        view,
+        resolve_target,
+        ops: wgpu::Operations { load, store: true },
+    }
+}
+
+fn draw_chunks<'a>(state: &'a State, render_pass: &mut wgpu::RenderPass<'a>, kind: RenderPassKind) {
+    for pass_data in state.render_passes.iter().filter(|pass| pass.kind == kind) {
+        render_pass.set_pipeline(state.pipelines.get(pass_data.pipeline));
+        draw_arena(state, render_pass, pass_data);
+    }
+}
+
+// Expects the pipeline to be set already
+fn draw_arena<'a>(
+    state: &'a State,
+    render_pass: &mut wgpu::RenderPass<'a>,
+    pass_data: &'a RenderPassData,
+) {
+    // Transparent geometry isn't in the G-buffer, so its occlusion would be that of what's behind it
+    let occlusion = match pass_data.kind {
+        RenderPassKind::Opaque => state.ssao.occlusion_bind_group(),
+        RenderPassKind::Transparent => &state.ssao.no_occlusion_bind_group,
+    };
+
+    render_pass.set_bind_group(0, &state.materials.bind_group, &[]);
+    render_pass.set_bind_group(1, &state.camera_bind_group, &[]);
+    render_pass.set_bind_group(2, &state.lights.bind_group, &[]);
+    render_pass.set_bind_group(3, occlusion, &[]);
+    pass_data.arena.draw(render_pass, state.multi_draw_indirect);
+}
@@ -0,0 +1,122 @@
+use wgpu::util::DeviceExt;
+
+use super::assets::Asset;
+use super::camera;
+use super::pipeline::{PipelineCache, PipelineDescriptor, PipelineId};
+use super::texture;
+
+pub const SHADER: Asset = Asset::new("shaders/sky.wgsl", include_bytes!("../shaders/sky.wgsl"));
+
+// Fraction of light that survives the fog at the edge of the view distance
+const FOG_VISIBILITY_AT_VIEW_DISTANCE: f32 = 0.01;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SkyUniform {
+    pub zenith_color: [f32; 4],
+    pub horizon_color: [f32; 4],
+    // xyz is the direction towards the sun, w is the angular size of the sun disk (cosine)
+    pub sun_direction: [f32; 4],
+    pub sun_color: [f32; 4],
+    // x is the exponential fog density, the rest is padding
+    pub fog: [f32; 4],
+}
+
+impl SkyUniform {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let sun = cgmath::Vector3::new(-0.5f32, 0.6, -0.3);
+        let sun = cgmath::InnerSpace::normalize(sun);
+
+        Self {
+            zenith_color: [0.18, 0.38, 0.75, 1.0],
+            horizon_color: [0.7, 0.8, 0.92, 1.0],
+            sun_direction: [sun.x, sun.y, sun.z, 0.9995],
+            sun_color: [1.0, 0.95, 0.8, 1.0],
+            fog: [0.0; 4],
+        }
+    }
+
+    /// Chooses the fog density so that geometry at `view_distance` (in voxels) has faded into the sky.
+    pub fn set_view_distance(&mut self, view_distance: f32) {
+        self.fog[0] = -FOG_VISIBILITY_AT_VIEW_DISTANCE.ln() / view_distance.max(1.0);
+    }
+}
+
+// Layout of the bind group holding the `SkyUniform`
+pub const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+pub struct Sky {
+    pub uniform: SkyUniform,
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub pipeline: PipelineId,
+}
+
+impl Sky {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        pipelines: &mut PipelineCache,
+    ) -> Self {
+        let uniform = SkyUniform::new();
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &BIND_GROUP_ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("sky_bind_group"),
+        });
+
+        let pipeline =
+            pipelines.get_or_create(device, &pipeline_descriptor(color_format, sample_count));
+
+        Self {
+            uniform,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn write_uniform(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
+
+/// The pipeline drawing the sky into a target with this format and sample count.
+pub fn pipeline_descriptor(
+    color_format: wgpu::TextureFormat,
+    sample_count: u32,
+) -> PipelineDescriptor {
+    // The fullscreen triangle is generated from the vertex index, so there are no vertex buffers.
+    // The sky is drawn first and never occludes anything
+    PipelineDescriptor::new("Sky Pipeline", SHADER, color_format)
+        .bind_group(&camera::BIND_GROUP_ENTRIES)
+        .bind_group(&BIND_GROUP_ENTRIES)
+        .cull_mode(None)
+        .depth(
+            texture::Texture::DEPTH_FORMAT,
+            false,
+            wgpu::CompareFunction::Always,
+        )
+        .sample_count(sample_count)
+}
@@ -0,0 +1,349 @@
+use rand::{Rng, SeedableRng};
+use wgpu::util::DeviceExt;
+
+use super::assets::Asset;
+use super::camera;
+use super::pipeline::{PipelineCache, PipelineDescriptor, PipelineId};
+use super::render_graph::{draw_fullscreen, Attachments, RenderGraph, RenderNode};
+use crate::state::State;
+
+pub const SHADER: Asset = Asset::new("shaders/ssao.wgsl", include_bytes!("../shaders/ssao.wgsl"));
+
+// Normals and depth of the opaque scene, drawn without multisampling so they can be read per pixel
+pub const GBUFFER_NORMALS: &str = "gbuffer_normals";
+pub const GBUFFER_DEPTH: &str = "gbuffer_depth";
+// Occlusion before and after blurring, 1 where nothing is occluded
+pub const OCCLUSION_RAW: &str = "occlusion_raw";
+pub const OCCLUSION: &str = "occlusion";
+
+pub const NORMALS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+// Must match the size of the kernel array in ssao.wgsl
+const MAX_KERNEL_SIZE: usize = 32;
+// In voxels
+const RADIUS: f32 = 1.0;
+// Keeps flat surfaces from occluding themselves
+const DEPTH_BIAS: f32 = 0.05;
+const INTENSITY: f32 = 1.0;
+
+/// Trades the number of samples and the size of the blur for speed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SsaoQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl SsaoQuality {
+    pub fn next(self) -> Self {
+        match self {
+            SsaoQuality::Off => SsaoQuality::Low,
+            SsaoQuality::Low => SsaoQuality::Medium,
+            SsaoQuality::Medium => SsaoQuality::High,
+            SsaoQuality::High => SsaoQuality::Off,
+        }
+    }
+
+    // Number of kernel samples per pixel and the blur radius in pixels
+    fn parameters(self) -> (u32, u32) {
+        match self {
+            SsaoQuality::Off => (0, 0),
+            SsaoQuality::Low => (8, 1),
+            SsaoQuality::Medium => (16, 2),
+            SsaoQuality::High => (32, 2),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsaoUniform {
+    // Offsets in a hemisphere around +Z, only the first `counts[0]` are used
+    kernel: [[f32; 4]; MAX_KERNEL_SIZE],
+    // x is the radius, y the depth bias, z the intensity
+    params: [f32; 4],
+    // x is the number of kernel samples, y the blur radius
+    counts: [u32; 4],
+}
+
+impl SsaoUniform {
+    fn new(quality: SsaoQuality) -> Self {
+        let (samples, blur_radius) = quality.parameters();
+
+        // A fixed seed keeps the pattern the same from run to run
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut kernel = [[0.0; 4]; MAX_KERNEL_SIZE];
+        for (index, offset) in kernel.iter_mut().take(samples as usize).enumerate() {
+            let direction = glam::Vec3::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..1.0),
+            )
+            .normalize_or_zero();
+            // More samples close to the center, where occluders matter most
+            let scale = index as f32 / samples as f32;
+            let length = rng.gen_range(0.0..1.0) * (0.1 + 0.9 * scale * scale);
+            *offset = (direction * length).extend(0.0).into();
+        }
+
+        Self {
+            kernel,
+            params: [RADIUS, DEPTH_BIAS, INTENSITY, 0.0],
+            counts: [samples, blur_radius, 0, 0],
+        }
+    }
+}
+
+// Layout of the bind group with the blurred occlusion read by `fs_main` in shader.wgsl
+pub const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Texture {
+        multisampled: false,
+        view_dimension: wgpu::TextureViewDimension::D2,
+        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+    },
+    count: None,
+}];
+
+// The settings and the G-buffer, used by both the occlusion and the blur pass
+const INPUT_BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 3] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Depth,
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    },
+];
+
+/// Screen space ambient occlusion of the opaque scene, darkening its ambient light.
+pub struct Ssao {
+    pub quality: SsaoQuality,
+    buffer: wgpu::Buffer,
+
+    // Draws the opaque chunks into the G-buffer
+    pub gbuffer_pipeline: PipelineId,
+    occlusion_pipeline: PipelineId,
+    blur_pipeline: PipelineId,
+
+    // Bound instead of the occlusion while SSAO is off, and for transparent geometry
+    pub no_occlusion_bind_group: wgpu::BindGroup,
+
+    // Recreated with the attachments on resize
+    input_bind_group: Option<wgpu::BindGroup>,
+    raw_bind_group: Option<wgpu::BindGroup>,
+    occlusion_bind_group: Option<wgpu::BindGroup>,
+}
+
+impl Ssao {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+        gbuffer: &PipelineDescriptor,
+    ) -> Self {
+        let quality = SsaoQuality::Medium;
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSAO Buffer"),
+            contents: bytemuck::cast_slice(&[SsaoUniform::new(quality)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let occlusion = PipelineDescriptor::new("SSAO Pipeline", SHADER, OCCLUSION_FORMAT)
+            .entry_points("vs_main", "fs_ssao")
+            .bind_group(&camera::BIND_GROUP_ENTRIES)
+            .bind_group(&INPUT_BIND_GROUP_ENTRIES)
+            .cull_mode(None);
+        let blur = PipelineDescriptor::new("SSAO Blur Pipeline", SHADER, OCCLUSION_FORMAT)
+            .entry_points("vs_main", "fs_blur")
+            .bind_group(&camera::BIND_GROUP_ENTRIES)
+            .bind_group(&INPUT_BIND_GROUP_ENTRIES)
+            .bind_group(&BIND_GROUP_ENTRIES)
+            .cull_mode(None);
+
+        // A single white texel, which shader.wgsl stretches over the whole screen
+        let white = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("No Occlusion Texture"),
+                size: wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: OCCLUSION_FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+            },
+            &[255],
+        );
+        let no_occlusion_bind_group = create_texture_bind_group(
+            device,
+            pipelines,
+            &white.create_view(&wgpu::TextureViewDescriptor::default()),
+        );
+
+        Self {
+            quality,
+            buffer,
+            gbuffer_pipeline: pipelines.get_or_create(device, gbuffer),
+            occlusion_pipeline: pipelines.get_or_create(device, &occlusion),
+            blur_pipeline: pipelines.get_or_create(device, &blur),
+            no_occlusion_bind_group,
+            input_bind_group: None,
+            raw_bind_group: None,
+            occlusion_bind_group: None,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.quality != SsaoQuality::Off
+    }
+
+    /// Uploads the kernel and settings of `quality`, call this after changing it.
+    pub fn write_uniform(&self, queue: &wgpu::Queue) {
+        let uniform = SsaoUniform::new(self.quality);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// The occlusion for opaque geometry to be shaded with.
+    pub fn occlusion_bind_group(&self) -> &wgpu::BindGroup {
+        match &self.occlusion_bind_group {
+            Some(bind_group) if self.enabled() => bind_group,
+            _ => &self.no_occlusion_bind_group,
+        }
+    }
+
+    /// Points the bind groups at the attachments of `render_graph`, after they were reallocated.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        pipelines: &mut PipelineCache,
+        render_graph: &RenderGraph<State>,
+    ) {
+        let view = |name| {
+            &render_graph
+                .attachment(name)
+                .unwrap_or_else(|| panic!("SSAO needs the {} attachment", name))
+                .view
+        };
+
+        self.input_bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &INPUT_BIND_GROUP_ENTRIES),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(view(GBUFFER_DEPTH)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(view(GBUFFER_NORMALS)),
+                },
+            ],
+            label: Some("ssao_input_bind_group"),
+        }));
+        self.raw_bind_group = Some(create_texture_bind_group(
+            device,
+            pipelines,
+            view(OCCLUSION_RAW),
+        ));
+        self.occlusion_bind_group = Some(create_texture_bind_group(
+            device,
+            pipelines,
+            view(OCCLUSION),
+        ));
+    }
+}
+
+fn create_texture_bind_group(
+    device: &wgpu::Device,
+    pipelines: &mut PipelineCache,
+    view: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: pipelines.bind_group_layout(device, &BIND_GROUP_ENTRIES),
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: wgpu::BindingResource::TextureView(view),
+        }],
+        label: Some("occlusion_bind_group"),
+    })
+}
+
+/// Computes the occlusion from the G-buffer, then blurs it.
+pub struct SsaoNode;
+
+impl RenderNode<State> for SsaoNode {
+    fn name(&self) -> &'static str {
+        "ssao"
+    }
+
+    fn reads(&self) -> &[&'static str] {
+        &[GBUFFER_NORMALS, GBUFFER_DEPTH]
+    }
+
+    fn writes(&self) -> &[&'static str] {
+        &[OCCLUSION_RAW, OCCLUSION]
+    }
+
+    fn run(&self, state: &State, attachments: &Attachments, encoder: &mut wgpu::CommandEncoder) {
+        let ssao = &state.ssao;
+        if !ssao.enabled() {
+            return;
+        }
+        let (input, raw) = match (&ssao.input_bind_group, &ssao.raw_bind_group) {
+            (Some(input), Some(raw)) => (input, raw),
+            _ => panic!("SSAO should be resized before the first frame"),
+        };
+
+        draw_fullscreen(
+            encoder,
+            "SSAO Pass",
+            state.pipelines.get(ssao.occlusion_pipeline),
+            &[&state.camera_bind_group, input],
+            attachments.view(OCCLUSION_RAW),
+            wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+        );
+        draw_fullscreen(
+            encoder,
+            "SSAO Blur Pass",
+            state.pipelines.get(ssao.blur_pipeline),
+            &[&state.camera_bind_group, input, raw],
+            attachments.view(OCCLUSION),
+            wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use wgpu::util::DeviceExt;
+
+use super::assets::Asset;
+use super::pipeline::{PipelineCache, PipelineDescriptor, PipelineId};
+use super::texture;
+
+pub const SHADER: Asset = Asset::new("shaders/text.wgsl", include_bytes!("../shaders/text.wgsl"));
+// Printable ASCII in 16 columns of 8x8 pixel glyphs, starting with the space
+pub const FONT: Asset = Asset::new("textures/font.png", include_bytes!("../textures/font.png"));
+
+const FIRST_CHAR: char = ' ';
+const LAST_CHAR: char = '~';
+const GLYPH_SIZE: f32 = 8.0;
+// Distance of the text from the top left corner, in glyphs
+const MARGIN: f32 = 1.0;
+const SHADOW_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+const INITIAL_GLYPH_CAPACITY: u32 = 1024;
+
+// Layout of the bind group holding the `TextUniform`
+const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 1] = [wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::VERTEX,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TextUniform {
+    // xy is the size of the target in pixels, z the factor glyphs are scaled up by
+    screen_size: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    // Top left corner in pixels
+    position: [f32; 2],
+    glyph: u32,
+    color: [f32; 4],
+}
+
+impl GlyphInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 3] =
+            wgpu::vertex_attr_array![0 => Float32x2, 1 => Uint32, 2 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// Draws lines of monospaced text over the top left of the screen.
+pub struct TextRenderer {
+    // Pixels per font pixel
+    pub scale: f32,
+    pub color: [f32; 4],
+
+    pipeline: PipelineId,
+    // Kept alive for the bind group
+    _atlas: texture::Texture,
+    atlas_bind_group: wgpu::BindGroup,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+
+    glyph_buffer: wgpu::Buffer,
+    glyph_capacity: u32,
+    glyph_count: u32,
+}
+
+impl TextRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+        color_format: wgpu::TextureFormat,
+    ) -> Self {
+        let atlas = texture::Texture::from_bytes(device, queue, &FONT.load(), FONT.path)
+            .expect("the font atlas is a valid image");
+        let atlas_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &texture::BIND_GROUP_ENTRIES),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.sampler),
+                },
+            ],
+            label: Some("font_atlas_bind_group"),
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Text Buffer"),
+            contents: bytemuck::cast_slice(&[TextUniform {
+                screen_size: [1.0, 1.0, 1.0, 0.0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: pipelines.bind_group_layout(device, &BIND_GROUP_ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("text_bind_group"),
+        });
+
+        let descriptor = PipelineDescriptor::new("Text Pipeline", SHADER, color_format)
+            .vertex_layout(GlyphInstance::desc())
+            .bind_group(&texture::BIND_GROUP_ENTRIES)
+            .bind_group(&BIND_GROUP_ENTRIES)
+            .blend(Some(wgpu::BlendState::ALPHA_BLENDING))
+            .cull_mode(None);
+
+        Self {
+            scale: 2.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            pipeline: pipelines.get_or_create(device, &descriptor),
+            _atlas: atlas,
+            atlas_bind_group,
+            uniform_buffer,
+            uniform_bind_group,
+            glyph_buffer: create_glyph_buffer(device, INITIAL_GLYPH_CAPACITY),
+            glyph_capacity: INITIAL_GLYPH_CAPACITY,
+            glyph_count: 0,
+        }
+    }
+
+    /// Replaces the text with `lines`, laid out for a target of `width` x `height` pixels.
+    /// Characters the font doesn't have are drawn as '?'.
+    pub fn set_lines(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lines: &[String],
+        (width, height): (u32, u32),
+    ) {
+        let advance = GLYPH_SIZE * self.scale;
+        let mut glyphs = Vec::new();
+        for (row, line) in lines.iter().enumerate() {
+            for (column, character) in line.chars().enumerate() {
+                if character == ' ' {
+                    continue;
+                }
+                let position = [
+                    (MARGIN + column as f32) * advance,
+                    (MARGIN + row as f32) * advance,
+                ];
+                let glyph = glyph_index(character);
+                // A drop shadow keeps the text readable over bright parts of the scene
+                glyphs.push(GlyphInstance {
+                    position: [position[0] + self.scale, position[1] + self.scale],
+                    glyph,
+                    color: SHADOW_COLOR,
+                });
+                glyphs.push(GlyphInstance {
+                    position,
+                    glyph,
+                    color: self.color,
+                });
+            }
+        }
+
+        let uniform = TextUniform {
+            screen_size: [width as f32, height as f32, self.scale, 0.0],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        if glyphs.len() as u32 > self.glyph_capacity {
+            self.glyph_capacity = (glyphs.len() as u32).next_power_of_two();
+            self.glyph_buffer = create_glyph_buffer(device, self.glyph_capacity);
+        }
+        queue.write_buffer(&self.glyph_buffer, 0, bytemuck::cast_slice(&glyphs));
+        self.glyph_count = glyphs.len() as u32;
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        pipelines: &'a PipelineCache,
+        render_pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        if self.glyph_count == 0 {
+            return;
+        }
+
+        render_pass.set_pipeline(pipelines.get(self.pipeline));
+        render_pass.set_bind_group(0, &self.atlas_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.glyph_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.glyph_count);
+    }
+}
+
+fn glyph_index(character: char) -> u32 {
+    let character = if (FIRST_CHAR..=LAST_CHAR).contains(&character) {
+        character
+    } else {
+        '?'
+    };
+    character as u32 - FIRST_CHAR as u32
+}
+
+fn create_glyph_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Glyph Buffer"),
+        size: (capacity as usize * std::mem::size_of::<GlyphInstance>()) as u64,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
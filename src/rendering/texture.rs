@@ -1,6 +1,32 @@
 use anyhow::*;
 use image::GenericImageView;
 
+// Layout of a bind group with a single texture and its sampler
+pub const BIND_GROUP_ENTRIES: [wgpu::BindGroupLayoutEntry; 2] = [
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(
+            // SamplerBindingType::Comparison is only for TextureSampleType::Depth
+            // SamplerBindingType::Filtering if the sample_type of the texture is:
+            //     TextureSampleType::Float { filterable: true }
+            // Otherwise you'll get an error.
+            wgpu::SamplerBindingType::Filtering,
+        ),
+        count: None,
+    },
+];
+
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
@@ -24,7 +50,8 @@ impl Texture {
         img: &image::DynamicImage,
         label: Option<&str>,
     ) -> Result<Self> {
-        let rgba = img.as_rgba8().unwrap();
+        // Converting instead of unwrapping `as_rgba8` so RGB images load too
+        let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
         let size = wgpu::Extent3d {
@@ -35,28 +62,14 @@ impl Texture {
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count: mip_level_count(dimensions.0, dimensions.1),
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-            },
-            rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: std::num::NonZeroU32::new(dimensions.1),
-            },
-            size,
-        );
+        write_mip_chain(queue, &texture, &rgba, 0);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
         let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
@@ -65,7 +78,7 @@ impl Texture {
             address_mode_w: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Nearest,
             min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
 
@@ -77,6 +90,8 @@ impl Texture {
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+    // The scene is lit into this so bright highlights survive until tone mapping
+    pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
     pub fn create_depth_texture(
         device: &wgpu::Device,
@@ -123,3 +138,86 @@ impl Texture {
         }
     }
 }
+
+/// Number of mip levels needed to halve a texture of this size down to a single texel.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Uploads `image` to `layer` of `texture`, filling every mip level of the texture with
+/// successively halved copies of it.
+pub fn write_mip_chain(
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    image: &image::RgbaImage,
+    layer: u32,
+) {
+    let mip_level_count = mip_level_count(image.width(), image.height());
+    let mut level = image.clone();
+    for mip_level in 0..mip_level_count {
+        if mip_level > 0 {
+            level = image::imageops::resize(
+                &level,
+                (level.width() / 2).max(1),
+                (level.height() / 2).max(1),
+                image::imageops::FilterType::Triangle,
+            );
+        }
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture,
+                mip_level,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: layer,
+                },
+            },
+            &level,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(4 * level.width()),
+                rows_per_image: std::num::NonZeroU32::new(level.height()),
+            },
+            wgpu::Extent3d {
+                width: level.width(),
+                height: level.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+// wgpu only accepts these sample counts in a render pass, whatever the adapter supports
+const RENDER_PASS_SAMPLE_COUNTS: [u32; 2] = [1, 4];
+
+/// The sample counts that can be used to render into every one of `formats`, in ascending order.
+pub fn supported_sample_counts(
+    adapter: &wgpu::Adapter,
+    formats: &[wgpu::TextureFormat],
+) -> Vec<u32> {
+    let renderable = formats.iter().all(|&format| {
+        let features = adapter.get_texture_format_features(format);
+        features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+    });
+
+    RENDER_PASS_SAMPLE_COUNTS
+        .iter()
+        .copied()
+        .filter(|&count| count == 1 || renderable)
+        .collect()
+}
+
+/// The highest supported sample count that isn't above `requested`.
+pub fn closest_sample_count(supported: &[u32], requested: u32) -> u32 {
+    supported
+        .iter()
+        .copied()
+        .filter(|&count| count <= requested)
+        .max()
+        .unwrap_or(1)
+}
@@ -2,21 +2,39 @@
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     pub position: [f32; 3],
-    pub color: [f32; 3],
+    pub color: [f32; 4],
     pub normal: [f32; 3],
     pub uv: [f32; 2],
+    // Layer of the material texture array
+    pub material: u32,
+    // Direction the U coordinate increases in, w is the sign of the bitangent for normal mapping
+    pub tangent: [f32; 4],
 }
 
 impl Vertex {
     pub fn new(position: [f32; 3]) -> Vertex {
         Vertex {
             position,
-            color: [1.0, 1.0, 1.0],
+            color: [1.0, 1.0, 1.0, 1.0],
             normal: [0.0, 0.0, 1.0],
             uv: [0.0, 0.0],
+            material: 0,
+            tangent: [1.0, 0.0, 0.0, 1.0],
         }
     }
 
+    /// The tangent of a quad whose corners are ordered like its UVs: (0, 0), (1, 0), (0, 1), (1, 1).
+    pub fn quad_tangent(corners: &[[f32; 3]; 4], normal: [f32; 3]) -> [f32; 4] {
+        let origin = glam::Vec3::from(corners[0]);
+        let tangent = glam::Vec3::from(corners[1]) - origin;
+        let bitangent = glam::Vec3::from(corners[2]) - origin;
+        let handedness = glam::Vec3::from(normal)
+            .cross(tangent)
+            .dot(bitangent)
+            .signum();
+        tangent.normalize().extend(handedness).into()
+    }
+
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -32,20 +50,32 @@ impl Vertex {
                 wgpu::VertexAttribute {
                     offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
                 // Normal
                 wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    offset: std::mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float32x3,
                 },
                 // UV
                 wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    offset: std::mem::size_of::<[f32; 10]>() as wgpu::BufferAddress,
                     shader_location: 3,
                     format: wgpu::VertexFormat::Float32x2,
                 },
+                // Material
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+                // Tangent
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 13]>() as wgpu::BufferAddress,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
             ],
         }
     }
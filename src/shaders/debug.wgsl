@@ -0,0 +1,106 @@
+// Debug views of the chunk meshes, drawn straight onto the surface instead of the lit scene
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    inv_view_proj: mat4x4<f32>;
+    view_pos: vec4<f32>;
+};
+
+// Must match `DebugUniform` in debug_view.rs
+struct DebugUniform {
+    // x is the edge length of a chunk in voxels
+    chunk_size: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+[[group(1), binding(0)]]
+var<uniform> debug: DebugUniform;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(2)]] normal: vec3<f32>;
+    [[location(3)]] uv: vec2<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] normal: vec3<f32>;
+    [[location(2)]] uv: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(in.position, 1.0);
+    out.position = in.position;
+    out.normal = in.normal;
+    out.uv = in.uv;
+    return out;
+}
+
+let WIRE_COLOR: vec4<f32> = vec4<f32>(0.9, 0.9, 0.9, 1.0);
+
+// Drawn with `PolygonMode::Line`, so every fragment is on an edge
+[[stage(fragment)]]
+fn fs_wireframe(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return WIRE_COLOR;
+}
+
+// For adapters without line polygons. Every quad spans the whole UV square, so its edges are
+// where the UVs reach 0 or 1. This leaves out the diagonal between the two triangles
+[[stage(fragment)]]
+fn fs_wireframe_fallback(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let width = fwidth(in.uv) * 1.5;
+    if (all(in.uv > width) && all(in.uv < vec2<f32>(1.0) - width)) {
+        discard;
+    }
+    return WIRE_COLOR;
+}
+
+[[stage(fragment)]]
+fn fs_normals(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(normalize(in.normal) * 0.5 + 0.5, 1.0);
+}
+
+// An 8x8 checkerboard over each face, with U in red and V in green so flipped UVs stand out
+[[stage(fragment)]]
+fn fs_uv_checker(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let cells = floor(in.uv * 8.0);
+    let parity = (cells.x + cells.y) - 2.0 * floor((cells.x + cells.y) / 2.0);
+    let color = vec3<f32>(in.uv, 0.0) * 0.6 + 0.4 * parity;
+    return vec4<f32>(color, 1.0);
+}
+
+fn hash(value: vec3<f32>) -> vec3<f32> {
+    let p = fract(value * vec3<f32>(0.1031, 0.1030, 0.0973));
+    let q = p + dot(p, p.yxz + 33.33);
+    return fract((q.xxy + q.yxx) * q.zyx);
+}
+
+// A random color per chunk, shaded by the face direction so the voxels stay readable
+[[stage(fragment)]]
+fn fs_chunk_tint(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Faces on a chunk boundary belong to the voxel behind them
+    let chunk = floor((in.position - in.normal * 0.01) / debug.chunk_size.x);
+    let tint = hash(chunk) * 0.8 + 0.2;
+    let shade = 0.7 + 0.3 * dot(normalize(in.normal), normalize(vec3<f32>(0.3, 1.0, 0.5)));
+    return vec4<f32>(tint * shade, 1.0);
+}
+
+// Only fills the depth buffer, its blend state keeps the color underneath
+[[stage(fragment)]]
+fn fs_depth_only(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(0.0);
+}
+
+[[stage(vertex)]]
+fn vs_bounds([[location(0)]] position: vec3<f32>) -> [[builtin(position)]] vec4<f32> {
+    return camera.view_proj * vec4<f32>(position, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_bounds() -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(1.0, 0.85, 0.1, 1.0);
+}
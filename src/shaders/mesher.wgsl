@@ -0,0 +1,318 @@
+// Builds the faces of one chunk, mirroring `generate_faces` in voxel_scene.rs
+struct MesherParams {
+    chunk_size: u32;
+    max_vertices: u32;
+    max_indices: u32;
+};
+
+// One voxel per u32: shape in bits 0-7, fluid level in bits 8-15, profile in bits 16-31
+struct Voxels {
+    data: array<u32>;
+};
+
+struct Profile {
+    color: vec4<f32>;
+    flags: u32;
+    material: u32;
+};
+
+struct Profiles {
+    data: array<Profile>;
+};
+
+// A face of a partially filled voxel, see `GpuShapeFace`
+struct ShapeFace {
+    corners: array<vec4<f32>, 4>;
+    uvs: array<vec2<f32>, 4>;
+    normal: vec4<f32>;
+    tangent: vec4<f32>;
+    corner_count: u32;
+    // One of the six faces below, or NO_SIDE for faces cutting through the voxel
+    side: u32;
+};
+
+// Faces of every shape, ranges holds the first face in the low 16 bits and the count in the high
+struct Shapes {
+    ranges: array<u32, 256>;
+    faces: array<ShapeFace>;
+};
+
+// 17 words per vertex, laid out like `Vertex`. Written as bits so the material index stays intact
+struct Vertices {
+    data: array<u32>;
+};
+
+struct Indices {
+    data: array<u32>;
+};
+
+struct DrawArgs {
+    index_count: atomic<u32>;
+    instance_count: u32;
+    first_index: u32;
+    base_vertex: i32;
+    first_instance: u32;
+};
+
+struct DrawArgsPair {
+    opaque: DrawArgs;
+    transparent: DrawArgs;
+    opaque_vertex_count: atomic<u32>;
+    transparent_vertex_count: atomic<u32>;
+};
+
+let PROFILE_TRANSPARENT: u32 = 1u;
+let PROFILE_FLUID: u32 = 2u;
+let MAX_FLUID_LEVEL: f32 = 8.0;
+let WORDS_PER_VERTEX: u32 = 17u;
+let NO_SIDE: u32 = 6u;
+
+[[group(0), binding(0)]]
+var<uniform> params: MesherParams;
+
+[[group(0), binding(1)]]
+var<storage, read> voxels: Voxels;
+
+[[group(0), binding(2)]]
+var<storage, read> profiles: Profiles;
+
+[[group(0), binding(3)]]
+var<storage, read_write> opaque_vertices: Vertices;
+
+[[group(0), binding(4)]]
+var<storage, read_write> opaque_indices: Indices;
+
+[[group(0), binding(5)]]
+var<storage, read_write> transparent_vertices: Vertices;
+
+[[group(0), binding(6)]]
+var<storage, read_write> transparent_indices: Indices;
+
+[[group(0), binding(7)]]
+var<storage, read_write> draw_args: DrawArgsPair;
+
+[[group(0), binding(8)]]
+var<storage, read> shapes: Shapes;
+
+fn voxel_index(position: vec3<u32>) -> u32 {
+    return (position.x * params.chunk_size + position.y) * params.chunk_size + position.z;
+}
+
+fn face_offset(face: u32) -> vec3<i32> {
+    var offsets = array<vec3<i32>, 6>(
+        vec3<i32>(0, 0, 1),
+        vec3<i32>(0, 0, -1),
+        vec3<i32>(1, 0, 0),
+        vec3<i32>(-1, 0, 0),
+        vec3<i32>(0, 1, 0),
+        vec3<i32>(0, -1, 0),
+    );
+    return offsets[face];
+}
+
+// The corners a neighbour needs to have filled to hide the face, see `voxel_shapes`
+fn face_requirement(face: u32) -> u32 {
+    var requirements = array<u32, 6>(
+        153u, // SOUTH
+        102u, // NORTH
+        51u,  // WEST
+        204u, // EAST
+        15u,  // BOTTOM
+        240u, // TOP
+    );
+    return requirements[face];
+}
+
+fn face_corner(face: u32, corner: u32) -> vec3<f32> {
+    var corners = array<vec3<f32>, 24>(
+        // North
+        vec3<f32>(1.0, 0.0, 1.0),
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(1.0, 1.0, 1.0),
+        vec3<f32>(0.0, 1.0, 1.0),
+        // South
+        vec3<f32>(0.0, 0.0, 0.0),
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(1.0, 1.0, 0.0),
+        // East
+        vec3<f32>(1.0, 0.0, 0.0),
+        vec3<f32>(1.0, 0.0, 1.0),
+        vec3<f32>(1.0, 1.0, 0.0),
+        vec3<f32>(1.0, 1.0, 1.0),
+        // West
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(0.0, 0.0, 0.0),
+        vec3<f32>(0.0, 1.0, 1.0),
+        vec3<f32>(0.0, 1.0, 0.0),
+        // Top
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(1.0, 1.0, 0.0),
+        vec3<f32>(0.0, 1.0, 1.0),
+        vec3<f32>(1.0, 1.0, 1.0),
+        // Bottom
+        vec3<f32>(0.0, 0.0, 1.0),
+        vec3<f32>(1.0, 0.0, 1.0),
+        vec3<f32>(0.0, 0.0, 0.0),
+        vec3<f32>(1.0, 0.0, 0.0),
+    );
+    return corners[face * 4u + corner];
+}
+
+fn face_exposed(position: vec3<i32>, voxel: u32, face: u32) -> bool {
+    let neighbour_position = position + face_offset(face);
+    let size = i32(params.chunk_size);
+    // Neighbours outside of the chunk are treated as empty
+    if (any(neighbour_position < vec3<i32>(0)) || any(neighbour_position >= vec3<i32>(size))) {
+        return true;
+    }
+
+    let neighbour = voxels.data[voxel_index(vec3<u32>(neighbour_position))];
+    let requirement = face_requirement(face);
+    if ((neighbour & requirement) != requirement) {
+        return true;
+    }
+
+    let profile = voxel >> 16u;
+    let neighbour_profile = neighbour >> 16u;
+    if (neighbour_profile == profile && (profiles.data[profile].flags & PROFILE_FLUID) != 0u) {
+        // Lower fluid beside us leaves part of this face exposed, fluid above or below never does
+        return face < 4u && ((neighbour >> 8u) & 255u) < ((voxel >> 8u) & 255u);
+    }
+    return (profiles.data[neighbour_profile].flags & PROFILE_TRANSPARENT) != 0u
+        && neighbour_profile != profile;
+}
+
+fn write_vertex(transparent: bool, index: u32, position: vec3<f32>, color: vec4<f32>, normal: vec3<f32>, uv: vec2<f32>, material: u32, tangent: vec4<f32>) {
+    var values = array<u32, 17>(
+        bitcast<u32>(position.x), bitcast<u32>(position.y), bitcast<u32>(position.z),
+        bitcast<u32>(color.x), bitcast<u32>(color.y), bitcast<u32>(color.z), bitcast<u32>(color.w),
+        bitcast<u32>(normal.x), bitcast<u32>(normal.y), bitcast<u32>(normal.z),
+        bitcast<u32>(uv.x), bitcast<u32>(uv.y),
+        material,
+        bitcast<u32>(tangent.x), bitcast<u32>(tangent.y), bitcast<u32>(tangent.z), bitcast<u32>(tangent.w),
+    );
+    let base = index * WORDS_PER_VERTEX;
+    for (var i = 0u; i < WORDS_PER_VERTEX; i = i + 1u) {
+        if (transparent) {
+            transparent_vertices.data[base + i] = values[i];
+        } else {
+            opaque_vertices.data[base + i] = values[i];
+        }
+    }
+}
+
+fn write_index(transparent: bool, index: u32, value: u32) {
+    if (transparent) {
+        transparent_indices.data[index] = value;
+    } else {
+        opaque_indices.data[index] = value;
+    }
+}
+
+// Reserves room for a face, returning its first vertex and first index. The buffers are sized
+// for the worst case, so running out of room only happens on bad input and returns false in z
+fn allocate_face(transparent: bool, vertex_count: u32, index_count: u32) -> vec3<u32> {
+    var vertex: u32;
+    var index: u32;
+    if (transparent) {
+        vertex = atomicAdd(&draw_args.transparent_vertex_count, vertex_count);
+        index = atomicAdd(&draw_args.transparent.index_count, index_count);
+    } else {
+        vertex = atomicAdd(&draw_args.opaque_vertex_count, vertex_count);
+        index = atomicAdd(&draw_args.opaque.index_count, index_count);
+    }
+    let fits = vertex + vertex_count <= params.max_vertices && index + index_count <= params.max_indices;
+    return vec3<u32>(vertex, index, u32(fits));
+}
+
+[[stage(compute), workgroup_size(4, 4, 4)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    if (any(id >= vec3<u32>(params.chunk_size))) {
+        return;
+    }
+
+    let voxel = voxels.data[voxel_index(id)];
+    if ((voxel & 255u) == 0u) {
+        return;
+    }
+
+    let profile = profiles.data[voxel >> 16u];
+    let transparent = (profile.flags & PROFILE_TRANSPARENT) != 0u;
+
+    // Fluids are only as tall as their fill level
+    var height = 1.0;
+    if ((profile.flags & PROFILE_FLUID) != 0u) {
+        height = f32((voxel >> 8u) & 255u) / MAX_FLUID_LEVEL;
+    }
+
+    let position = vec3<i32>(id);
+    let f_position = vec3<f32>(id);
+
+    // Partially filled voxels are meshed as the solid spanned by their filled corners
+    let shape = voxel & 255u;
+    if (shape != 255u) {
+        let range = shapes.ranges[shape];
+        for (var i = 0u; i < range >> 16u; i = i + 1u) {
+            let face_index = (range & 65535u) + i;
+            let face = shapes.faces[face_index];
+            if (face.side != NO_SIDE && !face_exposed(position, voxel, face.side)) {
+                continue;
+            }
+
+            let slot = allocate_face(transparent, face.corner_count, (face.corner_count - 2u) * 3u);
+            if (slot.z == 0u) {
+                continue;
+            }
+
+            // Fanned out from the first corner, like the CPU mesher
+            for (var corner = 1u; corner + 1u < face.corner_count; corner = corner + 1u) {
+                let index = slot.y + (corner - 1u) * 3u;
+                write_index(transparent, index, slot.x);
+                write_index(transparent, index + 1u, slot.x + corner);
+                write_index(transparent, index + 2u, slot.x + corner + 1u);
+            }
+            for (var corner = 0u; corner < face.corner_count; corner = corner + 1u) {
+                write_vertex(transparent, slot.x + corner, shapes.faces[face_index].corners[corner].xyz + f_position, profile.color, face.normal.xyz, shapes.faces[face_index].uvs[corner], profile.material, face.tangent);
+            }
+        }
+        return;
+    }
+
+    var uvs = array<vec2<f32>, 4>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+
+    for (var face = 0u; face < 6u; face = face + 1u) {
+        if (!face_exposed(position, voxel, face)) {
+            continue;
+        }
+
+        let slot = allocate_face(transparent, 4u, 6u);
+        if (slot.z == 0u) {
+            continue;
+        }
+
+        let normal = vec3<f32>(face_offset(face));
+        // Same as `Vertex::quad_tangent`, the corners are in the order of the UVs
+        let tangent = face_corner(face, 1u) - face_corner(face, 0u);
+        let bitangent = face_corner(face, 2u) - face_corner(face, 0u);
+        let handedness = sign(dot(cross(normal, tangent), bitangent));
+        for (var corner = 0u; corner < 4u; corner = corner + 1u) {
+            let offset = face_corner(face, corner);
+            let vertex_position = vec3<f32>(
+                offset.x + f_position.x,
+                offset.y * height + f_position.y,
+                offset.z + f_position.z,
+            );
+            write_vertex(transparent, slot.x + corner, vertex_position, profile.color, normal, uvs[corner], profile.material, vec4<f32>(tangent, handedness));
+        }
+        var indices = array<u32, 6>(0u, 2u, 1u, 1u, 2u, 3u);
+        for (var i = 0u; i < 6u; i = i + 1u) {
+            write_index(transparent, slot.y + i, slot.x + indices[i]);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+// Bloom, exposure and tone mapping, applied to the HDR scene before it reaches the surface
+struct PostProcessUniform {
+    // x is the exposure multiplier, y the bloom intensity, z the brightness bloom starts at
+    params: vec4<f32>;
+    // x is 1 when bloom is enabled, y the tone mapping operator
+    flags: vec4<u32>;
+};
+
+[[group(0), binding(0)]]
+var t_source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_source: sampler;
+
+[[group(1), binding(0)]]
+var<uniform> settings: PostProcessUniform;
+
+// Only bound for the composite pass
+[[group(2), binding(0)]]
+var t_bloom: texture_2d<f32>;
+
+// Must match `ToneMapping` in post_process.rs
+let TONE_MAPPING_REINHARD: u32 = 1u;
+let TONE_MAPPING_ACES: u32 = 2u;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+// A single triangle that covers the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let x = f32(i32(index & 1u) * 4 - 1);
+    let y = f32(i32(index >> 1u) * 4 - 1);
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.uv = vec2<f32>(x * 0.5 + 0.5, 0.5 - y * 0.5);
+    return out;
+}
+
+fn texel_size() -> vec2<f32> {
+    return 1.0 / vec2<f32>(textureDimensions(t_source));
+}
+
+// Averages four bilinear samples around the pixel, which covers a 4x4 block of the source
+fn box_filter(uv: vec2<f32>) -> vec3<f32> {
+    let offset = texel_size();
+    var color = textureSample(t_source, s_source, uv + vec2<f32>(-offset.x, -offset.y)).rgb;
+    color = color + textureSample(t_source, s_source, uv + vec2<f32>(offset.x, -offset.y)).rgb;
+    color = color + textureSample(t_source, s_source, uv + vec2<f32>(-offset.x, offset.y)).rgb;
+    color = color + textureSample(t_source, s_source, uv + vec2<f32>(offset.x, offset.y)).rgb;
+    return color * 0.25;
+}
+
+// The first downsample only keeps what is brighter than the threshold
+[[stage(fragment)]]
+fn fs_prefilter(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = box_filter(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - settings.params.z, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_downsample(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(box_filter(in.uv), 1.0);
+}
+
+// 3x3 tent filter, added on top of the next larger level by the blend state
+[[stage(fragment)]]
+fn fs_upsample(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let offset = texel_size();
+    var color = textureSample(t_source, s_source, in.uv).rgb * 4.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(-offset.x, 0.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(offset.x, 0.0)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, -offset.y)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(0.0, offset.y)).rgb * 2.0;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(-offset.x, -offset.y)).rgb;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(offset.x, -offset.y)).rgb;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(-offset.x, offset.y)).rgb;
+    color = color + textureSample(t_source, s_source, in.uv + vec2<f32>(offset.x, offset.y)).rgb;
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (color + vec3<f32>(1.0));
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+[[stage(fragment)]]
+fn fs_composite(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    var color = textureSample(t_source, s_source, in.uv).rgb;
+    if (settings.flags.x != 0u) {
+        color = color + textureSample(t_bloom, s_source, in.uv).rgb * settings.params.y;
+    }
+    color = color * settings.params.x;
+
+    if (settings.flags.y == TONE_MAPPING_REINHARD) {
+        color = reinhard(color);
+    } else if (settings.flags.y == TONE_MAPPING_ACES) {
+        color = aces(color);
+    }
+    return vec4<f32>(color, 1.0);
+}
@@ -1,25 +1,75 @@
 // Vertex shader
 struct CameraUniform {
     view_proj: mat4x4<f32>;
+    inv_view_proj: mat4x4<f32>;
     view_pos: vec4<f32>;
 };
 
+struct SkyUniform {
+    zenith_color: vec4<f32>;
+    horizon_color: vec4<f32>;
+    sun_direction: vec4<f32>;
+    sun_color: vec4<f32>;
+    fog: vec4<f32>;
+};
+
 [[group(1), binding(0)]]
 var<uniform> camera: CameraUniform;
 
+// Must match `GpuLight` in lights.rs
+struct Light {
+    // w is the radius
+    position: vec4<f32>;
+    // w is the intensity
+    color: vec4<f32>;
+    // Axis of a spot light, w is the cosine of its inner angle
+    direction: vec4<f32>;
+    // x is the cosine of the outer angle, y is 1 for spot lights
+    cone: vec4<f32>;
+};
+
+struct Lights {
+    data: array<Light>;
+};
+
+// An (offset, count) pair per screen tile, the offsets point at indices into `lights` after them
+struct LightTiles {
+    data: array<u32>;
+};
+
+struct LightGrid {
+    tile_size: u32;
+    tiles_x: u32;
+    tiles_y: u32;
+    light_count: u32;
+};
+
+[[group(2), binding(0)]]
+var<uniform> sky: SkyUniform;
+[[group(2), binding(1)]]
+var<storage, read> lights: Lights;
+[[group(2), binding(2)]]
+var<storage, read> light_tiles: LightTiles;
+[[group(2), binding(3)]]
+var<uniform> light_grid: LightGrid;
+
 struct VertexInput {
     [[location(0)]] position : vec3<f32>;
-    [[location(1)]] color : vec3<f32>;
+    [[location(1)]] color : vec4<f32>;
     [[location(2)]] normal : vec3<f32>;
     [[location(3)]] uv : vec2<f32>;
+    [[location(4)]] material : u32;
+    [[location(5)]] tangent : vec4<f32>;
 };
 
 struct VertexOutput {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] position : vec3<f32>;
-    [[location(1)]] color : vec3<f32>;
+    [[location(1)]] color : vec4<f32>;
     [[location(2)]] normal : vec3<f32>;
     [[location(3)]] uv : vec2<f32>;
+    [[location(4), interpolate(flat)]] material : u32;
+    [[location(5)]] tangent : vec4<f32>;
 };
 
 [[stage(vertex)]]
@@ -30,31 +80,178 @@ fn vs_main(in : VertexInput) -> VertexOutput {
     out.color = in.color;
     out.normal = in.normal;
     out.uv = in.uv;
+    out.material = in.material;
+    out.tangent = in.tangent;
     return out;
 }
 
+// Must match `MaterialUniform` in material.rs
+struct Materials {
+    // x is the sampler each material is drawn with
+    samplers: array<vec4<u32>, 64>;
+    // x scales the roughness map, y the emissive map
+    properties: array<vec4<f32>, 64>;
+};
+
+// One layer per material in each of the texture arrays
 [[group(0), binding(0)]]
-var t_diffuse: texture_2d<f32>;
+var t_diffuse: texture_2d_array<f32>;
 [[group(0), binding(1)]]
-var s_diffuse: sampler;
+var<uniform> materials: Materials;
+[[group(0), binding(2)]]
+var s_diffuse_0: sampler;
+[[group(0), binding(3)]]
+var s_diffuse_1: sampler;
+[[group(0), binding(4)]]
+var s_diffuse_2: sampler;
+[[group(0), binding(5)]]
+var s_diffuse_3: sampler;
+[[group(0), binding(6)]]
+var t_normal: texture_2d_array<f32>;
+[[group(0), binding(7)]]
+var t_roughness: texture_2d_array<f32>;
+[[group(0), binding(8)]]
+var t_emissive: texture_2d_array<f32>;
+
+// Blurred screen space ambient occlusion of the opaque scene, white if it is turned off
+[[group(3), binding(0)]]
+var t_occlusion: texture_2d<f32>;
+
+fn ambient_occlusion(clip_position: vec4<f32>) -> f32 {
+    let pixel = min(vec2<i32>(clip_position.xy), textureDimensions(t_occlusion) - vec2<i32>(1));
+    return textureLoad(t_occlusion, pixel, 0).r;
+}
+
+// The gradients are taken up front, so the sampler can be picked per material without
+// sampling in non-uniform control flow
+fn sample_material(texture: texture_2d_array<f32>, material: u32, uv: vec2<f32>, ddx: vec2<f32>, ddy: vec2<f32>) -> vec4<f32> {
+    let layer = i32(material);
+    let sampler_index = materials.samplers[material].x;
+    if (sampler_index == 1u) {
+        return textureSampleGrad(texture, s_diffuse_1, uv, layer, ddx, ddy);
+    } else if (sampler_index == 2u) {
+        return textureSampleGrad(texture, s_diffuse_2, uv, layer, ddx, ddy);
+    } else if (sampler_index == 3u) {
+        return textureSampleGrad(texture, s_diffuse_3, uv, layer, ddx, ddy);
+    }
+    return textureSampleGrad(texture, s_diffuse_0, uv, layer, ddx, ddy);
+}
+
+// Perturbs the face normal by the material's normal map
+fn mapped_normal(in: VertexOutput, ddx: vec2<f32>, ddy: vec2<f32>) -> vec3<f32> {
+    let normal = normalize(in.normal);
+    // Gram-Schmidt, in case interpolation left the tangent slightly off
+    let tangent = normalize(in.tangent.xyz - normal * dot(normal, in.tangent.xyz));
+    let bitangent = cross(normal, tangent) * in.tangent.w;
+    let texel = sample_material(t_normal, in.material, in.uv, ddx, ddy).xyz * 2.0 - 1.0;
+    return normalize(mat3x3<f32>(tangent, bitangent, normal) * texel);
+}
+
+let PI: f32 = 3.14159265;
+// Reflectance of non-metals seen head on
+let DIELECTRIC_F0: f32 = 0.04;
+
+// Cook-Torrance specular with the GGX distribution, Smith-Schlick geometry and Schlick's
+// Fresnel approximation, already multiplied by the cosine of the light. Scaled by PI to match
+// the diffuse term, which leaves out its 1 / PI
+fn specular(normal: vec3<f32>, view_dir: vec3<f32>, light_dir: vec3<f32>, roughness: f32) -> f32 {
+    let half_dir = normalize(view_dir + light_dir);
+    let n_dot_l = clamp(dot(normal, light_dir), 0.0, 1.0);
+    let n_dot_v = max(dot(normal, view_dir), 0.0001);
+    let n_dot_h = clamp(dot(normal, half_dir), 0.0, 1.0);
+    let v_dot_h = clamp(dot(view_dir, half_dir), 0.0, 1.0);
+
+    // Perceptual roughness is squared, which spreads the highlights more evenly over the range
+    let alpha = max(roughness * roughness, 0.002);
+    let alpha_2 = alpha * alpha;
+    let d_denom = n_dot_h * n_dot_h * (alpha_2 - 1.0) + 1.0;
+    let distribution = alpha_2 / (PI * d_denom * d_denom);
+
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let geometry = n_dot_l / (n_dot_l * (1.0 - k) + k) * n_dot_v / (n_dot_v * (1.0 - k) + k);
+
+    let fresnel = DIELECTRIC_F0 + (1.0 - DIELECTRIC_F0) * pow(1.0 - v_dot_h, 5.0);
+
+    return PI * distribution * geometry * fresnel / (4.0 * n_dot_v);
+}
+
+// Diffuse and specular light from every point and spot light culled into this pixel's tile
+fn local_lights(position: vec3<f32>, clip_position: vec4<f32>, normal: vec3<f32>, view_dir: vec3<f32>, albedo: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let last_tile = vec2<u32>(light_grid.tiles_x - 1u, light_grid.tiles_y - 1u);
+    let tile = min(vec2<u32>(clip_position.xy) / light_grid.tile_size, last_tile);
+    let header = (tile.y * light_grid.tiles_x + tile.x) * 2u;
+    let offset = light_tiles.data[header];
+    let count = light_tiles.data[header + 1u];
+
+    var total = vec3<f32>(0.0);
+    for (var i = 0u; i < count; i = i + 1u) {
+        let index = light_tiles.data[offset + i];
+        let light = lights.data[index];
+        let to_light = light.position.xyz - position;
+        let distance = length(to_light);
+        let radius = light.position.w;
+        if (distance >= radius) {
+            continue;
+        }
+        let light_dir = to_light / max(distance, 0.0001);
+
+        // Inverse square falloff, windowed so it reaches zero at the radius
+        let window = clamp(1.0 - pow(distance / radius, 4.0), 0.0, 1.0);
+        var attenuation = window * window / (distance * distance + 1.0);
+        if (light.cone.y > 0.5) {
+            let cos_angle = dot(-light_dir, light.direction.xyz);
+            let edge = max(light.direction.w - light.cone.x, 0.0001);
+            attenuation = attenuation * clamp((cos_angle - light.cone.x) / edge, 0.0, 1.0);
+        }
+
+        let radiance = light.color.rgb * light.color.w * attenuation;
+        let n_dot_l = clamp(dot(normal, light_dir), 0.0, 1.0);
+        total = total + radiance * (albedo * n_dot_l + specular(normal, view_dir, light_dir, roughness));
+    }
+    return total;
+}
+
+// Must match sky_color in sky.wgsl so distant geometry blends into the sky behind it
+fn sky_color(direction: vec3<f32>) -> vec3<f32> {
+    let height = clamp(direction.y, 0.0, 1.0);
+    return mix(sky.horizon_color.xyz, sky.zenith_color.xyz, sqrt(height));
+}
 
  // Fragment shader
 [[stage(fragment)]]
 fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
-    var col: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.uv);
+    let ddx = dpdx(in.uv);
+    let ddy = dpdy(in.uv);
+    var col: vec4<f32> = sample_material(t_diffuse, in.material, in.uv, ddx, ddy) * in.color;
+    let properties = materials.properties[in.material];
+    let roughness = clamp(sample_material(t_roughness, in.material, in.uv, ddx, ddy).r * properties.x, 0.0, 1.0);
+    let emissive = sample_material(t_emissive, in.material, in.uv, ddx, ddy).rgb * properties.y;
+    let normal = mapped_normal(in, ddx, ddy);
 
-    var light_dir: vec3<f32> = normalize(vec3<f32>(-0.5, 0.6, -0.3));
-    var ambient_light: f32 = 0.5;
-    var light_dot: f32 = clamp(dot(in.normal, light_dir), 0.0, 1.0);
+    var light_dir: vec3<f32> = sky.sun_direction.xyz;
+    var ambient_light: f32 = 0.5 * ambient_occlusion(in.clip_position);
+    var light_dot: f32 = clamp(dot(normal, light_dir), 0.0, 1.0);
 
     var shading: f32 = light_dot;
 
-    var specular_intensity: f32 = clamp(dot(light_dir, reflect(normalize(in.position - camera.view_pos.xyz), in.normal)), 0.0, 1.0);
-    specular_intensity = pow(specular_intensity, 2.0) * 0.2;
-    //var specular_color: vec4<f32> = specular_intensity * col;
+    let view_dir = normalize(camera.view_pos.xyz - in.position);
+    let specular_intensity = specular(normal, view_dir, light_dir, roughness);
+    let local_light = local_lights(in.position, in.clip_position, normal, view_dir, col.xyz, roughness);
+
+    var alpha: f32 = col.a;
+    col = vec4<f32>(col.xyz * (shading + ambient_light) + specular_intensity + local_light + emissive, 1.0);
 
-    col = vec4<f32>(col.xyz * (shading + ambient_light), 1.0);
-    col = col + specular_intensity;
+    // Exponential distance fog
+    var view_offset: vec3<f32> = in.position - camera.view_pos.xyz;
+    var view_distance: f32 = length(view_offset);
+    var fog_amount: f32 = 1.0 - exp(-sky.fog.x * view_distance);
+    col = vec4<f32>(mix(col.xyz, sky_color(view_offset / view_distance), fog_amount), alpha);
 
     return col;
-}
\ No newline at end of file
+}
+
+// Writes the normals of the opaque scene into the G-buffer, for the SSAO pass
+[[stage(fragment)]]
+fn fs_normal(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(normalize(in.normal), 1.0);
+}
@@ -0,0 +1,58 @@
+// Vertex shader
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    inv_view_proj: mat4x4<f32>;
+    view_pos: vec4<f32>;
+};
+
+struct SkyUniform {
+    zenith_color: vec4<f32>;
+    horizon_color: vec4<f32>;
+    sun_direction: vec4<f32>;
+    sun_color: vec4<f32>;
+    fog: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+[[group(1), binding(0)]]
+var<uniform> sky: SkyUniform;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+// A single triangle that covers the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let x = f32(i32(index & 1u) * 4 - 1);
+    let y = f32(i32(index >> 1u) * 4 - 1);
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    out.ndc = vec2<f32>(x, y);
+    return out;
+}
+
+fn sky_color(direction: vec3<f32>) -> vec3<f32> {
+    let height = clamp(direction.y, 0.0, 1.0);
+    return mix(sky.horizon_color.xyz, sky.zenith_color.xyz, sqrt(height));
+}
+
+ // Fragment shader
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Unproject a point on the far plane to find the view direction of this pixel
+    let far = camera.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(far.xyz / far.w - camera.view_pos.xyz);
+
+    var col: vec3<f32> = sky_color(direction);
+
+    let sun_dot = dot(direction, sky.sun_direction.xyz);
+    let sun_disk = clamp((sun_dot - sky.sun_direction.w) / (1.0 - sky.sun_direction.w), 0.0, 1.0);
+    let sun_glow = pow(clamp(sun_dot, 0.0, 1.0), 64.0) * 0.3;
+    col = col + sky.sun_color.xyz * (sun_disk + sun_glow);
+
+    return vec4<f32>(col, 1.0);
+}
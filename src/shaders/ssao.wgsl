@@ -0,0 +1,141 @@
+// Screen space ambient occlusion, computed from the G-buffer and then blurred
+struct CameraUniform {
+    view_proj: mat4x4<f32>;
+    inv_view_proj: mat4x4<f32>;
+    view_pos: vec4<f32>;
+};
+
+// Must match `SsaoUniform` in ssao.rs
+struct SsaoUniform {
+    // Offsets in a hemisphere around +Z, scaled towards the center
+    kernel: array<vec4<f32>, 32>;
+    // x is the radius in voxels, y the depth bias, z the intensity
+    params: vec4<f32>;
+    // x is the number of kernel samples, y the blur radius in pixels
+    counts: vec4<u32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> camera: CameraUniform;
+
+[[group(1), binding(0)]]
+var<uniform> settings: SsaoUniform;
+[[group(1), binding(1)]]
+var t_depth: texture_depth_2d;
+[[group(1), binding(2)]]
+var t_normals: texture_2d<f32>;
+
+// The unblurred occlusion, only bound for the blur pass
+[[group(2), binding(0)]]
+var t_occlusion: texture_2d<f32>;
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+};
+
+// A single triangle that covers the whole screen
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let x = f32(i32(index & 1u) * 4 - 1);
+    let y = f32(i32(index >> 1u) * 4 - 1);
+    out.clip_position = vec4<f32>(x, y, 0.0, 1.0);
+    return out;
+}
+
+// Scene space position of the surface seen at `uv` with this depth
+fn scene_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let position = camera.inv_view_proj * ndc;
+    return position.xyz / position.w;
+}
+
+// Cheap per pixel noise, used to rotate the kernel so its pattern is hidden by the blur
+fn interleaved_gradient_noise(pixel: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(pixel, vec2<f32>(0.06711056, 0.00583715))));
+}
+
+[[stage(fragment)]]
+fn fs_ssao(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let depth = textureLoad(t_depth, pixel, 0);
+    // Nothing but sky
+    if (depth >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let position = scene_position(in.clip_position.xy / size, depth);
+    let normal = normalize(textureLoad(t_normals, pixel, 0).xyz);
+    let distance_to_camera = distance(position, camera.view_pos.xyz);
+
+    // Orient the kernel's hemisphere along the normal, rotated by the noise around it
+    let angle = interleaved_gradient_noise(in.clip_position.xy) * 6.2831853;
+    var helper = vec3<f32>(cos(angle), sin(angle), 0.0);
+    if (abs(normal.z) < 0.9) {
+        helper = vec3<f32>(cos(angle), 0.0, sin(angle));
+    }
+    let tangent = normalize(helper - normal * dot(helper, normal));
+    let bitangent = cross(normal, tangent);
+    let tbn = mat3x3<f32>(tangent, bitangent, normal);
+
+    let radius = settings.params.x;
+    var occlusion = 0.0;
+    for (var i = 0u; i < settings.counts.x; i = i + 1u) {
+        let sample_position = position + tbn * settings.kernel[i].xyz * radius;
+        let clip = camera.view_proj * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let sample_uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (any(sample_uv < vec2<f32>(0.0)) || any(sample_uv >= vec2<f32>(1.0))) {
+            continue;
+        }
+
+        let sample_depth = textureLoad(t_depth, vec2<i32>(sample_uv * size), 0);
+        let occluder = scene_position(sample_uv, sample_depth);
+        let occluder_distance = distance(occluder, camera.view_pos.xyz);
+        let sample_distance = distance(sample_position, camera.view_pos.xyz);
+
+        // Geometry far in front of the sample is a different surface and shouldn't darken this one
+        let range = clamp(radius / max(abs(distance_to_camera - occluder_distance), 0.0001), 0.0, 1.0);
+        if (occluder_distance <= sample_distance - settings.params.y) {
+            occlusion = occlusion + range;
+        }
+    }
+
+    let visibility = 1.0 - occlusion / f32(max(settings.counts.x, 1u)) * settings.params.z;
+    return vec4<f32>(clamp(visibility, 0.0, 1.0));
+}
+
+// Distance from the camera to the surface seen at `pixel`
+fn camera_distance(pixel: vec2<i32>, size: vec2<f32>) -> f32 {
+    let depth = textureLoad(t_depth, pixel, 0);
+    let uv = (vec2<f32>(pixel) + 0.5) / size;
+    return distance(scene_position(uv, depth), camera.view_pos.xyz);
+}
+
+// Box blur that skips pixels at a very different distance, so occlusion doesn't bleed across edges
+[[stage(fragment)]]
+fn fs_blur(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let size = textureDimensions(t_occlusion);
+    let center_distance = camera_distance(pixel, vec2<f32>(size));
+    let blur_radius = i32(settings.counts.y);
+
+    var total = 0.0;
+    var weight = 0.0;
+    for (var x = -blur_radius; x <= blur_radius; x = x + 1) {
+        for (var y = -blur_radius; y <= blur_radius; y = y + 1) {
+            let neighbour = clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - vec2<i32>(1));
+            let neighbour_distance = camera_distance(neighbour, vec2<f32>(size));
+            if (abs(neighbour_distance - center_distance) < 0.05 * center_distance) {
+                total = total + textureLoad(t_occlusion, neighbour, 0).r;
+                weight = weight + 1.0;
+            }
+        }
+    }
+
+    if (weight == 0.0) {
+        return textureLoad(t_occlusion, pixel, 0);
+    }
+    return vec4<f32>(total / weight);
+}
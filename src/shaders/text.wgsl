@@ -0,0 +1,61 @@
+// Screen space text, one instanced quad per glyph of the bitmap font atlas
+struct TextUniform {
+    // xy is the size of the target in pixels, z the factor glyphs are scaled up by
+    screen_size: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var t_atlas: texture_2d<f32>;
+[[group(0), binding(1)]]
+var s_atlas: sampler;
+
+[[group(1), binding(0)]]
+var<uniform> text: TextUniform;
+
+// Must match the atlas layout in text.rs
+let ATLAS_COLUMNS: f32 = 16.0;
+let ATLAS_ROWS: f32 = 6.0;
+let GLYPH_SIZE: f32 = 8.0;
+
+struct GlyphInput {
+    // Top left corner in pixels, from the top left of the target
+    [[location(0)]] position: vec2<f32>;
+    // Cell of the glyph in the atlas, row by row
+    [[location(1)]] glyph: u32;
+    [[location(2)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32, glyph: GlyphInput) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    let pixel = glyph.position + corner * GLYPH_SIZE * text.screen_size.z;
+    let ndc = pixel / text.screen_size.xy * 2.0 - 1.0;
+    let cell = vec2<f32>(f32(glyph.glyph % 16u), f32(glyph.glyph / 16u));
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.uv = (cell + corner) / vec2<f32>(ATLAS_COLUMNS, ATLAS_ROWS);
+    out.color = glyph.color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let coverage = textureSample(t_atlas, s_atlas, in.uv).a;
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
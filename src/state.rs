@@ -1,18 +1,46 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use cgmath::InnerSpace;
 use winit::event::ElementState;
 use winit::event::KeyboardInput;
 use winit::event::VirtualKeyCode;
 use winit::event::WindowEvent;
 use winit::window::Window;
 
-use crate::rendering::camera::Camera;
 use crate::camera_controller::CameraController;
-use crate::rendering::camera::CameraUniform;
+use crate::profiler::{self, Profiler};
+use crate::rendering::assets::{dev_mode, Asset, AssetWatcher};
+use crate::rendering::camera::{self, Camera, CameraUniform};
+use crate::rendering::chunk_arena::ChunkArena;
+use crate::rendering::debug_view::{self, DebugView, DebugViewNode, DebugViews, DEBUG_DEPTH};
+use crate::rendering::gpu_timer::GpuTimer;
+use crate::rendering::lights::{self, Light, LightId, Lights};
+use crate::rendering::material::{self, MaterialTextures};
+use crate::rendering::overlay::{DebugOverlay, OverlayNode};
+use crate::rendering::pipeline::{PipelineCache, PipelineDescriptor};
+use crate::rendering::post_process::{self, PostProcess, PostProcessNode};
+use crate::rendering::render_graph::{AttachmentDescriptor, RenderGraph};
+use crate::rendering::render_pass_data::{RenderPassData, RenderPassKind};
+use crate::rendering::scene_nodes::{
+    GBufferNode, OpaqueNode, TransparentNode, DEPTH, SCENE_COLOR, SCENE_COLOR_MULTISAMPLED,
+};
+use crate::rendering::sky::{self, Sky};
+use crate::rendering::ssao::{self, Ssao, SsaoNode};
+use crate::rendering::text;
 use crate::rendering::texture;
 use crate::rendering::vertex::Vertex;
-use crate::rendering::render_pass_data::RenderPassData;
+use crate::voxels::voxel_profile::{VoxelProfileId, VOXEL_PROFILES};
+use crate::voxels::voxel_scene::DEFAULT_CHUNK_SIZE;
 
 use wgpu::util::DeviceExt;
 
+pub const SHADER: Asset = Asset::new("shaders/shader.wgsl", include_bytes!("shaders/shader.wgsl"));
+
+// Factor the exposure changes by per key press
+const EXPOSURE_STEP: f32 = 1.25;
+// Used if the adapter supports it, otherwise the closest lower sample count is
+const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
 pub struct State {
     pub surface: wgpu::Surface,
     pub device: wgpu::Device,
@@ -21,15 +49,49 @@ pub struct State {
     pub size: winit::dpi::PhysicalSize<u32>,
 
     pub render_passes: Vec<RenderPassData>,
+    // Whether all chunks of a pass can be drawn with a single indirect call
+    pub multi_draw_indirect: bool,
+    // Every render pipeline and bind group layout, built once per descriptor
+    pub pipelines: PipelineCache,
+    // Samples per pixel the scene is rendered with, 1 turns multisampling off
+    pub sample_count: u32,
+    // Every sample count the adapter can render the scene with
+    pub supported_sample_counts: Vec<u32>,
+    // Texture array, samplers and filter settings of every material
+    pub materials: MaterialTextures,
+    // Set in development builds, to rebuild pipelines and textures when their files change
+    pub asset_watcher: Option<AssetWatcher>,
 
     pub camera: Camera,
+    // The camera as it was at the start of the last tick, for interpolation
+    pub previous_camera: Camera,
     pub camera_uniform: CameraUniform,
     pub camera_buffer: wgpu::Buffer,
-    pub camera_bind_group_layout: wgpu::BindGroupLayout,
     pub camera_bind_group: wgpu::BindGroup,
     pub camera_controller: CameraController,
 
-    pub depth_texture: texture::Texture,
+    pub sky: Sky,
+    // Point and spot lights, including the ones of emissive voxels
+    pub lights: Lights,
+    // Measured in chunks, the fog fully hides anything past this distance
+    pub view_distance: u32,
+    // Edge length of the scene's chunks in voxels, to turn the view distance into a fog distance
+    pub chunk_size: u32,
+
+    // Bloom, exposure and tone mapping between the HDR scene and the surface
+    pub post_process: PostProcess,
+    pub ssao: Ssao,
+    // Wireframe, normals and other views of the chunk meshes, bound to the function keys
+    pub debug_views: DebugViews,
+    // Frame timings, camera and scene statistics, toggled with F6
+    pub overlay: DebugOverlay,
+    // CPU scopes and GPU pass timings, captured with F7 and saved as a Chrome trace
+    pub profiler: Profiler,
+    // Only if the adapter supports timestamp queries
+    pub gpu_timer: Option<GpuTimer>,
+
+    // Every pass of a frame and the textures they render into
+    pub render_graph: RenderGraph<State>,
 }
 
 impl State {
@@ -51,10 +113,28 @@ impl State {
             .next()
             .unwrap(); // Finds a suitable adapter
 
+        // Without it every chunk gets its own draw call
+        let features = adapter.features()
+            & (wgpu::Features::MULTI_DRAW_INDIRECT
+                | wgpu::Features::POLYGON_MODE_LINE
+                | wgpu::Features::TIMESTAMP_QUERY);
+        let multi_draw_indirect = features.contains(wgpu::Features::MULTI_DRAW_INDIRECT);
+        // Without it the wireframe debug view is drawn by the fragment shader
+        let polygon_mode_line = features.contains(wgpu::Features::POLYGON_MODE_LINE);
+        // Without it traces only have CPU scopes
+        let timestamp_query = features.contains(wgpu::Features::TIMESTAMP_QUERY);
+
+        let supported_sample_counts = texture::supported_sample_counts(
+            &adapter,
+            &[texture::Texture::HDR_FORMAT, texture::Texture::DEPTH_FORMAT],
+        );
+        let sample_count =
+            texture::closest_sample_count(&supported_sample_counts, DEFAULT_SAMPLE_COUNT);
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
+                    features,
                     limits: wgpu::Limits::default(),
                     label: None,
                 },
@@ -99,23 +179,10 @@ impl State {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-                label: Some("camera_bind_group_layout"),
-            });
+        let mut pipelines = PipelineCache::new();
 
         let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &camera_bind_group_layout,
+            layout: pipelines.bind_group_layout(&device, &camera::BIND_GROUP_ENTRIES),
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: camera_buffer.as_entire_binding(),
@@ -126,173 +193,256 @@ impl State {
         // Camera controller
         let camera_controller = CameraController::new(0.2);
 
+        // Sky
+        let view_distance = 32;
+        let chunk_size = DEFAULT_CHUNK_SIZE;
+        let mut sky = Sky::new(
+            &device,
+            texture::Texture::HDR_FORMAT,
+            sample_count,
+            &mut pipelines,
+        );
+        sky.uniform
+            .set_view_distance((view_distance * chunk_size) as f32);
+        sky.write_uniform(&queue);
+        let lights = Lights::new(&device, &mut pipelines, &sky);
+
         // Render passes
         let render_passes = Vec::new();
+        let materials = MaterialTextures::new(&device, &queue, &mut pipelines);
+
+        // Assets are only watched in development builds
+        let asset_watcher = if dev_mode() {
+            let mut assets = vec![
+                SHADER,
+                sky::SHADER,
+                post_process::SHADER,
+                ssao::SHADER,
+                debug_view::SHADER,
+                text::SHADER,
+            ];
+            assets.extend_from_slice(&material::TEXTURES);
+            AssetWatcher::new(&assets)
+                .map_err(|error| eprintln!("Not watching assets for changes: {}", error))
+                .ok()
+        } else {
+            None
+        };
 
-        // Depth texture
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        // Post processing
+        let mut post_process = PostProcess::new(&device, config.format, &mut pipelines);
+        let mut ssao = Ssao::new(
+            &device,
+            &queue,
+            &mut pipelines,
+            &gbuffer_pipeline_descriptor(),
+        );
+        let debug_views = DebugViews::new(
+            &device,
+            &mut pipelines,
+            config.format,
+            polygon_mode_line,
+            chunk_size,
+        );
+        let overlay = DebugOverlay::new(&device, &queue, &mut pipelines, config.format);
+
+        // Render graph
+        let mut render_graph = RenderGraph::new();
+        render_graph.add_attachment(
+            SCENE_COLOR,
+            AttachmentDescriptor {
+                format: texture::Texture::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                sample_count: 1,
+            },
+        );
+        set_scene_sample_count(&mut render_graph, &device, sample_count);
+        add_ssao_attachments(&mut render_graph);
+        render_graph.add_attachment(
+            DEBUG_DEPTH,
+            AttachmentDescriptor {
+                format: texture::Texture::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                sample_count: 1,
+            },
+        );
+        render_graph.add_node(GBufferNode);
+        render_graph.add_node(SsaoNode);
+        render_graph.add_node(OpaqueNode);
+        render_graph.add_node(TransparentNode);
+        render_graph.add_node(PostProcessNode);
+        render_graph.add_node(DebugViewNode);
+        render_graph.add_node(OverlayNode);
+        let gpu_timer = timestamp_query
+            .then(|| GpuTimer::new(&device, &queue, render_graph.node_names().len() as u32));
+        render_graph.resize(&device, config.width, config.height);
+        ssao.resize(&device, &mut pipelines, &render_graph);
+        post_process.resize(
+            &device,
+            &mut pipelines,
+            &render_graph.attachment(SCENE_COLOR).unwrap().view,
+            config.width,
+            config.height,
+        );
 
-        Self {
+        let mut state = Self {
             surface,
             device,
             queue,
             config,
             size,
             camera,
+            previous_camera: camera,
             camera_uniform,
             camera_buffer,
-            camera_bind_group_layout,
             camera_bind_group,
             camera_controller,
+            sky,
+            lights,
+            view_distance,
+            chunk_size,
+            post_process,
+            ssao,
+            debug_views,
+            overlay,
+            profiler: Profiler::new(),
+            gpu_timer,
             render_passes,
-            depth_texture,
-        }
-    }
-
-    pub fn add_render_pass(&mut self) {
-        let texture_bind_group_layout =
-            self.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    entries: &[
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Texture {
-                                multisampled: false,
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::FRAGMENT,
-                            ty: wgpu::BindingType::Sampler(
-                                // SamplerBindingType::Comparison is only for TextureSampleType::Depth
-                                // SamplerBindingType::Filtering if the sample_type of the texture is:
-                                //     TextureSampleType::Float { filterable: true }
-                                // Otherwise you'll get an error.
-                                wgpu::SamplerBindingType::Filtering,
-                            ),
-                            count: None,
-                        },
-                    ],
-                    label: Some("texture_bind_group_layout"),
-                });
-
-        let diffuse_texture = self.get_texture();
-
-        let diffuse_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
+            multi_draw_indirect,
+            pipelines,
+            materials,
+            sample_count,
+            supported_sample_counts,
+            asset_watcher,
+            render_graph,
+        };
 
-        let shader = self
-            .device
-            .create_shader_module(&wgpu::ShaderModuleDescriptor {
-                label: Some("Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shaders/shader.wgsl").into()),
-            });
-
-        let render_pipeline_layout =
-            self.device
-                .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some("Render Pipeline Layout"),
-                    bind_group_layouts: &[
-                        &texture_bind_group_layout,
-                        &self.camera_bind_group_layout,
-                    ],
-                    push_constant_ranges: &[],
-                });
-
-        let render_pipeline = self
-            .device
-            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Render Pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[Vertex::desc()],
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[wgpu::ColorTargetState {
-                        format: self.config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    }],
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw, // <- Polygons are wound counter-clockwise
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                },
-                depth_stencil: Some(wgpu::DepthStencilState {
-                    format: texture::Texture::DEPTH_FORMAT,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
-                    stencil: wgpu::StencilState::default(),
-                    bias: wgpu::DepthBiasState::default(),
-                }),
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-            });
-
-        let vertex_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: &[],
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-
-        let index_buffer = self
-            .device
-            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: &[],
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-        let vertex_count = 0;
-        let index_count = 0;
+        state.add_render_pass(RenderPassKind::Opaque);
+        state.add_render_pass(RenderPassKind::Transparent);
+        state
+    }
 
+    pub fn add_render_pass(&mut self, kind: RenderPassKind) {
+        let descriptor = chunk_pipeline_descriptor(kind, self.sample_count);
         let pass = RenderPassData {
-            render_pipeline,
-            vertex_buffer,
-            index_buffer,
-            vertex_count,
-            index_count,
-            diffuse_bind_group,
+            kind,
+            pipeline: self.pipelines.get_or_create(&self.device, &descriptor),
+            arena: ChunkArena::new(&self.device),
         };
 
         self.render_passes.push(pass);
     }
 
-    pub fn get_texture(&self) -> texture::Texture {
-        let diffuse_bytes = include_bytes!("textures/lapis_block.png");
-        texture::Texture::from_bytes(&self.device, &self.queue, diffuse_bytes, "tex.png").unwrap()
+    /// Switches multisampling to the closest supported sample count that isn't above `requested`,
+    /// reallocating the scene's targets and switching every scene pipeline over.
+    pub fn set_sample_count(&mut self, requested: u32) {
+        let sample_count = texture::closest_sample_count(&self.supported_sample_counts, requested);
+        if sample_count != requested {
+            eprintln!(
+                "{}x multisampling is not supported, using {}x",
+                requested, sample_count
+            );
+        }
+        if sample_count == self.sample_count {
+            return;
+        }
+        self.sample_count = sample_count;
+
+        set_scene_sample_count(&mut self.render_graph, &self.device, sample_count);
+        self.sky.pipeline = self.pipelines.get_or_create(
+            &self.device,
+            &sky::pipeline_descriptor(texture::Texture::HDR_FORMAT, sample_count),
+        );
+        for pass in self.render_passes.iter_mut() {
+            let descriptor = chunk_pipeline_descriptor(pass.kind, sample_count);
+            pass.pipeline = self.pipelines.get_or_create(&self.device, &descriptor);
+        }
+    }
+
+    /// Rebuilds whatever depends on assets that changed on disk since the last frame.
+    /// Failures are reported and leave the previous pipelines and textures in place.
+    fn reload_changed_assets(&mut self) {
+        let changed = match &self.asset_watcher {
+            Some(watcher) => watcher.take_changed(),
+            None => return,
+        };
+
+        for asset in changed {
+            println!("Reloading {}", asset.path);
+            let result = if material::TEXTURES.contains(&asset) {
+                self.materials
+                    .reload(&self.device, &self.queue, &mut self.pipelines)
+            } else {
+                self.pipelines.reload_shader(&self.device, &asset)
+            };
+
+            if let Err(error) = result {
+                eprintln!("Failed to reload {}:\n{}", asset.path, error);
+            }
+        }
+    }
+
+    pub fn set_view_distance(&mut self, view_distance: u32) {
+        self.view_distance = view_distance;
+        self.update_fog();
+    }
+
+    pub fn set_chunk_size(&mut self, chunk_size: u32) {
+        self.chunk_size = chunk_size;
+        self.update_fog();
+        self.debug_views.set_chunk_size(&self.queue, chunk_size);
+    }
+
+    fn update_fog(&mut self) {
+        self.sky
+            .uniform
+            .set_view_distance((self.view_distance * self.chunk_size) as f32);
+        self.sky.write_uniform(&self.queue);
+    }
+
+    /// Adds a point or spot light, which stays in the scene until it is removed.
+    pub fn add_light(&mut self, light: Light) -> LightId {
+        self.lights.add(light)
+    }
+
+    /// Changes a light, such as its color or radius. Returns None if it was removed.
+    pub fn light_mut(&mut self, id: LightId) -> Option<&mut Light> {
+        self.lights.get_mut(id)
+    }
+
+    /// Returns false if the light was removed.
+    pub fn move_light(&mut self, id: LightId, position: glam::Vec3) -> bool {
+        match self.lights.get_mut(id) {
+            Some(light) => {
+                light.position = position;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn remove_light(&mut self, id: LightId) -> Option<Light> {
+        self.lights.remove(id)
+    }
+
+    /// Replaces the lights of the emissive voxels in `chunk` with ones for `emitters`,
+    /// given as the scene space position and profile of each voxel.
+    pub fn set_chunk_emitters(
+        &mut self,
+        chunk: glam::IVec3,
+        emitters: &[(glam::IVec3, VoxelProfileId)],
+    ) {
+        let lights = emitters.iter().filter_map(|&(position, profile)| {
+            let emissive = VOXEL_PROFILES.get(profile).emissive?;
+            Some(Light::point(
+                position.as_vec3() + glam::Vec3::splat(0.5),
+                emissive.color.into(),
+                emissive.radius,
+                emissive.intensity,
+            ))
+        });
+        self.lights.set_chunk_lights(chunk, lights);
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -303,8 +453,17 @@ impl State {
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
 
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.render_graph
+                .resize(&self.device, new_size.width, new_size.height);
+            self.post_process.resize(
+                &self.device,
+                &mut self.pipelines,
+                &self.render_graph.attachment(SCENE_COLOR).unwrap().view,
+                new_size.width,
+                new_size.height,
+            );
+            self.ssao
+                .resize(&self.device, &mut self.pipelines, &self.render_graph);
         }
     }
 
@@ -328,6 +487,50 @@ impl State {
                             }
                             true
                         }
+                        VirtualKeyCode::M => {
+                            if is_pressed {
+                                self.cycle_sample_count();
+                            }
+                            true
+                        }
+                        VirtualKeyCode::O => {
+                            if is_pressed {
+                                self.cycle_ssao_quality();
+                            }
+                            true
+                        }
+                        VirtualKeyCode::F1
+                        | VirtualKeyCode::F2
+                        | VirtualKeyCode::F3
+                        | VirtualKeyCode::F4
+                        | VirtualKeyCode::F5 => {
+                            if is_pressed {
+                                self.change_debug_view(*keycode);
+                            }
+                            true
+                        }
+                        VirtualKeyCode::F6 => {
+                            if is_pressed {
+                                self.overlay.visible = !self.overlay.visible;
+                            }
+                            true
+                        }
+                        VirtualKeyCode::F7 => {
+                            if is_pressed {
+                                self.toggle_trace_capture();
+                            }
+                            true
+                        }
+                        VirtualKeyCode::B
+                        | VirtualKeyCode::X
+                        | VirtualKeyCode::T
+                        | VirtualKeyCode::Minus
+                        | VirtualKeyCode::Equals => {
+                            if is_pressed {
+                                self.change_post_process(*keycode);
+                            }
+                            true
+                        }
                         _ => false,
                     }
                 }
@@ -336,14 +539,186 @@ impl State {
         }
     }
 
-    pub fn update(&mut self) {
+    fn cycle_sample_count(&mut self) {
+        let supported = &self.supported_sample_counts;
+        let index = supported
+            .iter()
+            .position(|&count| count == self.sample_count)
+            .unwrap_or(0);
+        let sample_count = supported[(index + 1) % supported.len()];
+        log::info!("{}x multisampling", sample_count);
+        self.set_sample_count(sample_count);
+    }
+
+    fn cycle_ssao_quality(&mut self) {
+        self.ssao.quality = self.ssao.quality.next();
+        log::info!("SSAO quality: {:?}", self.ssao.quality);
+        self.ssao.write_uniform(&self.queue);
+    }
+
+    // B toggles bloom, X exposure and T cycles the tone mapping, - and = change the exposure
+    fn change_post_process(&mut self, keycode: VirtualKeyCode) {
+        let settings = &mut self.post_process.settings;
+        match keycode {
+            VirtualKeyCode::B => settings.bloom = !settings.bloom,
+            VirtualKeyCode::X => settings.exposure_enabled = !settings.exposure_enabled,
+            VirtualKeyCode::T => settings.tone_mapping = settings.tone_mapping.next(),
+            VirtualKeyCode::Minus => settings.exposure /= EXPOSURE_STEP,
+            VirtualKeyCode::Equals => settings.exposure *= EXPOSURE_STEP,
+            _ => return,
+        }
+        log::info!("{:?}", settings);
+        self.post_process.write_uniform(&self.queue);
+    }
+
+    // F1 to F4 switch between the wireframe, normals, UV checker and chunk tint views, pressing
+    // the current one again goes back to the scene. F5 toggles the chunk outlines
+    fn change_debug_view(&mut self, keycode: VirtualKeyCode) {
+        let debug_views = &mut self.debug_views;
+        match keycode {
+            VirtualKeyCode::F1 => debug_views.toggle_view(DebugView::Wireframe),
+            VirtualKeyCode::F2 => debug_views.toggle_view(DebugView::Normals),
+            VirtualKeyCode::F3 => debug_views.toggle_view(DebugView::UvChecker),
+            VirtualKeyCode::F4 => debug_views.toggle_view(DebugView::ChunkTint),
+            VirtualKeyCode::F5 => debug_views.chunk_bounds = !debug_views.chunk_bounds,
+            _ => return,
+        }
+        log::info!(
+            "Debug view: {:?}, chunk bounds: {}",
+            debug_views.view, debug_views.chunk_bounds
+        );
+    }
+
+    /// Starts capturing a trace, or saves the one being captured into the working directory.
+    pub fn toggle_trace_capture(&mut self) {
+        if !self.profiler.is_capturing() {
+            self.profiler.start_capture();
+            if self.gpu_timer.is_none() {
+                log::warn!(
+                    "Capturing a trace without GPU timings, timestamp queries are not supported"
+                );
+            } else {
+                log::info!("Capturing a trace");
+            }
+            return;
+        }
+
+        let events = self.profiler.stop_capture();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let path = format!("trace-{}.json", timestamp);
+        match profiler::write_chrome_trace(path.as_ref(), &events) {
+            Ok(()) => log::info!("Saved {} events to {}", events.len(), path),
+            Err(error) => eprintln!("Failed to save trace to {}: {}", path, error),
+        }
+    }
+
+    /// Advances the simulation side of the state by one fixed timestep.
+    pub fn tick(&mut self) {
+        self.previous_camera = self.camera;
         self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
+    }
+
+    /// Prepares GPU data for a frame that is `alpha` of the way between the last two ticks.
+    pub fn update(&mut self, alpha: f32) {
+        self.reload_changed_assets();
+        self.overlay.frame_stats.frame();
+
+        let camera = self.previous_camera.interpolate(&self.camera, alpha);
+        self.camera_uniform.update_view_proj(&camera);
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
+        self.lights.update(
+            &self.device,
+            &self.queue,
+            &mut self.pipelines,
+            &self.sky,
+            self.camera_uniform.view_proj(),
+            (self.config.width, self.config.height),
+        );
+        self.debug_views.update(&self.device);
+        if self.overlay.visible {
+            let lines = self.overlay_lines(&camera);
+            self.overlay.set_lines(
+                &self.device,
+                &self.queue,
+                &lines,
+                (self.config.width, self.config.height),
+            );
+        }
+
+        let eye = glam::Vec3::new(camera.eye.x, camera.eye.y, camera.eye.z);
+        for pass in self.render_passes.iter_mut() {
+            match pass.kind {
+                RenderPassKind::Opaque => pass.arena.flush(&self.device, &self.queue),
+                RenderPassKind::Transparent => {
+                    pass.arena
+                        .flush_back_to_front(&self.device, &self.queue, eye)
+                }
+            }
+        }
+    }
+
+    fn overlay_lines(&self, camera: &Camera) -> Vec<String> {
+        let stats = &self.overlay.frame_stats;
+        let post_process = &self.post_process.settings;
+        let eye = glam::Vec3::new(camera.eye.x, camera.eye.y, camera.eye.z);
+        let forward = (camera.target - camera.eye).normalize();
+        let chunk = (eye / self.chunk_size as f32).floor().as_ivec3();
+        let (vertices, indices) = self.render_passes.iter().fold((0, 0), |(v, i), pass| {
+            (v + pass.arena.vertex_count(), i + pass.arena.index_count())
+        });
+        let target = match &self.overlay.target {
+            Some(hit) => format!(
+                "Target: {} {} {} {} ({:.1} away)",
+                hit.position.x,
+                hit.position.y,
+                hit.position.z,
+                VOXEL_PROFILES
+                    .name_of(hit.voxel.profile)
+                    .unwrap_or("unknown"),
+                hit.distance,
+            ),
+            None => "Target: none".to_string(),
+        };
+
+        vec![
+            format!(
+                "{:.0} FPS ({:.2} ms)",
+                stats.fps,
+                stats.frame_time.as_secs_f32() * 1000.0
+            ),
+            format!("Position: {:.1} {:.1} {:.1}", eye.x, eye.y, eye.z),
+            format!(
+                "Direction: {:.2} {:.2} {:.2}",
+                forward.x, forward.y, forward.z
+            ),
+            format!("Chunk: {} {} {}", chunk.x, chunk.y, chunk.z),
+            format!("Chunks loaded: {}", self.overlay.loaded_chunks),
+            format!("Vertices: {} Indices: {}", vertices, indices),
+            target,
+            // Settings changed with the keys in `input`
+            format!(
+                "{}x multisampling, SSAO: {:?}",
+                self.sample_count, self.ssao.quality
+            ),
+            format!(
+                "Bloom: {} Exposure: {} ({:.2}) Tone mapping: {:?}",
+                post_process.bloom,
+                post_process.exposure_enabled,
+                post_process.exposure,
+                post_process.tone_mapping
+            ),
+            format!(
+                "Debug view: {:?} Chunk bounds: {}",
+                self.debug_views.view, self.debug_views.chunk_bounds
+            ),
+            format!("Capturing a trace: {}", self.profiler.is_capturing()),
+        ]
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -352,58 +727,127 @@ impl State {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("Render Encoder"),
-            }); // The encoder is responsible for sending commands to the GPU via a command buffer.
-
-        {
-            // Wrap encoder.begin_render_pass borrows 'encoder'so that the borrow is dropped and can be used later
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[
-                    // This is what [[location(0)]] in the fragment shader targets
-                    wgpu::RenderPassColorAttachment {
-                        view: &view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.0,
-                                g: 0.0,
-                                b: 0.0,
-                                a: 1.0,
-                            }),
-                            store: true,
-                        },
-                    },
-                ],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture.view,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
-                        store: true,
-                    }),
-                    stencil_ops: None,
-                }),
-            });
-
-            for pass_data in &self.render_passes {
-                render_pass.set_pipeline(&pass_data.render_pipeline);
-                render_pass.set_bind_group(0, &pass_data.diffuse_bind_group, &[]);
-                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-                render_pass.set_vertex_buffer(0, pass_data.vertex_buffer.slice(..));
-                render_pass
-                    .set_index_buffer(pass_data.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-
-                render_pass.draw_indexed(0..pass_data.index_count, 0, 0..1);
+        // Passes are only timed while capturing, as reading the timings back stalls the frame
+        let timer = self
+            .gpu_timer
+            .as_ref()
+            .filter(|_| self.profiler.is_capturing());
+        let spans = self
+            .render_graph
+            .execute(self, &self.device, &self.queue, &view, timer);
+        let finished = Instant::now();
+        output.present();
+
+        // The GPU was done by the time the timings were read back, which puts the spans
+        // on the CPU's clock within the time the read back took
+        let gpu_time = spans
+            .iter()
+            .map(|span| span.start + span.duration)
+            .max()
+            .unwrap_or_default();
+        if let Some(gpu_start) = finished.checked_sub(gpu_time) {
+            for span in spans {
+                self.profiler
+                    .record_gpu(span.name, gpu_start + span.start, span.duration);
             }
         }
 
-        // submit will accept anything that implements IntoIter
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
-
         Ok(())
     }
 }
+
+fn chunk_pipeline_descriptor(kind: RenderPassKind, sample_count: u32) -> PipelineDescriptor {
+    let transparent = kind == RenderPassKind::Transparent;
+    PipelineDescriptor::new("Render Pipeline", SHADER, texture::Texture::HDR_FORMAT)
+        .vertex_layout(Vertex::desc())
+        .bind_group(&material::BIND_GROUP_ENTRIES)
+        .bind_group(&camera::BIND_GROUP_ENTRIES)
+        .bind_group(&lights::BIND_GROUP_ENTRIES)
+        .bind_group(&ssao::BIND_GROUP_ENTRIES)
+        .blend(Some(if transparent {
+            wgpu::BlendState::ALPHA_BLENDING
+        } else {
+            wgpu::BlendState::REPLACE
+        }))
+        // Transparent surfaces such as water should also be visible from behind
+        .cull_mode(if transparent {
+            None
+        } else {
+            Some(wgpu::Face::Back)
+        })
+        .depth(
+            texture::Texture::DEPTH_FORMAT,
+            !transparent,
+            wgpu::CompareFunction::Less,
+        )
+        .sample_count(sample_count)
+}
+
+// Shares the layout of the chunk pipelines, so the same bind groups can be set for both
+fn gbuffer_pipeline_descriptor() -> PipelineDescriptor {
+    PipelineDescriptor::new("G-Buffer Pipeline", SHADER, ssao::NORMALS_FORMAT)
+        .entry_points("vs_main", "fs_normal")
+        .vertex_layout(Vertex::desc())
+        .bind_group(&material::BIND_GROUP_ENTRIES)
+        .bind_group(&camera::BIND_GROUP_ENTRIES)
+        .bind_group(&lights::BIND_GROUP_ENTRIES)
+        .bind_group(&ssao::BIND_GROUP_ENTRIES)
+        .cull_mode(Some(wgpu::Face::Back))
+        .depth(
+            texture::Texture::DEPTH_FORMAT,
+            true,
+            wgpu::CompareFunction::Less,
+        )
+}
+
+// The G-buffer has its own depth, since the scene's can be multisampled
+fn add_ssao_attachments(render_graph: &mut RenderGraph<State>) {
+    let sampled = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING;
+    let attachments = [
+        (ssao::GBUFFER_NORMALS, ssao::NORMALS_FORMAT),
+        (ssao::GBUFFER_DEPTH, texture::Texture::DEPTH_FORMAT),
+        (ssao::OCCLUSION_RAW, ssao::OCCLUSION_FORMAT),
+        (ssao::OCCLUSION, ssao::OCCLUSION_FORMAT),
+    ];
+    for (name, format) in attachments {
+        render_graph.add_attachment(
+            name,
+            AttachmentDescriptor {
+                format,
+                usage: sampled,
+                sample_count: 1,
+            },
+        );
+    }
+}
+
+// The depth buffer is multisampled along with the scene, which is resolved into `SCENE_COLOR`
+fn set_scene_sample_count(
+    render_graph: &mut RenderGraph<State>,
+    device: &wgpu::Device,
+    sample_count: u32,
+) {
+    render_graph.set_attachment(
+        device,
+        DEPTH,
+        AttachmentDescriptor {
+            format: texture::Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            sample_count,
+        },
+    );
+
+    if sample_count > 1 {
+        render_graph.set_attachment(
+            device,
+            SCENE_COLOR_MULTISAMPLED,
+            AttachmentDescriptor {
+                format: texture::Texture::HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                sample_count,
+            },
+        );
+    } else {
+        render_graph.remove_attachment(SCENE_COLOR_MULTISAMPLED);
+    }
+}
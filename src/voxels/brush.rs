@@ -0,0 +1,409 @@
+use glam::{IVec3, UVec3, Vec3};
+
+use crate::voxels::voxel_data::{voxel_shapes, VoxelData, VoxelShape};
+use crate::voxels::voxel_edit::VoxelEdit;
+use crate::voxels::voxel_profile::{VoxelProfileId, VOXEL_PROFILES};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BrushShape {
+    Sphere,
+    Box,
+    // Upright, as tall as it is wide
+    Cylinder,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum BrushMode {
+    Add,
+    Subtract,
+    Paint,
+    // Fills everything below the brush center and clears everything above it
+    Flatten,
+    Smooth,
+}
+
+pub struct Brush {
+    pub shape: BrushShape,
+    pub mode: BrushMode,
+    pub radius: f32,
+    // Profile given to voxels that the brush creates or paints
+    pub profile: VoxelProfileId,
+}
+
+const CORNERS: [UVec3; 8] = [
+    UVec3::ZERO,
+    UVec3::X,
+    UVec3::Y,
+    UVec3::Z,
+    glam::const_uvec3!([1, 1, 0]),
+    glam::const_uvec3!([1, 0, 1]),
+    glam::const_uvec3!([0, 1, 1]),
+    UVec3::ONE,
+];
+
+impl Brush {
+    pub fn new(shape: BrushShape, mode: BrushMode, radius: f32, profile: VoxelProfileId) -> Self {
+        Self {
+            shape,
+            mode,
+            radius,
+            profile,
+        }
+    }
+
+    /// Applies the brush once around `center` and returns the positions of the voxels it changed.
+    /// A stroke is any number of dabs into the same edit.
+    pub fn dab(&self, edit: &mut VoxelEdit, center: Vec3) -> Vec<IVec3> {
+        let reach = IVec3::splat(self.radius.ceil() as i32 + 1);
+        let min = center.floor().as_ivec3() - reach;
+        let max = center.floor().as_ivec3() + reach;
+
+        // Work out every change before writing any, so smoothing only sees the old surface
+        let mut updates = Vec::new();
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let position = IVec3::new(x, y, z);
+                    let voxel = match edit.voxel_at(&position) {
+                        Some(voxel) => *voxel,
+                        None => continue,
+                    };
+
+                    if let Some(updated) = self.apply_to_voxel(edit, &position, voxel, center) {
+                        updates.push((position, updated));
+                    }
+                }
+            }
+        }
+
+        updates
+            .into_iter()
+            .map(|(position, voxel)| {
+                edit.set_voxel(&position, voxel);
+                position
+            })
+            .collect()
+    }
+
+    fn apply_to_voxel(
+        &self,
+        edit: &VoxelEdit,
+        position: &IVec3,
+        voxel: VoxelData,
+        center: Vec3,
+    ) -> Option<VoxelData> {
+        let inside = self.corner_mask(position, center);
+        if inside == voxel_shapes::EMPTY {
+            return None;
+        }
+
+        let current = solid_shape(&voxel);
+        let shape = match self.mode {
+            BrushMode::Add => current.append(inside),
+            BrushMode::Subtract => current.remove(inside),
+            BrushMode::Paint => {
+                return (current != voxel_shapes::EMPTY && voxel.profile != self.profile)
+                    .then_some(VoxelData {
+                        profile: self.profile,
+                        ..voxel
+                    });
+            }
+            BrushMode::Flatten => self.map_corners(current, inside, |corner| {
+                (position.y + corner.y as i32) as f32 <= center.y
+            }),
+            BrushMode::Smooth => self.map_corners(current, inside, |corner| {
+                smoothed_solidity(edit, *position + corner.as_ivec3()) >= 0.5
+            }),
+        };
+
+        if shape == current {
+            return None;
+        }
+
+        Some(if shape == voxel_shapes::EMPTY {
+            VoxelData {
+                shape,
+                profile: 0,
+                fluid_level: 0,
+            }
+        } else if current == voxel_shapes::EMPTY {
+            // New material replaces air and fluids alike
+            VoxelData {
+                shape,
+                profile: self.profile,
+                fluid_level: 0,
+            }
+        } else {
+            VoxelData { shape, ..voxel }
+        })
+    }
+
+    /// Sets every corner inside the brush to `filled(corner)`, leaving the others untouched.
+    fn map_corners(
+        &self,
+        shape: VoxelShape,
+        inside: VoxelShape,
+        filled: impl Fn(UVec3) -> bool,
+    ) -> VoxelShape {
+        CORNERS.iter().fold(shape, |shape, corner| {
+            let bit = VoxelShape::corner(corner.x, corner.y, corner.z);
+            if !inside.contains(bit) {
+                shape
+            } else if filled(*corner) {
+                shape.append(bit)
+            } else {
+                shape.remove(bit)
+            }
+        })
+    }
+
+    /// The corners of the voxel at `position` that fall inside the brush footprint.
+    fn corner_mask(&self, position: &IVec3, center: Vec3) -> VoxelShape {
+        CORNERS.iter().fold(voxel_shapes::EMPTY, |mask, corner| {
+            let point = (*position + corner.as_ivec3()).as_vec3();
+            if self.contains(point - center) {
+                mask.append(VoxelShape::corner(corner.x, corner.y, corner.z))
+            } else {
+                mask
+            }
+        })
+    }
+
+    fn contains(&self, offset: Vec3) -> bool {
+        match self.shape {
+            BrushShape::Sphere => offset.length() <= self.radius,
+            BrushShape::Box => offset.abs().max_element() <= self.radius,
+            BrushShape::Cylinder => {
+                Vec3::new(offset.x, 0.0, offset.z).length() <= self.radius
+                    && offset.y.abs() <= self.radius
+            }
+        }
+    }
+}
+
+/// The shape of the solid part of a voxel. Fluids count as empty space.
+fn solid_shape(voxel: &VoxelData) -> VoxelShape {
+    if VOXEL_PROFILES.get(voxel.profile).fluid {
+        voxel_shapes::EMPTY
+    } else {
+        voxel.shape
+    }
+}
+
+/// Fraction of the voxels sharing the corner point `point` that have that corner filled.
+fn corner_solidity(edit: &VoxelEdit, point: IVec3) -> f32 {
+    let filled = CORNERS
+        .iter()
+        .filter(|corner| {
+            // `point` is this corner of the voxel that sits at `point - corner`
+            let owner = point - corner.as_ivec3();
+            edit.voxel_at(&owner).is_some_and(|voxel| {
+                solid_shape(voxel).contains(VoxelShape::corner(corner.x, corner.y, corner.z))
+            })
+        })
+        .count();
+
+    filled as f32 / CORNERS.len() as f32
+}
+
+/// Corner solidity averaged with the six corner points around it.
+fn smoothed_solidity(edit: &VoxelEdit, point: IVec3) -> f32 {
+    let neighbours = [
+        IVec3::X,
+        IVec3::Y,
+        IVec3::Z,
+        -IVec3::X,
+        -IVec3::Y,
+        -IVec3::Z,
+    ];
+
+    let total = neighbours
+        .iter()
+        .map(|offset| corner_solidity(edit, point + *offset))
+        .sum::<f32>()
+        + corner_solidity(edit, point);
+
+    total / (neighbours.len() + 1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::voxel_edit::EditTransaction;
+    use crate::voxels::voxel_scene::{VoxelChunk, VoxelScene};
+
+    const CHUNK_SIZE: u32 = 8;
+
+    fn dirt() -> VoxelProfileId {
+        VOXEL_PROFILES.id_of("dirt").unwrap()
+    }
+
+    fn glass() -> VoxelProfileId {
+        VOXEL_PROFILES.id_of("glass").unwrap()
+    }
+
+    /// A single chunk whose voxels below y = 4 are solid dirt.
+    fn scene_with_floor() -> VoxelScene {
+        let mut scene = VoxelScene::with_chunk_size(CHUNK_SIZE);
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO, CHUNK_SIZE));
+
+        let solid = VoxelData {
+            shape: voxel_shapes::ALL,
+            profile: dirt(),
+            fluid_level: 0,
+        };
+        for x in 0..CHUNK_SIZE as i32 {
+            for y in 0..4 {
+                for z in 0..CHUNK_SIZE as i32 {
+                    *scene.voxel_at_mut(&IVec3::new(x, y, z)).unwrap() = solid;
+                }
+            }
+        }
+        scene
+    }
+
+    fn dab(scene: &mut VoxelScene, brush: &Brush, center: Vec3) {
+        let mut edit = scene.begin_edit();
+        brush.dab(&mut edit, center);
+        edit.commit();
+    }
+
+    fn voxel(scene: &VoxelScene, x: i32, y: i32, z: i32) -> VoxelData {
+        *scene.voxel_at(&IVec3::new(x, y, z)).unwrap()
+    }
+
+    #[test]
+    fn corner_masks_follow_the_footprint() {
+        let center = Vec3::splat(4.0);
+        let mask =
+            |shape| Brush::new(shape, BrushMode::Add, 1.0, 0).corner_mask(&IVec3::splat(4), center);
+
+        // The corner on the center and its three neighbours one voxel away
+        assert_eq!(
+            mask(BrushShape::Sphere),
+            VoxelShape::corner(0, 0, 0)
+                .append(VoxelShape::corner(1, 0, 0))
+                .append(VoxelShape::corner(0, 1, 0))
+                .append(VoxelShape::corner(0, 0, 1))
+        );
+        assert_eq!(mask(BrushShape::Box), voxel_shapes::ALL);
+        // Only the corners diagonally away from the axis stick out of the cylinder
+        assert_eq!(
+            mask(BrushShape::Cylinder),
+            voxel_shapes::ALL
+                .remove(VoxelShape::corner(1, 0, 1))
+                .remove(VoxelShape::corner(1, 1, 1))
+        );
+
+        let brush = Brush::new(BrushShape::Box, BrushMode::Add, 1.0, 0);
+        assert_eq!(
+            brush.corner_mask(&IVec3::new(5, 4, 4), center),
+            voxel_shapes::WEST
+        );
+        assert_eq!(
+            brush.corner_mask(&IVec3::new(6, 4, 4), center),
+            voxel_shapes::EMPTY
+        );
+    }
+
+    #[test]
+    fn add_fills_the_footprint() {
+        let mut scene = scene_with_floor();
+        let brush = Brush::new(BrushShape::Box, BrushMode::Add, 1.0, glass());
+        dab(&mut scene, &brush, Vec3::new(4.0, 5.0, 4.0));
+
+        let inside = voxel(&scene, 4, 5, 4);
+        assert_eq!(inside.shape, voxel_shapes::ALL);
+        assert_eq!(inside.profile, glass());
+        // Voxels on the edge only get the corners inside the box
+        assert_eq!(voxel(&scene, 5, 5, 4).shape, voxel_shapes::WEST);
+        assert_eq!(voxel(&scene, 2, 5, 4).shape, voxel_shapes::EAST);
+        // Solid voxels keep their profile
+        assert_eq!(voxel(&scene, 4, 3, 4).profile, dirt());
+    }
+
+    #[test]
+    fn subtract_cuts_corners_out_of_the_surface() {
+        let mut scene = scene_with_floor();
+        let brush = Brush::new(BrushShape::Sphere, BrushMode::Subtract, 1.5, dirt());
+        dab(&mut scene, &brush, Vec3::splat(4.0));
+
+        // Every corner but the one sqrt(3) away from the center is removed
+        assert_eq!(voxel(&scene, 4, 3, 4).shape, VoxelShape::corner(1, 0, 1));
+        assert_eq!(voxel(&scene, 0, 3, 0).shape, voxel_shapes::ALL);
+        // Nothing appears in the air above
+        assert_eq!(voxel(&scene, 4, 4, 4).shape, voxel_shapes::EMPTY);
+    }
+
+    #[test]
+    fn paint_only_changes_the_profile_of_solid_voxels() {
+        let mut scene = scene_with_floor();
+        let brush = Brush::new(BrushShape::Box, BrushMode::Paint, 1.0, glass());
+        dab(&mut scene, &brush, Vec3::splat(4.0));
+
+        let painted = voxel(&scene, 4, 3, 4);
+        assert_eq!(painted.profile, glass());
+        assert_eq!(painted.shape, voxel_shapes::ALL);
+        assert_eq!(voxel(&scene, 0, 3, 0).profile, dirt());
+
+        let air = voxel(&scene, 4, 4, 4);
+        assert_eq!(air.shape, voxel_shapes::EMPTY);
+        assert_ne!(air.profile, glass());
+    }
+
+    #[test]
+    fn flatten_levels_the_footprint_at_the_center() {
+        // Cuts down to the center
+        let mut scene = scene_with_floor();
+        let brush = Brush::new(BrushShape::Box, BrushMode::Flatten, 2.0, dirt());
+        dab(&mut scene, &brush, Vec3::new(4.0, 2.5, 4.0));
+        assert_eq!(voxel(&scene, 4, 1, 4).shape, voxel_shapes::ALL);
+        assert_eq!(voxel(&scene, 4, 2, 4).shape, voxel_shapes::BOTTOM);
+        assert_eq!(voxel(&scene, 4, 3, 4).shape, voxel_shapes::EMPTY);
+
+        // And fills up to it
+        let mut scene = scene_with_floor();
+        dab(&mut scene, &brush, Vec3::new(4.0, 5.5, 4.0));
+        assert_eq!(voxel(&scene, 4, 4, 4).shape, voxel_shapes::ALL);
+        assert_eq!(voxel(&scene, 4, 5, 4).shape, voxel_shapes::BOTTOM);
+        assert_eq!(voxel(&scene, 4, 6, 4).shape, voxel_shapes::EMPTY);
+    }
+
+    #[test]
+    fn smooth_wears_down_a_lone_pillar() {
+        let mut scene = scene_with_floor();
+        *scene.voxel_at_mut(&IVec3::splat(4)).unwrap() = voxel(&scene, 4, 3, 4);
+        let brush = Brush::new(BrushShape::Sphere, BrushMode::Smooth, 2.0, dirt());
+        dab(&mut scene, &brush, Vec3::splat(4.5));
+
+        let pillar = voxel(&scene, 4, 4, 4);
+        assert!(!pillar.shape.overlaps(voxel_shapes::TOP));
+        assert_eq!(voxel(&scene, 4, 3, 4).shape, voxel_shapes::ALL);
+    }
+
+    #[test]
+    fn stroke_is_undone_as_one_step() {
+        let mut scene = scene_with_floor();
+        let brush = Brush::new(BrushShape::Sphere, BrushMode::Subtract, 1.5, dirt());
+
+        // Dabbed over several frames like `BrushController` does
+        let mut stroke = EditTransaction::new();
+        for x in [2.0, 4.0, 6.0] {
+            let mut edit = scene.resume_edit(stroke);
+            let changed = brush.dab(&mut edit, Vec3::new(x, 4.0, 4.0));
+            assert!(changed.contains(&IVec3::new(x as i32, 3, 4)));
+            stroke = edit.suspend();
+        }
+        scene.resume_edit(stroke).commit();
+        assert_ne!(voxel(&scene, 2, 3, 4).shape, voxel_shapes::ALL);
+        assert_ne!(voxel(&scene, 6, 3, 4).shape, voxel_shapes::ALL);
+
+        scene.undo().unwrap();
+        for x in 0..CHUNK_SIZE as i32 {
+            assert_eq!(voxel(&scene, x, 3, 4).shape, voxel_shapes::ALL);
+        }
+        assert!(scene.undo().is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::voxels::voxel_data::VoxelData;
+
+/// Backing store for the voxels of a single chunk, addressed by a flat index.
+pub trait VoxelStorage: Send + Sync {
+    fn get(&self, index: usize) -> &VoxelData;
+    /// Storages that don't hold every voxel by value hand out a copy here and write it back
+    /// before the next change to the storage.
+    fn get_mut(&mut self, index: usize) -> &mut VoxelData;
+    fn set(&mut self, index: usize, voxel: VoxelData);
+    /// Drops anything that isn't referenced anymore, e.g. after a lot of edits.
+    fn compact(&mut self) {}
+    /// Approximate heap and inline size in bytes.
+    fn memory_usage(&self) -> usize;
+}
+
+/// Every distinct voxel is stored once in a palette, and each voxel only stores a bit-packed
+/// index into it. A chunk made of a single kind of voxel needs no indices at all.
+pub struct PaletteStorage {
+    len: usize,
+    palette: Vec<VoxelData>,
+    indices: PackedIndices,
+    // The voxel last handed out by `get_mut`, not written back yet
+    pending: Option<(usize, VoxelData)>,
+}
+
+impl PaletteStorage {
+    pub fn new(len: usize, fill: VoxelData) -> Self {
+        Self {
+            len,
+            palette: vec![fill],
+            indices: PackedIndices::new(len, 0),
+            pending: None,
+        }
+    }
+
+    fn write_back(&mut self) {
+        if let Some((index, voxel)) = self.pending.take() {
+            self.write(index, voxel);
+        }
+    }
+
+    fn write(&mut self, index: usize, voxel: VoxelData) {
+        if *self.get(index) == voxel {
+            return;
+        }
+        let palette_index = self.palette_index(voxel);
+        self.indices.set(index, palette_index);
+    }
+
+    fn palette_index(&mut self, voxel: VoxelData) -> usize {
+        if let Some(index) = self.palette.iter().position(|entry| *entry == voxel) {
+            return index;
+        }
+
+        self.palette.push(voxel);
+        let bits = bits_for(self.palette.len());
+        if bits > self.indices.bits {
+            self.indices = self.indices.repacked(self.len, bits);
+        }
+        self.palette.len() - 1
+    }
+}
+
+impl VoxelStorage for PaletteStorage {
+    fn get(&self, index: usize) -> &VoxelData {
+        match &self.pending {
+            Some((pending_index, voxel)) if *pending_index == index => voxel,
+            _ => &self.palette[self.indices.get(index)],
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut VoxelData {
+        self.write_back();
+        let voxel = *self.get(index);
+        &mut self.pending.insert((index, voxel)).1
+    }
+
+    fn set(&mut self, index: usize, voxel: VoxelData) {
+        self.write_back();
+        self.write(index, voxel);
+    }
+
+    fn compact(&mut self) {
+        self.write_back();
+        let mut used = vec![false; self.palette.len()];
+        for index in 0..self.len {
+            used[self.indices.get(index)] = true;
+        }
+        if used.iter().all(|&used| used) {
+            return;
+        }
+
+        // Old palette index -> new palette index
+        let mut remap = vec![0; self.palette.len()];
+        let mut palette = Vec::new();
+        for (old, voxel) in self.palette.iter().enumerate() {
+            if used[old] {
+                remap[old] = palette.len();
+                palette.push(*voxel);
+            }
+        }
+
+        let mut indices = PackedIndices::new(self.len, bits_for(palette.len()));
+        for index in 0..self.len {
+            indices.set(index, remap[self.indices.get(index)]);
+        }
+
+        self.palette = palette;
+        self.indices = indices;
+    }
+
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.palette.capacity() * std::mem::size_of::<VoxelData>()
+            + self.indices.words.capacity() * std::mem::size_of::<u64>()
+    }
+}
+
+/// Fixed width unsigned integers packed into 64 bit words. Entries never straddle two words.
+struct PackedIndices {
+    bits: u32,
+    words: Vec<u64>,
+}
+
+impl PackedIndices {
+    fn new(len: usize, bits: u32) -> Self {
+        let words = match 64u32.checked_div(bits) {
+            Some(per_word) => vec![0; len.div_ceil(per_word as usize)],
+            // A single palette entry needs no indices at all
+            None => Vec::new(),
+        };
+        Self { bits, words }
+    }
+
+    fn get(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = (64 / self.bits) as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        ((self.words[index / per_word] >> shift) & self.mask()) as usize
+    }
+
+    fn set(&mut self, index: usize, value: usize) {
+        if self.bits == 0 {
+            debug_assert_eq!(value, 0);
+            return;
+        }
+        let per_word = (64 / self.bits) as usize;
+        let shift = (index % per_word) as u32 * self.bits;
+        let mask = self.mask();
+        let word = &mut self.words[index / per_word];
+        *word = (*word & !(mask << shift)) | ((value as u64) << shift);
+    }
+
+    fn repacked(&self, len: usize, bits: u32) -> Self {
+        let mut repacked = Self::new(len, bits);
+        for index in 0..len {
+            repacked.set(index, self.get(index));
+        }
+        repacked
+    }
+
+    fn mask(&self) -> u64 {
+        (1 << self.bits) - 1
+    }
+}
+
+/// Smallest index width that can address `palette_len` entries.
+fn bits_for(palette_len: usize) -> u32 {
+    if palette_len <= 1 {
+        0
+    } else {
+        usize::BITS - (palette_len - 1).leading_zeros()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::voxel_data::voxel_shapes;
+
+    fn voxel(profile: u16) -> VoxelData {
+        VoxelData {
+            shape: voxel_shapes::ALL,
+            profile,
+            fluid_level: 0,
+        }
+    }
+
+    #[test]
+    fn palette_writes_back_mutable_access() {
+        let air = VoxelData {
+            shape: voxel_shapes::EMPTY,
+            profile: 0,
+            fluid_level: 0,
+        };
+        let mut storage = PaletteStorage::new(64, air);
+
+        *storage.get_mut(3) = voxel(1);
+        assert_eq!(*storage.get(3), voxel(1));
+
+        // Handing out another voxel writes the first one back
+        *storage.get_mut(5) = voxel(2);
+        storage.set(7, voxel(3));
+        storage.compact();
+
+        assert_eq!(*storage.get(3), voxel(1));
+        assert_eq!(*storage.get(5), voxel(2));
+        assert_eq!(*storage.get(7), voxel(3));
+        assert_eq!(*storage.get(4), air);
+    }
+}
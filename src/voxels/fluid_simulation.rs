@@ -0,0 +1,370 @@
+use std::collections::HashSet;
+
+use glam::{const_ivec3, IVec3, UVec3};
+use rayon::prelude::*;
+
+use crate::voxels::voxel_data::{voxel_shapes, VoxelData, MAX_FLUID_LEVEL};
+use crate::voxels::voxel_profile::{VoxelProfileId, VOXEL_PROFILES};
+use crate::voxels::voxel_scene::VoxelScene;
+
+const HORIZONTAL_NEIGHBOURS: [IVec3; 4] = [
+    IVec3::X,
+    const_ivec3!([-1, 0, 0]),
+    IVec3::Z,
+    const_ivec3!([0, 0, -1]),
+];
+
+const ALL_NEIGHBOURS: [IVec3; 6] = [
+    IVec3::X,
+    const_ivec3!([-1, 0, 0]),
+    IVec3::Y,
+    const_ivec3!([0, -1, 0]),
+    IVec3::Z,
+    const_ivec3!([0, 0, -1]),
+];
+
+/// Cellular fluid simulation over the fluid levels stored in a `VoxelScene`.
+///
+/// Only voxels that changed recently (or were explicitly activated) are visited each tick,
+/// in a fixed order, so the same scene and activations always produce the same result.
+pub struct FluidSimulation {
+    active: HashSet<IVec3>,
+}
+
+impl FluidSimulation {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            active: HashSet::new(),
+        }
+    }
+
+    /// Wakes up the voxel at `position` so it is simulated during the next tick.
+    pub fn activate(&mut self, position: IVec3) {
+        self.active.insert(position);
+    }
+
+    /// Wakes up a voxel and its direct neighbours, e.g. after it was edited.
+    pub fn activate_around(&mut self, position: IVec3) {
+        self.active.insert(position);
+        for offset in ALL_NEIGHBOURS {
+            self.active.insert(position + offset);
+        }
+    }
+
+    /// Wakes up every fluid voxel in the scene that is not at rest, e.g. after generating chunks.
+    pub fn activate_unsettled(&mut self, scene: &VoxelScene) {
+        let unsettled = scene
+            .chunks
+            .par_iter()
+            .flat_map_iter(|(_, chunk)| {
+                let origin = chunk.scenespace_pos();
+                let size = chunk.size();
+                (0..size * size * size).filter_map(move |index| {
+                    let local =
+                        UVec3::new(index / (size * size), index / size % size, index % size);
+                    let position = origin + local.as_ivec3();
+                    (is_fluid(chunk.voxel_at(&local)) && can_flow(scene, &position))
+                        .then_some(position)
+                })
+            })
+            .collect::<Vec<IVec3>>();
+
+        for position in unsettled {
+            self.activate(position);
+        }
+    }
+
+    /// Advances the simulation by one step and returns the chunks whose voxels changed.
+    pub fn tick(&mut self, scene: &mut VoxelScene) -> HashSet<IVec3> {
+        let mut active = self.active.drain().collect::<Vec<IVec3>>();
+        // Bottom up, so falling fluid makes room before the fluid above it moves
+        active.sort_unstable_by_key(|position| (position.y, position.z, position.x));
+
+        let mut changed = Vec::new();
+        for position in active {
+            step_voxel(scene, position, &mut changed);
+        }
+
+        let mut changed_chunks = HashSet::new();
+        for position in changed {
+            changed_chunks.insert(scene.chunk_position_of(&position));
+            self.activate_around(position);
+        }
+
+        changed_chunks
+    }
+}
+
+fn step_voxel(scene: &mut VoxelScene, position: IVec3, changed: &mut Vec<IVec3>) {
+    let voxel = match scene.voxel_at(&position) {
+        Some(voxel) if is_fluid(voxel) => *voxel,
+        _ => return,
+    };
+    let profile = voxel.profile;
+    let mut level = voxel.fluid_level;
+
+    // Fall down as far as the voxel below can take
+    let below = position - IVec3::Y;
+    if let Some(below_level) = fluid_level_at(scene, &below, profile) {
+        let amount = level.min(MAX_FLUID_LEVEL - below_level);
+        if amount > 0 {
+            level -= amount;
+            set_fluid_level(scene, &below, profile, below_level + amount);
+            set_fluid_level(scene, &position, profile, level);
+            changed.push(below);
+            changed.push(position);
+        }
+
+        // Fluid that can still fall doesn't spread sideways
+        if below_level + amount < MAX_FLUID_LEVEL || level == 0 {
+            return;
+        }
+    }
+
+    // Spread out one level at a time towards lower neighbours
+    for offset in HORIZONTAL_NEIGHBOURS {
+        if level <= 1 {
+            break;
+        }
+
+        let neighbour = position + offset;
+        if let Some(neighbour_level) = fluid_level_at(scene, &neighbour, profile) {
+            if neighbour_level + 1 < level {
+                level -= 1;
+                set_fluid_level(scene, &neighbour, profile, neighbour_level + 1);
+                set_fluid_level(scene, &position, profile, level);
+                changed.push(neighbour);
+                changed.push(position);
+            }
+        }
+    }
+}
+
+fn can_flow(scene: &VoxelScene, position: &IVec3) -> bool {
+    // Fluid next to a chunk that isn't loaded is blocked, like by any other solid voxel
+    let voxel = match scene.voxel_at(position) {
+        Some(voxel) => voxel,
+        None => return false,
+    };
+    let below = fluid_level_at(scene, &(*position - IVec3::Y), voxel.profile);
+    if below.is_some_and(|level| level < MAX_FLUID_LEVEL) {
+        return true;
+    }
+
+    HORIZONTAL_NEIGHBOURS.iter().any(|offset| {
+        fluid_level_at(scene, &(*position + *offset), voxel.profile)
+            .is_some_and(|level| level + 1 < voxel.fluid_level)
+    })
+}
+
+/// Whether `voxel` holds any fluid that the simulation moves around.
+pub fn is_fluid(voxel: &VoxelData) -> bool {
+    voxel.fluid_level > 0 && VOXEL_PROFILES.get(voxel.profile).fluid
+}
+
+/// The fluid level of a voxel that fluid of `profile` could flow into, `None` if it is blocked.
+fn fluid_level_at(scene: &VoxelScene, position: &IVec3, profile: VoxelProfileId) -> Option<u8> {
+    let voxel = scene.voxel_at(position)?;
+    if voxel.shape == voxel_shapes::EMPTY {
+        Some(0)
+    } else if voxel.profile == profile && is_fluid(voxel) {
+        Some(voxel.fluid_level)
+    } else {
+        None
+    }
+}
+
+pub fn set_fluid_level(
+    scene: &mut VoxelScene,
+    position: &IVec3,
+    profile: VoxelProfileId,
+    level: u8,
+) {
+    if let Some(voxel) = scene.voxel_at_mut(position) {
+        *voxel = if level == 0 {
+            VoxelData {
+                shape: voxel_shapes::EMPTY,
+                profile: 0,
+                fluid_level: 0,
+            }
+        } else {
+            VoxelData {
+                shape: voxel_shapes::ALL,
+                profile,
+                fluid_level: level.min(MAX_FLUID_LEVEL),
+            }
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::voxels::voxel_scene::VoxelChunk;
+
+    const CHUNK_SIZE: u32 = 4;
+
+    /// Two empty chunks side by side along x, with a solid floor at y = 0.
+    fn scene_with_floor() -> VoxelScene {
+        let mut scene = VoxelScene::with_chunk_size(CHUNK_SIZE);
+        for position in [IVec3::ZERO, IVec3::X] {
+            scene
+                .chunks
+                .insert(position, VoxelChunk::new(position, CHUNK_SIZE));
+        }
+
+        let floor = VoxelData {
+            shape: voxel_shapes::ALL,
+            profile: VOXEL_PROFILES.id_of("dirt").unwrap(),
+            fluid_level: 0,
+        };
+        for x in 0..CHUNK_SIZE as i32 * 2 {
+            for z in 0..CHUNK_SIZE as i32 {
+                *scene.voxel_at_mut(&IVec3::new(x, 0, z)).unwrap() = floor;
+            }
+        }
+        scene
+    }
+
+    fn water() -> VoxelProfileId {
+        VOXEL_PROFILES.id_of("water").unwrap()
+    }
+
+    fn pour(scene: &mut VoxelScene, simulation: &mut FluidSimulation, position: IVec3) {
+        set_fluid_level(scene, &position, water(), MAX_FLUID_LEVEL);
+        simulation.activate(position);
+    }
+
+    fn snapshot(scene: &VoxelScene) -> HashMap<IVec3, VoxelData> {
+        let size = CHUNK_SIZE as i32;
+        let mut voxels = HashMap::new();
+        for chunk in scene.chunks.keys() {
+            for x in 0..size {
+                for y in 0..size {
+                    for z in 0..size {
+                        let position = *chunk * size + IVec3::new(x, y, z);
+                        voxels.insert(position, *scene.voxel_at(&position).unwrap());
+                    }
+                }
+            }
+        }
+        voxels
+    }
+
+    fn fluid_level(scene: &VoxelScene, position: IVec3) -> u8 {
+        let voxel = scene.voxel_at(&position).unwrap();
+        if is_fluid(voxel) {
+            voxel.fluid_level
+        } else {
+            0
+        }
+    }
+
+    fn total_fluid(scene: &VoxelScene) -> u32 {
+        snapshot(scene)
+            .values()
+            .filter(|voxel| is_fluid(voxel))
+            .map(|voxel| voxel.fluid_level as u32)
+            .sum()
+    }
+
+    /// Ticks until nothing changes anymore and returns the number of ticks that did something.
+    fn run_until_settled(scene: &mut VoxelScene, simulation: &mut FluidSimulation) -> usize {
+        for ticks in 0..256 {
+            if simulation.tick(scene).is_empty() {
+                return ticks;
+            }
+        }
+        panic!("fluid didn't settle");
+    }
+
+    #[test]
+    fn fluid_falls_and_spreads() {
+        let mut scene = scene_with_floor();
+        let mut simulation = FluidSimulation::new();
+        pour(&mut scene, &mut simulation, IVec3::new(1, 3, 1));
+
+        simulation.tick(&mut scene);
+        assert_eq!(fluid_level(&scene, IVec3::new(1, 3, 1)), 0);
+        assert_eq!(fluid_level(&scene, IVec3::new(1, 2, 1)), MAX_FLUID_LEVEL);
+
+        run_until_settled(&mut scene, &mut simulation);
+        assert!(fluid_level(&scene, IVec3::new(1, 1, 1)) > 0);
+        assert!(fluid_level(&scene, IVec3::new(2, 1, 1)) > 0);
+        assert!(fluid_level(&scene, IVec3::new(1, 1, 2)) > 0);
+        assert_eq!(fluid_level(&scene, IVec3::new(1, 2, 1)), 0);
+    }
+
+    #[test]
+    fn fluid_settles_without_losing_any() {
+        let mut scene = scene_with_floor();
+        let mut simulation = FluidSimulation::new();
+        pour(&mut scene, &mut simulation, IVec3::new(1, 2, 1));
+        pour(&mut scene, &mut simulation, IVec3::new(1, 3, 1));
+
+        run_until_settled(&mut scene, &mut simulation);
+        assert_eq!(total_fluid(&scene), MAX_FLUID_LEVEL as u32 * 2);
+
+        // Nothing is left that could still flow
+        let mut settled = FluidSimulation::new();
+        settled.activate_unsettled(&scene);
+        assert!(settled.tick(&mut scene).is_empty());
+    }
+
+    #[test]
+    fn fluid_is_deterministic() {
+        let run = || {
+            let mut scene = scene_with_floor();
+            let mut simulation = FluidSimulation::new();
+            pour(&mut scene, &mut simulation, IVec3::new(3, 3, 1));
+            pour(&mut scene, &mut simulation, IVec3::new(5, 3, 2));
+            for _ in 0..6 {
+                simulation.tick(&mut scene);
+            }
+            snapshot(&scene)
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn tick_returns_the_changed_chunks() {
+        let mut scene = scene_with_floor();
+        let mut simulation = FluidSimulation::new();
+        // Next to the border between the two chunks, so the fluid crosses it while spreading
+        pour(&mut scene, &mut simulation, IVec3::new(3, 3, 1));
+
+        loop {
+            let before = snapshot(&scene);
+            let changed_chunks = simulation.tick(&mut scene);
+            let after = snapshot(&scene);
+
+            let expected = before
+                .iter()
+                .filter(|(position, voxel)| after[*position] != **voxel)
+                .map(|(position, _)| scene.chunk_position_of(position))
+                .collect::<HashSet<IVec3>>();
+            assert_eq!(changed_chunks, expected);
+
+            if changed_chunks.is_empty() {
+                break;
+            }
+        }
+        assert!(fluid_level(&scene, IVec3::new(4, 1, 1)) > 0);
+    }
+
+    #[test]
+    fn fluid_next_to_an_unloaded_chunk_is_blocked() {
+        let mut scene = scene_with_floor();
+        let mut simulation = FluidSimulation::new();
+        // The chunk at -x isn't loaded
+        pour(&mut scene, &mut simulation, IVec3::new(0, 1, 1));
+
+        simulation.activate_unsettled(&scene);
+        run_until_settled(&mut scene, &mut simulation);
+        assert_eq!(total_fluid(&scene), MAX_FLUID_LEVEL as u32);
+    }
+}
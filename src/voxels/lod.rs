@@ -0,0 +1,424 @@
+use glam::{IVec3, Vec3};
+
+use crate::rendering::mesh::Mesh;
+use crate::rendering::vertex::Vertex;
+use crate::voxels::voxel_data::{voxel_shapes, VoxelData, MAX_FLUID_LEVEL};
+use crate::voxels::voxel_profile::{VoxelProfile, VoxelProfileId, VOXEL_PROFILES};
+use crate::voxels::voxel_scene::VoxelScene;
+
+// Level 0 is full resolution, every level after that halves the resolution again
+pub const MAX_LOD_LEVEL: u32 = 3;
+
+// Distance from the camera in chunks at which levels 1, 2 and 3 start
+pub const LOD_DISTANCES: [f32; MAX_LOD_LEVEL as usize] = [8.0, 16.0, 24.0];
+
+// A coarse cell becomes solid when at least this fraction of its corners is filled
+const DENSITY_THRESHOLD: f32 = 0.5;
+
+const EMPTY_VOXEL: VoxelData = VoxelData {
+    shape: voxel_shapes::EMPTY,
+    profile: 0,
+    fluid_level: 0,
+};
+
+// Direction and corners of every face, wound the same way as the full resolution mesher
+const FACES: [(IVec3, [[f32; 3]; 4]); 6] = [
+    (
+        IVec3::Z,
+        [
+            [1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [0.0, 1.0, 1.0],
+        ],
+    ),
+    (
+        glam::const_ivec3!([0, 0, -1]),
+        [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+        ],
+    ),
+    (
+        IVec3::X,
+        [
+            [1.0, 0.0, 0.0],
+            [1.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 1.0, 1.0],
+        ],
+    ),
+    (
+        glam::const_ivec3!([-1, 0, 0]),
+        [
+            [0.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [0.0, 1.0, 0.0],
+        ],
+    ),
+    (
+        IVec3::Y,
+        [
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+        ],
+    ),
+    (
+        glam::const_ivec3!([0, -1, 0]),
+        [
+            [0.0, 0.0, 1.0],
+            [1.0, 0.0, 1.0],
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+        ],
+    ),
+];
+
+/// The level of detail for a chunk `distance` chunks away from the camera.
+pub fn lod_level_for_distance(distance: f32) -> u32 {
+    LOD_DISTANCES
+        .iter()
+        .filter(|&&threshold| distance >= threshold)
+        .count() as u32
+}
+
+/// The first chunk of the group that the chunk at `position` is meshed with at `level`.
+/// Groups are `2^level` chunks along every axis and aligned to multiples of that.
+pub fn lod_group_min(position: IVec3, level: u32) -> IVec3 {
+    let size = 1 << level;
+    IVec3::new(
+        position.x.div_floor(size),
+        position.y.div_floor(size),
+        position.z.div_floor(size),
+    ) * size
+}
+
+/// The level of detail for the chunk at `position`, with `eye` measured in chunks.
+/// Levels are picked by the distance to the center of a whole group, coarsest first,
+/// so every chunk of a group ends up at the same level.
+pub fn lod_level_for_chunk(position: IVec3, eye: Vec3) -> u32 {
+    for level in (1..=MAX_LOD_LEVEL).rev() {
+        let size = (1 << level) as f32;
+        let center = lod_group_min(position, level).as_vec3() + size / 2.0;
+        if lod_level_for_distance(center.distance(eye)) >= level {
+            return level;
+        }
+    }
+    0
+}
+
+/// A group of chunks at reduced resolution, meshed as one. Every cell stands in for a cube of
+/// `2^level` voxels, so a group of `2^level` chunks has as many cells as a single chunk has voxels.
+pub struct DownsampledChunk<'a> {
+    scene: &'a VoxelScene,
+    level: u32,
+    // Edge length of a cell in voxels
+    cell_size: i32,
+    // Scene space position of the first voxel of the group
+    origin: IVec3,
+    // Edge length of the group in voxels, cells on the far edges are cut off if it doesn't divide
+    extent: i32,
+    cell_count: i32,
+    cells: Vec<VoxelData>,
+}
+
+impl<'a> DownsampledChunk<'a> {
+    /// Samples the `group_size` chunks along every axis starting at the chunk `group_min`.
+    pub fn new(scene: &'a VoxelScene, group_min: IVec3, group_size: i32, level: u32) -> Self {
+        let chunk_size = scene.chunk_size() as i32;
+        let extent = group_size * chunk_size;
+        let cell_size = (1 << level).min(extent);
+        let cell_count = extent.div_ceil(cell_size);
+        let origin = group_min * chunk_size;
+
+        let mut cells = Vec::with_capacity((cell_count * cell_count * cell_count) as usize);
+        for x in 0..cell_count {
+            for y in 0..cell_count {
+                for z in 0..cell_count {
+                    let min = IVec3::new(x, y, z) * cell_size;
+                    let max = (min + cell_size).min(IVec3::splat(extent));
+                    cells.push(sample_cell(scene, origin + min, origin + max));
+                }
+            }
+        }
+
+        Self {
+            scene,
+            level,
+            cell_size,
+            origin,
+            extent,
+            cell_count,
+            cells,
+        }
+    }
+
+    fn cell_at(&self, position: IVec3) -> Option<&VoxelData> {
+        if position.cmplt(IVec3::ZERO).any() || position.cmpge(IVec3::splat(self.cell_count)).any()
+        {
+            return None;
+        }
+        let index = (position.x * self.cell_count + position.y) * self.cell_count + position.z;
+        Some(&self.cells[index as usize])
+    }
+
+    /// Whether the face of `cell` towards the cell at `position` is hidden. Cells beyond the
+    /// group are sampled from the scene at this group's resolution, so faces towards them are
+    /// only emitted where the surface actually is. Where the neighbouring chunk is meshed at a
+    /// coarser level the face is always emitted, as a skirt that covers the gap between the
+    /// two surfaces. Finer neighbours do the same towards this group.
+    fn hides_face(&self, cell: &VoxelData, position: IVec3) -> bool {
+        let neighbour = match self.cell_at(position) {
+            Some(neighbour) => *neighbour,
+            None => {
+                let min = self.origin + position * self.cell_size;
+                match self.scene.chunk_at(&min) {
+                    Some(chunk) if chunk.lod_level() <= self.level => {
+                        sample_cell(self.scene, min, min + self.cell_size)
+                    }
+                    // A skirt towards coarser chunks, and nothing can hide it where none is loaded
+                    _ => return false,
+                }
+            }
+        };
+
+        neighbour.shape != voxel_shapes::EMPTY
+            && (neighbour.profile == cell.profile
+                || !VOXEL_PROFILES.get(neighbour.profile).transparent)
+    }
+
+    /// Meshes every cell as a cube, returning the opaque and the transparent mesh.
+    /// Vertices are relative to the first voxel of the group.
+    pub fn generate_meshes(&self) -> (Mesh, Mesh) {
+        let mut mesh = Mesh::new();
+        let mut transparent_mesh = Mesh::new();
+
+        for x in 0..self.cell_count {
+            for y in 0..self.cell_count {
+                for z in 0..self.cell_count {
+                    let position = IVec3::new(x, y, z);
+                    let cell = self.cell_at(position).unwrap();
+                    if cell.shape == voxel_shapes::EMPTY {
+                        continue;
+                    }
+
+                    let profile = VOXEL_PROFILES.get(cell.profile);
+                    let target = if profile.transparent {
+                        &mut transparent_mesh
+                    } else {
+                        &mut mesh
+                    };
+
+                    for (direction, corners) in FACES.iter() {
+                        if !self.hides_face(cell, position + *direction) {
+                            self.build_quad(target, position, corners, *direction, profile);
+                        }
+                    }
+                }
+            }
+        }
+
+        (mesh, transparent_mesh)
+    }
+
+    fn build_quad(
+        &self,
+        mesh: &mut Mesh,
+        cell: IVec3,
+        corners: &[[f32; 3]; 4],
+        direction: IVec3,
+        profile: &VoxelProfile,
+    ) {
+        let min = cell * self.cell_size;
+        // Cells on the far edges of the group may be cut short
+        let extent = ((cell + 1) * self.cell_size)
+            .min(IVec3::splat(self.extent))
+            .as_vec3()
+            - min.as_vec3();
+        let min = min.as_vec3();
+
+        let offset = mesh.vertices.len() as u32;
+        mesh.indices.extend_from_slice(&[
+            offset,
+            offset + 2,
+            offset + 1,
+            offset + 1,
+            offset + 2,
+            offset + 3,
+        ]);
+
+        let normal = direction.as_vec3().into();
+        let tangent = Vertex::quad_tangent(corners, normal);
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]];
+        for (corner, uv) in corners.iter().zip(uvs) {
+            mesh.vertices.push(Vertex {
+                position: [
+                    min.x + corner[0] * extent.x,
+                    min.y + corner[1] * extent.y,
+                    min.z + corner[2] * extent.z,
+                ],
+                color: profile.color,
+                normal,
+                uv,
+                material: profile.material_id as u32,
+                tangent,
+            });
+        }
+    }
+}
+
+/// Picks the voxel that represents the scene space box from `min` (inclusive) to `max`
+/// (exclusive). The cell is solid if enough of its corners are filled, and takes the profile
+/// that fills the most corners. Voxels outside of the loaded chunks count as empty.
+fn sample_cell(scene: &VoxelScene, min: IVec3, max: IVec3) -> VoxelData {
+    let mut weights: Vec<(VoxelProfileId, u32)> = Vec::new();
+    let mut filled = 0;
+    for x in min.x..max.x {
+        for y in min.y..max.y {
+            for z in min.z..max.z {
+                let voxel = match scene.voxel_at(&IVec3::new(x, y, z)) {
+                    Some(voxel) => voxel,
+                    None => continue,
+                };
+                let corners = voxel.shape.corner_count();
+                if corners == 0 {
+                    continue;
+                }
+
+                filled += corners;
+                match weights
+                    .iter_mut()
+                    .find(|(profile, _)| *profile == voxel.profile)
+                {
+                    Some((_, weight)) => *weight += corners,
+                    None => weights.push((voxel.profile, corners)),
+                }
+            }
+        }
+    }
+
+    let size = max - min;
+    let total = (size.x * size.y * size.z * 8) as u32;
+    if (filled as f32) < total as f32 * DENSITY_THRESHOLD {
+        return EMPTY_VOXEL;
+    }
+
+    let (profile, _) = weights
+        .into_iter()
+        .max_by_key(|(_, weight)| *weight)
+        .unwrap();
+    VoxelData {
+        shape: voxel_shapes::ALL,
+        profile,
+        fluid_level: if VOXEL_PROFILES.get(profile).fluid {
+            MAX_FLUID_LEVEL
+        } else {
+            0
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::voxel_scene::VoxelChunk;
+
+    const CHUNK_SIZE: u32 = 4;
+
+    #[test]
+    fn every_chunk_of_a_group_gets_the_same_level() {
+        let eye = Vec3::new(3.5, 1.5, -2.5);
+        for x in -40..40 {
+            for z in -40..40 {
+                let position = IVec3::new(x, 0, z);
+                let level = lod_level_for_chunk(position, eye);
+                let min = lod_group_min(position, level);
+                for offset in [IVec3::ZERO, IVec3::ONE * ((1 << level) - 1)] {
+                    assert_eq!(
+                        lod_level_for_chunk(min + offset, eye),
+                        level,
+                        "{}",
+                        position
+                    );
+                }
+            }
+        }
+    }
+
+    /// Chunks 0 to 3 along x and 0 to 1 along y and z, solid below y = 4.
+    fn scene() -> VoxelScene {
+        let mut scene = VoxelScene::with_chunk_size(CHUNK_SIZE);
+        for x in 0..4 {
+            for y in 0..2 {
+                for z in 0..2 {
+                    let position = IVec3::new(x, y, z);
+                    let mut chunk = VoxelChunk::new(position, CHUNK_SIZE);
+                    if y == 0 {
+                        for index in 0..CHUNK_SIZE.pow(3) {
+                            let local = glam::UVec3::new(
+                                index / (CHUNK_SIZE * CHUNK_SIZE),
+                                index / CHUNK_SIZE % CHUNK_SIZE,
+                                index % CHUNK_SIZE,
+                            );
+                            *chunk.voxel_at_mut(&local) = VoxelData {
+                                shape: voxel_shapes::ALL,
+                                profile: 0,
+                                fluid_level: 0,
+                            };
+                        }
+                    }
+                    scene.chunks.insert(position, chunk);
+                }
+            }
+        }
+        scene
+    }
+
+    /// Number of faces of the first group that lie on its +x border, with the chunks past
+    /// that border at `neighbour_level`.
+    fn border_faces(neighbour_level: u32) -> usize {
+        let mut scene = scene();
+        for (position, chunk) in scene.chunks.iter_mut() {
+            chunk.set_lod_level(if position.x < 2 { 1 } else { neighbour_level });
+        }
+
+        let (mesh, _) = DownsampledChunk::new(&scene, IVec3::ZERO, 2, 1).generate_meshes();
+        let border = (CHUNK_SIZE * 2) as f32;
+        mesh.vertices
+            .iter()
+            .filter(|vertex| vertex.normal == [1.0, 0.0, 0.0] && vertex.position[0] == border)
+            .count()
+            / 4
+    }
+
+    #[test]
+    fn group_covers_all_of_its_chunks() {
+        let mut scene = scene();
+        for chunk in scene.chunks.values_mut() {
+            chunk.set_lod_level(1);
+        }
+
+        let (mesh, _) = DownsampledChunk::new(&scene, IVec3::ZERO, 2, 1).generate_meshes();
+        let max = mesh
+            .vertices
+            .iter()
+            .map(|vertex| Vec3::from(vertex.position))
+            .reduce(Vec3::max)
+            .unwrap();
+        assert_eq!(max, Vec3::new(8.0, 4.0, 8.0));
+    }
+
+    #[test]
+    fn skirts_only_face_coarser_chunks() {
+        // Two cells high and four cells deep, at two voxels per cell
+        let skirt = 2 * 4;
+        assert_eq!(border_faces(2), skirt);
+        assert_eq!(border_faces(1), 0);
+        assert_eq!(border_faces(0), 0);
+    }
+}
@@ -1,2 +1,10 @@
+pub mod brush;
+pub mod chunk_storage;
+pub mod fluid_simulation;
+pub mod lod;
+pub mod octree;
+pub mod shape_faces;
 pub mod voxel_data;
+pub mod voxel_edit;
+pub mod voxel_profile;
 pub mod voxel_scene;
@@ -0,0 +1,429 @@
+use glam::IVec3;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::voxels::voxel_data::{voxel_shapes, VoxelData};
+use crate::voxels::voxel_scene::{VoxelChunk, VoxelScene};
+
+// Anything outside of the loaded chunks is treated as air
+const EMPTY_VOXEL: VoxelData = VoxelData {
+    shape: voxel_shapes::EMPTY,
+    profile: 0,
+    fluid_level: 0,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum OctreeNode {
+    // The whole cube of this node holds the same voxel
+    Uniform(VoxelData),
+    // Children are ordered by their offset, x being the lowest bit and z the highest
+    Branch(Box<[OctreeNode; 8]>),
+}
+
+impl OctreeNode {
+    /// Merges the children into a single node if they are all the same uniform voxel.
+    fn collapsed(children: [OctreeNode; 8]) -> OctreeNode {
+        match uniform_voxel(&children) {
+            Some(voxel) => OctreeNode::Uniform(voxel),
+            None => OctreeNode::Branch(Box::new(children)),
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            OctreeNode::Uniform(_) => 1,
+            OctreeNode::Branch(children) => {
+                1 + children.iter().map(|child| child.count()).sum::<usize>()
+            }
+        }
+    }
+}
+
+/// A cube of uniform voxels returned by region queries.
+#[derive(Clone, Copy, Debug)]
+pub struct OctreeBlock {
+    pub min: IVec3,
+    pub size: u32,
+    pub voxel: VoxelData,
+}
+
+/// Sparse voxel octree covering a power of two sized cube of the scene.
+/// Uniform regions are stored as a single node no matter how large they are,
+/// which makes it a lot smaller than the chunks for storing or sending far away terrain.
+/// It is a snapshot, later edits to the scene are only reflected through `set_voxel`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct VoxelOctree {
+    origin: IVec3,
+    size: u32,
+    root: OctreeNode,
+}
+
+impl VoxelOctree {
+    /// Builds an octree covering every loaded chunk in the scene.
+    pub fn from_scene(scene: &VoxelScene) -> Self {
+        let chunks = scene.chunks.values().collect::<Vec<&VoxelChunk>>();
+        if chunks.is_empty() {
+            return Self {
+                origin: IVec3::ZERO,
+                size: 1,
+                root: OctreeNode::Uniform(EMPTY_VOXEL),
+            };
+        }
+
+        let chunk_size = scene.chunk_size() as i32;
+        let min = chunks
+            .iter()
+            .map(|chunk| chunk.scenespace_pos())
+            .reduce(IVec3::min)
+            .unwrap();
+        let max = chunks
+            .iter()
+            .map(|chunk| chunk.scenespace_pos() + chunk_size)
+            .reduce(IVec3::max)
+            .unwrap();
+        let size = ((max - min).max_element() as u32).next_power_of_two();
+
+        Self {
+            origin: min,
+            size,
+            root: build_node(min, size as i32, &chunks),
+        }
+    }
+
+    pub fn origin(&self) -> IVec3 {
+        self.origin
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn root(&self) -> &OctreeNode {
+        &self.root
+    }
+
+    pub fn contains(&self, position: &IVec3) -> bool {
+        let local = *position - self.origin;
+        local.cmpge(IVec3::ZERO).all() && local.cmplt(IVec3::splat(self.size as i32)).all()
+    }
+
+    pub fn voxel_at(&self, position: &IVec3) -> Option<&VoxelData> {
+        if !self.contains(position) {
+            return None;
+        }
+
+        let mut node = &self.root;
+        let mut min = self.origin;
+        let mut size = self.size as i32;
+        loop {
+            match node {
+                OctreeNode::Uniform(voxel) => return Some(voxel),
+                OctreeNode::Branch(children) => {
+                    size /= 2;
+                    let index = child_index(*position, min, size);
+                    min += child_offset(index) * size;
+                    node = &children[index];
+                }
+            }
+        }
+    }
+
+    /// Every uniform block that overlaps the box from `min` to `max`, both inclusive.
+    /// Blocks are returned whole, so they can reach outside of the box.
+    pub fn region(&self, min: IVec3, max: IVec3) -> Vec<OctreeBlock> {
+        let mut blocks = Vec::new();
+        collect_region(
+            &self.root,
+            self.origin,
+            self.size as i32,
+            min,
+            max,
+            &mut blocks,
+        );
+        blocks
+    }
+
+    /// Writes a single voxel, splitting uniform nodes on the way down and collapsing them
+    /// again on the way up. Returns false if the position is outside the octree.
+    pub fn set_voxel(&mut self, position: &IVec3, voxel: VoxelData) -> bool {
+        if !self.contains(position) {
+            return false;
+        }
+
+        set_node(
+            &mut self.root,
+            self.origin,
+            self.size as i32,
+            *position,
+            voxel,
+        );
+        true
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.root.count()
+    }
+
+    /// Approximate heap and inline size in bytes.
+    pub fn memory_usage(&self) -> usize {
+        // Every node but the root lives in the boxed child array of its parent
+        std::mem::size_of::<Self>() + (self.node_count() - 1) * std::mem::size_of::<OctreeNode>()
+    }
+
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::deserialize(bytes)
+    }
+}
+
+fn uniform_voxel(children: &[OctreeNode; 8]) -> Option<VoxelData> {
+    match &children[0] {
+        OctreeNode::Uniform(first)
+            if children
+                .iter()
+                .all(|child| matches!(child, OctreeNode::Uniform(voxel) if voxel == first)) =>
+        {
+            Some(*first)
+        }
+        _ => None,
+    }
+}
+
+fn child_offset(index: usize) -> IVec3 {
+    IVec3::new(
+        index as i32 & 1,
+        (index as i32 >> 1) & 1,
+        (index as i32 >> 2) & 1,
+    )
+}
+
+fn child_index(position: IVec3, min: IVec3, half_size: i32) -> usize {
+    let offset = (position - min).cmpge(IVec3::splat(half_size));
+    offset.bitmask() as usize
+}
+
+fn overlaps(min_a: IVec3, size_a: i32, min_b: IVec3, size_b: i32) -> bool {
+    (min_a.cmplt(min_b + size_b) & min_b.cmplt(min_a + size_a)).all()
+}
+
+/// `chunks` holds only the chunks that overlap this node.
+fn build_node(min: IVec3, size: i32, chunks: &[&VoxelChunk]) -> OctreeNode {
+    if chunks.is_empty() {
+        return OctreeNode::Uniform(EMPTY_VOXEL);
+    }
+
+    if size == 1 {
+        let voxel = chunks[0].voxel_scenespace_at(&min).unwrap();
+        return OctreeNode::Uniform(*voxel);
+    }
+
+    let half_size = size / 2;
+    let children = (0..8)
+        .into_par_iter()
+        .map(|index| {
+            let child_min = min + child_offset(index) * half_size;
+            let overlapping = chunks
+                .iter()
+                .filter(|chunk| {
+                    overlaps(
+                        chunk.scenespace_pos(),
+                        chunk.size() as i32,
+                        child_min,
+                        half_size,
+                    )
+                })
+                .copied()
+                .collect::<Vec<&VoxelChunk>>();
+            build_node(child_min, half_size, &overlapping)
+        })
+        .collect::<Vec<OctreeNode>>();
+
+    OctreeNode::collapsed(children.try_into().unwrap())
+}
+
+fn collect_region(
+    node: &OctreeNode,
+    node_min: IVec3,
+    size: i32,
+    min: IVec3,
+    max: IVec3,
+    blocks: &mut Vec<OctreeBlock>,
+) {
+    if (node_min + size).cmple(min).any() || node_min.cmpgt(max).any() {
+        return;
+    }
+
+    match node {
+        OctreeNode::Uniform(voxel) => blocks.push(OctreeBlock {
+            min: node_min,
+            size: size as u32,
+            voxel: *voxel,
+        }),
+        OctreeNode::Branch(children) => {
+            let half_size = size / 2;
+            for (index, child) in children.iter().enumerate() {
+                let child_min = node_min + child_offset(index) * half_size;
+                collect_region(child, child_min, half_size, min, max, blocks);
+            }
+        }
+    }
+}
+
+fn set_node(node: &mut OctreeNode, min: IVec3, size: i32, position: IVec3, voxel: VoxelData) {
+    if size == 1 {
+        *node = OctreeNode::Uniform(voxel);
+        return;
+    }
+
+    if let OctreeNode::Uniform(current) = node {
+        if *current == voxel {
+            return;
+        }
+        *node = OctreeNode::Branch(Box::new(std::array::from_fn(|_| {
+            OctreeNode::Uniform(*current)
+        })));
+    }
+
+    if let OctreeNode::Branch(children) = node {
+        let half_size = size / 2;
+        let index = child_index(position, min, half_size);
+        let child_min = min + child_offset(index) * half_size;
+        set_node(&mut children[index], child_min, half_size, position, voxel);
+
+        if let Some(voxel) = uniform_voxel(children) {
+            *node = OctreeNode::Uniform(voxel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::voxel_profile::VOXEL_PROFILES;
+
+    const CHUNK_SIZE: u32 = 4;
+
+    fn dirt() -> VoxelData {
+        VoxelData {
+            shape: voxel_shapes::ALL,
+            profile: VOXEL_PROFILES.id_of("dirt").unwrap(),
+            fluid_level: 0,
+        }
+    }
+
+    /// Two chunks side by side along x with a floor at y = 0 and a pillar on top of it.
+    fn scene() -> VoxelScene {
+        let mut scene = VoxelScene::with_chunk_size(CHUNK_SIZE);
+        for position in [IVec3::ZERO, IVec3::X] {
+            scene
+                .chunks
+                .insert(position, VoxelChunk::new(position, CHUNK_SIZE));
+        }
+
+        for x in 0..CHUNK_SIZE as i32 * 2 {
+            for z in 0..CHUNK_SIZE as i32 {
+                *scene.voxel_at_mut(&IVec3::new(x, 0, z)).unwrap() = dirt();
+            }
+        }
+        for y in 1..3 {
+            *scene.voxel_at_mut(&IVec3::new(5, y, 2)).unwrap() = dirt();
+        }
+        scene
+    }
+
+    fn positions(octree: &VoxelOctree) -> impl Iterator<Item = IVec3> {
+        let min = octree.origin() - 1;
+        let max = octree.origin() + octree.size() as i32;
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| IVec3::new(x, y, z)))
+        })
+    }
+
+    #[test]
+    fn point_queries_match_the_scene() {
+        let scene = scene();
+        let octree = VoxelOctree::from_scene(&scene);
+        assert_eq!(octree.size(), CHUNK_SIZE * 2);
+
+        for position in positions(&octree) {
+            let expected = if octree.contains(&position) {
+                Some(scene.voxel_at(&position).copied().unwrap_or(EMPTY_VOXEL))
+            } else {
+                None
+            };
+            assert_eq!(
+                octree.voxel_at(&position).copied(),
+                expected,
+                "{}",
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn region_queries_cover_the_box() {
+        let scene = scene();
+        let octree = VoxelOctree::from_scene(&scene);
+        let (min, max) = (IVec3::new(3, 0, 1), IVec3::new(6, 2, 2));
+        let blocks = octree.region(min, max);
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                for z in min.z..=max.z {
+                    let position = IVec3::new(x, y, z);
+                    let covering = blocks
+                        .iter()
+                        .filter(|block| {
+                            let local = position - block.min;
+                            local.cmpge(IVec3::ZERO).all()
+                                && local.cmplt(IVec3::splat(block.size as i32)).all()
+                        })
+                        .collect::<Vec<_>>();
+                    assert_eq!(covering.len(), 1, "{}", position);
+                    assert_eq!(covering[0].voxel, *scene.voxel_at(&position).unwrap());
+                }
+            }
+        }
+
+        // Nothing far outside of the box is returned
+        for block in blocks {
+            assert!((block.min + block.size as i32).cmpgt(min).all());
+            assert!(block.min.cmple(max).all());
+        }
+    }
+
+    #[test]
+    fn uniform_nodes_collapse() {
+        let mut scene = VoxelScene::with_chunk_size(CHUNK_SIZE);
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO, CHUNK_SIZE));
+        let mut octree = VoxelOctree::from_scene(&scene);
+        assert_eq!(octree.node_count(), 1);
+
+        let position = IVec3::new(1, 2, 3);
+        assert!(octree.set_voxel(&position, dirt()));
+        assert!(octree.node_count() > 1);
+        assert_eq!(*octree.voxel_at(&position).unwrap(), dirt());
+
+        assert!(octree.set_voxel(&position, EMPTY_VOXEL));
+        assert_eq!(octree.node_count(), 1);
+        assert!(!octree.set_voxel(&IVec3::splat(-1), dirt()));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let octree = VoxelOctree::from_scene(&scene());
+        let restored = VoxelOctree::from_bytes(&octree.to_bytes().unwrap()).unwrap();
+
+        assert_eq!(restored.origin(), octree.origin());
+        assert_eq!(restored.size(), octree.size());
+        assert_eq!(restored.node_count(), octree.node_count());
+        for position in positions(&octree) {
+            assert_eq!(restored.voxel_at(&position), octree.voxel_at(&position));
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use glam::{IVec3, Vec3};
+use once_cell::sync::Lazy;
+
+use crate::voxels::voxel_data::{voxel_shapes, VoxelShape};
+
+/// A side of the voxel cube, with the UV layout the full cube faces on it are textured with.
+struct Side {
+    // Offset to the neighbour on this side
+    offset: IVec3,
+    // Corners the neighbour needs to have filled to hide faces on this side
+    requirement: VoxelShape,
+    // UV (0, 0) and the directions U and V increase in, see `generate_faces`
+    uv_origin: Vec3,
+    u: Vec3,
+    v: Vec3,
+}
+
+const SIDES: [Side; 6] = [
+    // North
+    Side {
+        offset: IVec3::Z,
+        requirement: voxel_shapes::SOUTH,
+        uv_origin: glam::const_vec3!([1.0, 0.0, 1.0]),
+        u: glam::const_vec3!([-1.0, 0.0, 0.0]),
+        v: Vec3::Y,
+    },
+    // South
+    Side {
+        offset: glam::const_ivec3!([0, 0, -1]),
+        requirement: voxel_shapes::NORTH,
+        uv_origin: Vec3::ZERO,
+        u: Vec3::X,
+        v: Vec3::Y,
+    },
+    // East
+    Side {
+        offset: IVec3::X,
+        requirement: voxel_shapes::WEST,
+        uv_origin: Vec3::X,
+        u: Vec3::Z,
+        v: Vec3::Y,
+    },
+    // West
+    Side {
+        offset: glam::const_ivec3!([-1, 0, 0]),
+        requirement: voxel_shapes::EAST,
+        uv_origin: Vec3::Z,
+        u: glam::const_vec3!([0.0, 0.0, -1.0]),
+        v: Vec3::Y,
+    },
+    // Top
+    Side {
+        offset: IVec3::Y,
+        requirement: voxel_shapes::BOTTOM,
+        uv_origin: Vec3::Y,
+        u: Vec3::X,
+        v: Vec3::Z,
+    },
+    // Bottom
+    Side {
+        offset: glam::const_ivec3!([0, -1, 0]),
+        requirement: voxel_shapes::TOP,
+        uv_origin: Vec3::Z,
+        u: Vec3::X,
+        v: glam::const_vec3!([0.0, 0.0, -1.0]),
+    },
+];
+
+const EPSILON: f32 = 1e-4;
+
+/// A flat face of the solid spanned by the filled corners of a voxel.
+pub struct ShapeFace {
+    // Voxel space positions and their UVs, counter-clockwise around `normal`. Three or four
+    pub corners: Vec<([f32; 3], [f32; 2])>,
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    // Offset to the neighbour and the corners it needs to hide this face, for faces that lie
+    // on a side of the voxel. Faces cutting through the voxel are always visible
+    pub side: Option<(IVec3, VoxelShape)>,
+}
+
+// Faces of every possible shape, indexed by its corner bits
+static SHAPE_FACES: Lazy<Vec<Vec<ShapeFace>>> = Lazy::new(|| {
+    (0..=u8::MAX)
+        .map(|bits| hull_faces(VoxelShape::from_bits(bits)))
+        .collect()
+});
+
+/// The faces of the convex solid spanned by the filled corners of `shape`. A full shape gives
+/// the six faces of the cube, shapes with fewer than three corners have no faces at all, and
+/// shapes whose corners are all in one plane give a face on either side of that plane.
+pub fn shape_faces(shape: VoxelShape) -> &'static [ShapeFace] {
+    &SHAPE_FACES[shape.bits() as usize]
+}
+
+fn hull_faces(shape: VoxelShape) -> Vec<ShapeFace> {
+    let mut points = Vec::new();
+    for x in 0..2 {
+        for y in 0..2 {
+            for z in 0..2 {
+                if shape.contains(VoxelShape::corner(x, y, z)) {
+                    points.push(Vec3::new(x as f32, y as f32, z as f32));
+                }
+            }
+        }
+    }
+
+    // Every plane through three corners that has no corner in front of it bounds the solid
+    let mut normals: Vec<Vec3> = Vec::new();
+    for i in 0..points.len() {
+        for j in i + 1..points.len() {
+            for k in j + 1..points.len() {
+                let normal = (points[j] - points[i]).cross(points[k] - points[i]);
+                if normal.length_squared() < EPSILON {
+                    continue;
+                }
+                let normal = normal.normalize();
+
+                let distances = points.iter().map(|point| normal.dot(*point - points[i]));
+                let behind = distances.clone().all(|distance| distance < EPSILON);
+                let in_front = distances.clone().all(|distance| distance > -EPSILON);
+                for (bounds, normal) in [(behind, normal), (in_front, -normal)] {
+                    if bounds && !normals.iter().any(|n| n.abs_diff_eq(normal, EPSILON)) {
+                        normals.push(normal);
+                    }
+                }
+            }
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|normal| hull_face(&points, normal))
+        .collect()
+}
+
+/// The face of the solid spanned by `points` that faces along `normal`.
+fn hull_face(points: &[Vec3], normal: Vec3) -> ShapeFace {
+    let plane = points
+        .iter()
+        .map(|point| normal.dot(*point))
+        .fold(f32::MIN, f32::max);
+    let mut corners = points
+        .iter()
+        .copied()
+        .filter(|point| (normal.dot(*point) - plane).abs() < EPSILON)
+        .collect::<Vec<Vec3>>();
+
+    // Sort the corners by their angle around the center, which winds them counter-clockwise
+    let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+    let u = (corners[0] - center).normalize();
+    let v = normal.cross(u);
+    let angle = |point: &Vec3| (*point - center).dot(v).atan2((*point - center).dot(u));
+    corners.sort_by(|a, b| angle(a).partial_cmp(&angle(b)).unwrap());
+
+    // Faces are textured like the cube face they are closest to, so they line up with it
+    let side = SIDES
+        .iter()
+        .max_by(|a, b| {
+            let a = normal.dot(a.offset.as_vec3());
+            let b = normal.dot(b.offset.as_vec3());
+            a.partial_cmp(&b).unwrap()
+        })
+        .unwrap();
+    let tangent = (side.u - normal * normal.dot(side.u)).normalize();
+    let bitangent = side.v - normal * normal.dot(side.v);
+    let handedness = normal.cross(tangent).dot(bitangent).signum();
+
+    // Only faces on the side itself can be hidden by the neighbour there
+    let on_side = normal.abs_diff_eq(side.offset.as_vec3(), EPSILON)
+        && (plane - normal.dot(Vec3::splat(0.5)) - 0.5).abs() < EPSILON;
+
+    ShapeFace {
+        corners: corners
+            .iter()
+            .map(|corner| {
+                let uv_offset = *corner - side.uv_origin;
+                (
+                    (*corner).into(),
+                    [uv_offset.dot(side.u), uv_offset.dot(side.v)],
+                )
+            })
+            .collect(),
+        normal: normal.into(),
+        tangent: tangent.extend(handedness).into(),
+        side: on_side.then_some((side.offset, side.requirement)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corners(face: &ShapeFace) -> Vec<Vec3> {
+        face.corners
+            .iter()
+            .map(|(position, _)| Vec3::from(*position))
+            .collect()
+    }
+
+    #[test]
+    fn full_shape_is_a_cube() {
+        let faces = shape_faces(voxel_shapes::ALL);
+        assert_eq!(faces.len(), 6);
+        for face in faces {
+            assert_eq!(face.corners.len(), 4);
+            assert!(face.side.is_some());
+        }
+    }
+
+    #[test]
+    fn faces_are_wound_counter_clockwise() {
+        for bits in 0..=u8::MAX {
+            for face in shape_faces(VoxelShape::from_bits(bits)) {
+                let corners = corners(face);
+                let winding = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+                assert!(
+                    winding.dot(Vec3::from(face.normal)) > 0.0,
+                    "shape {:08b}",
+                    bits
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn missing_corner_is_cut_off() {
+        let shape = voxel_shapes::ALL.remove(voxel_shapes::TOP_NORTH_EAST);
+        let faces = shape_faces(shape);
+
+        // Three full sides, three sides with a corner missing and the slope between them
+        assert_eq!(faces.len(), 7);
+        let slopes = faces
+            .iter()
+            .filter(|face| face.side.is_none())
+            .collect::<Vec<_>>();
+        assert_eq!(slopes.len(), 1);
+        assert_eq!(slopes[0].corners.len(), 3);
+        assert!(Vec3::from(slopes[0].normal).abs_diff_eq(Vec3::ONE.normalize(), EPSILON));
+    }
+
+    #[test]
+    fn flat_shape_has_a_face_on_either_side() {
+        let faces = shape_faces(voxel_shapes::BOTTOM);
+        assert_eq!(faces.len(), 2);
+
+        // The downward face lies on the bottom side and can be hidden, the upward one can't
+        let bottom = faces.iter().find(|face| face.normal[1] < 0.0).unwrap();
+        let top = faces.iter().find(|face| face.normal[1] > 0.0).unwrap();
+        assert_eq!(bottom.side, Some((-IVec3::Y, voxel_shapes::TOP)));
+        assert_eq!(top.side, None);
+    }
+
+    #[test]
+    fn too_few_corners_have_no_faces() {
+        assert!(shape_faces(voxel_shapes::EMPTY).is_empty());
+        assert!(shape_faces(voxel_shapes::BOTTOM_WEST).is_empty());
+    }
+}
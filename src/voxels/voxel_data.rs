@@ -1,4 +1,8 @@
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, Default)]
+use serde::{Deserialize, Serialize};
+
+use super::voxel_profile::VoxelProfileId;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, Default, Serialize, Deserialize)]
 pub struct VoxelShape {
     data: u8,
 }
@@ -64,9 +68,47 @@ impl VoxelShape {
             data: self.data & shape.data,
         }
     }
+
+    pub fn remove(self, shape: VoxelShape) -> VoxelShape {
+        VoxelShape {
+            data: self.data & !shape.data,
+        }
+    }
+
+    /// The raw corner mask, one bit per corner as laid out in `voxel_shapes`.
+    pub fn bits(&self) -> u8 {
+        self.data
+    }
+
+    pub fn from_bits(bits: u8) -> VoxelShape {
+        VoxelShape { data: bits }
+    }
+
+    pub fn corner_count(&self) -> u32 {
+        self.data.count_ones()
+    }
+
+    /// The single corner at local offset (x, y, z), where each component is 0 or 1.
+    pub fn corner(x: u32, y: u32, z: u32) -> VoxelShape {
+        // Bottom corners go south west, north west, north east, south east. Top corners repeat that
+        let index = match (x, z) {
+            (0, 0) => 0,
+            (0, _) => 1,
+            (_, 0) => 3,
+            _ => 2,
+        } + if y == 0 { 0 } else { 4 };
+
+        VoxelShape { data: 1 << index }
+    }
 }
 
-#[derive(Clone, Copy)]
+// Fill level of a completely filled fluid voxel
+pub const MAX_FLUID_LEVEL: u8 = 8;
+
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct VoxelData {
     pub shape: VoxelShape,
+    pub profile: VoxelProfileId,
+    // Only meaningful for voxels with a fluid profile, 0 for everything else
+    pub fluid_level: u8,
 }
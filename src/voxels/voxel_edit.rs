@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use glam::IVec3;
+
+use crate::voxels::voxel_data::{VoxelData, VoxelShape};
+use crate::voxels::voxel_scene::VoxelScene;
+
+// Number of transactions kept around for undo
+pub const DEFAULT_HISTORY_DEPTH: usize = 128;
+
+/// A single voxel going from `before` to `after`.
+#[derive(Copy, Clone, Debug)]
+pub struct VoxelChange {
+    pub position: IVec3,
+    pub before: VoxelData,
+    pub after: VoxelData,
+}
+
+/// A batch of voxel changes that is applied, undone and redone as one unit.
+#[derive(Clone, Default)]
+pub struct EditTransaction {
+    changes: Vec<VoxelChange>,
+    // Position -> index into `changes`, so repeated edits of one voxel collapse into one change
+    indices: HashMap<IVec3, usize>,
+}
+
+impl EditTransaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, position: IVec3, before: VoxelData, after: VoxelData) {
+        match self.indices.get(&position) {
+            Some(&index) => self.changes[index].after = after,
+            None => {
+                self.indices.insert(position, self.changes.len());
+                self.changes.push(VoxelChange {
+                    position,
+                    before,
+                    after,
+                });
+            }
+        }
+    }
+
+    pub fn changes(&self) -> &[VoxelChange] {
+        &self.changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes
+            .iter()
+            .all(|change| change.before == change.after)
+    }
+
+    /// The transaction that restores the scene to how it was before this one.
+    pub fn inverse(&self) -> EditTransaction {
+        let mut inverse = EditTransaction::new();
+        for change in self.changes.iter().rev() {
+            inverse.record(change.position, change.after, change.before);
+        }
+        inverse
+    }
+
+    pub fn touched_chunks(&self, scene: &VoxelScene) -> HashSet<IVec3> {
+        self.changes
+            .iter()
+            .map(|change| scene.chunk_position_of(&change.position))
+            .collect()
+    }
+}
+
+/// Undo and redo stacks of committed transactions.
+pub struct EditHistory {
+    // Oldest transaction at the front, so it can be dropped cheaply once `max_depth` is reached
+    undo_stack: VecDeque<EditTransaction>,
+    redo_stack: Vec<EditTransaction>,
+    max_depth: usize,
+}
+
+impl EditHistory {
+    pub fn new(max_depth: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            max_depth,
+        }
+    }
+
+    pub fn push(&mut self, transaction: EditTransaction) {
+        self.redo_stack.clear();
+        self.undo_stack.push_back(transaction);
+        if self.undo_stack.len() > self.max_depth {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    /// Takes the most recent transaction and returns its inverse, ready to be applied.
+    pub fn undo(&mut self) -> Option<EditTransaction> {
+        let transaction = self.undo_stack.pop_back()?;
+        let inverse = transaction.inverse();
+        self.redo_stack.push(transaction);
+        Some(inverse)
+    }
+
+    /// Takes the most recently undone transaction, ready to be applied again.
+    pub fn redo(&mut self) -> Option<EditTransaction> {
+        let transaction = self.redo_stack.pop()?;
+        self.undo_stack.push_back(transaction.clone());
+        Some(transaction)
+    }
+}
+
+/// An open edit on a scene. Changes are written to the scene immediately, so later edits in the
+/// same transaction see earlier ones, and are recorded until the edit is committed or cancelled.
+pub struct VoxelEdit<'a> {
+    scene: &'a mut VoxelScene,
+    transaction: EditTransaction,
+}
+
+impl<'a> VoxelEdit<'a> {
+    pub fn new(scene: &'a mut VoxelScene) -> Self {
+        Self::resume(scene, EditTransaction::new())
+    }
+
+    /// Continues recording into a transaction returned by `suspend`.
+    pub fn resume(scene: &'a mut VoxelScene, transaction: EditTransaction) -> Self {
+        Self { scene, transaction }
+    }
+
+    /// Stops recording without committing, so an edit can span multiple frames.
+    pub fn suspend(self) -> EditTransaction {
+        self.transaction
+    }
+
+    pub fn voxel_at(&self, position: &IVec3) -> Option<&VoxelData> {
+        self.scene.voxel_at(position)
+    }
+
+    /// Returns false if there is no loaded chunk at `position`.
+    pub fn set_voxel(&mut self, position: &IVec3, voxel: VoxelData) -> bool {
+        let before = match self.scene.voxel_at_mut(position) {
+            Some(current) => std::mem::replace(current, voxel),
+            None => return false,
+        };
+
+        self.transaction.record(*position, before, voxel);
+        self.scene.mark_dirty(position);
+        true
+    }
+
+    pub fn set_voxel_shape(&mut self, position: &IVec3, shape: VoxelShape) -> bool {
+        match self.voxel_at(position) {
+            Some(&voxel) => self.set_voxel(position, VoxelData { shape, ..voxel }),
+            None => false,
+        }
+    }
+
+    /// Records the transaction in the scene's history and returns it, e.g. for syncing.
+    pub fn commit(self) -> EditTransaction {
+        if !self.transaction.is_empty() {
+            self.scene.history.push(self.transaction.clone());
+        }
+        self.transaction
+    }
+
+    /// Reverts every change made through this edit.
+    pub fn cancel(self) {
+        self.scene.apply_transaction(&self.transaction.inverse());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxels::voxel_data::{voxel_shapes, MAX_FLUID_LEVEL};
+    use crate::voxels::voxel_profile::VOXEL_PROFILES;
+    use crate::voxels::voxel_scene::VoxelChunk;
+
+    const CHUNK_SIZE: u32 = 4;
+
+    fn empty_scene() -> VoxelScene {
+        let mut scene = VoxelScene::with_chunk_size(CHUNK_SIZE);
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO, CHUNK_SIZE));
+        scene
+    }
+
+    fn empty() -> VoxelData {
+        VoxelData {
+            shape: voxel_shapes::EMPTY,
+            profile: 0,
+            fluid_level: 0,
+        }
+    }
+
+    fn solid() -> VoxelData {
+        VoxelData {
+            shape: voxel_shapes::ALL,
+            profile: VOXEL_PROFILES.id_of("dirt").unwrap(),
+            fluid_level: 0,
+        }
+    }
+
+    fn water() -> VoxelData {
+        VoxelData {
+            shape: voxel_shapes::ALL,
+            profile: VOXEL_PROFILES.id_of("water").unwrap(),
+            fluid_level: MAX_FLUID_LEVEL,
+        }
+    }
+
+    fn shape_at(scene: &VoxelScene, position: IVec3) -> VoxelShape {
+        scene.voxel_at(&position).unwrap().shape
+    }
+
+    /// Commits a transaction that fills the voxel at `position`.
+    fn fill(scene: &mut VoxelScene, position: IVec3) {
+        let mut edit = scene.begin_edit();
+        edit.set_voxel(&position, solid());
+        edit.commit();
+    }
+
+    #[test]
+    fn repeated_writes_collapse_into_one_change() {
+        let empty = empty();
+        let half = VoxelData {
+            shape: voxel_shapes::BOTTOM,
+            ..solid()
+        };
+
+        let mut transaction = EditTransaction::new();
+        transaction.record(IVec3::ZERO, empty, half);
+        transaction.record(IVec3::ZERO, half, solid());
+        transaction.record(IVec3::X, empty, solid());
+
+        assert_eq!(transaction.changes().len(), 2);
+        let change = transaction.changes()[0];
+        assert_eq!(change.before, empty);
+        assert_eq!(change.after, solid());
+
+        // Writing the original data back leaves nothing to undo
+        transaction.record(IVec3::ZERO, solid(), empty);
+        transaction.record(IVec3::X, solid(), empty);
+        assert!(transaction.is_empty());
+    }
+
+    #[test]
+    fn inverse_restores_the_original_data() {
+        let mut scene = empty_scene();
+        let original = *scene.voxel_at(&IVec3::ONE).unwrap();
+
+        let mut edit = scene.begin_edit();
+        edit.set_voxel(&IVec3::ONE, solid());
+        edit.set_voxel_shape(&IVec3::ONE, voxel_shapes::TOP);
+        let transaction = edit.commit();
+
+        scene.apply_transaction(&transaction.inverse());
+        assert_eq!(*scene.voxel_at(&IVec3::ONE).unwrap(), original);
+        scene.apply_transaction(&transaction);
+        assert_eq!(shape_at(&scene, IVec3::ONE), voxel_shapes::TOP);
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip() {
+        let mut scene = empty_scene();
+        fill(&mut scene, IVec3::ZERO);
+        fill(&mut scene, IVec3::X);
+
+        scene.undo().unwrap();
+        assert_eq!(shape_at(&scene, IVec3::X), voxel_shapes::EMPTY);
+        assert_eq!(shape_at(&scene, IVec3::ZERO), voxel_shapes::ALL);
+        scene.undo().unwrap();
+        assert_eq!(shape_at(&scene, IVec3::ZERO), voxel_shapes::EMPTY);
+        assert!(scene.undo().is_none());
+
+        scene.redo().unwrap();
+        assert_eq!(shape_at(&scene, IVec3::ZERO), voxel_shapes::ALL);
+        assert_eq!(shape_at(&scene, IVec3::X), voxel_shapes::EMPTY);
+        scene.redo().unwrap();
+        assert_eq!(shape_at(&scene, IVec3::X), voxel_shapes::ALL);
+        assert!(scene.redo().is_none());
+    }
+
+    #[test]
+    fn commit_clears_the_redo_stack() {
+        let mut scene = empty_scene();
+        fill(&mut scene, IVec3::ZERO);
+        scene.undo().unwrap();
+
+        fill(&mut scene, IVec3::X);
+        assert!(scene.redo().is_none());
+        assert_eq!(shape_at(&scene, IVec3::ZERO), voxel_shapes::EMPTY);
+    }
+
+    #[test]
+    fn oldest_transaction_is_dropped_at_max_depth() {
+        let mut scene = empty_scene();
+        scene.history = EditHistory::new(2);
+        for x in 0..3 {
+            fill(&mut scene, IVec3::new(x, 0, 0));
+        }
+
+        assert!(scene.undo().is_some());
+        assert!(scene.undo().is_some());
+        assert!(scene.undo().is_none());
+        assert_eq!(shape_at(&scene, IVec3::ZERO), voxel_shapes::ALL);
+        assert_eq!(shape_at(&scene, IVec3::X), voxel_shapes::EMPTY);
+    }
+
+    #[test]
+    fn cancel_restores_the_voxels() {
+        let mut scene = empty_scene();
+        fill(&mut scene, IVec3::ZERO);
+
+        let mut edit = scene.begin_edit();
+        edit.set_voxel_shape(&IVec3::ZERO, voxel_shapes::BOTTOM);
+        edit.set_voxel(&IVec3::Y, solid());
+        edit.cancel();
+
+        assert_eq!(shape_at(&scene, IVec3::ZERO), voxel_shapes::ALL);
+        assert_eq!(shape_at(&scene, IVec3::Y), voxel_shapes::EMPTY);
+
+        // Cancelled edits aren't recorded, so undo reverts the fill instead
+        scene.undo().unwrap();
+        assert_eq!(shape_at(&scene, IVec3::ZERO), voxel_shapes::EMPTY);
+    }
+
+    #[test]
+    fn undo_leaves_fluids_alone() {
+        let mut scene = empty_scene();
+        let mut edit = scene.begin_edit();
+        edit.set_voxel(&IVec3::ZERO, water());
+        edit.set_voxel(&IVec3::X, solid());
+        edit.commit();
+
+        // Fluid flowing into an edited voxel afterwards isn't overwritten either
+        *scene.voxel_at_mut(&IVec3::X).unwrap() = water();
+        let undone = scene.undo().unwrap();
+        assert!(undone.changes().is_empty());
+        assert_eq!(*scene.voxel_at(&IVec3::ZERO).unwrap(), water());
+        assert_eq!(*scene.voxel_at(&IVec3::X).unwrap(), water());
+
+        // Redo doesn't write the recorded fluid back
+        *scene.voxel_at_mut(&IVec3::ZERO).unwrap() = empty();
+        *scene.voxel_at_mut(&IVec3::X).unwrap() = empty();
+        let redone = scene.redo().unwrap();
+        assert_eq!(redone.changes().len(), 1);
+        assert_eq!(*scene.voxel_at(&IVec3::ZERO).unwrap(), empty());
+        assert_eq!(*scene.voxel_at(&IVec3::X).unwrap(), solid());
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+use crate::rendering::material::{MaterialId, MATERIALS};
+
+pub type VoxelProfileId = u16;
+
+#[allow(dead_code)]
+#[derive(Deserialize, Clone, Debug)]
+pub struct VoxelProfile {
+    pub material: String,
+    #[serde(default)]
+    pub decorations: Vec<String>,
+    // Transparent voxels are meshed separately and drawn after all opaque geometry
+    #[serde(default)]
+    pub transparent: bool,
+    // Fluid voxels carry a fill level and are moved around by the fluid simulation
+    #[serde(default)]
+    pub fluid: bool,
+    #[serde(default = "default_color")]
+    pub color: [f32; 4],
+    // Voxels of emissive profiles, such as torches, light up their surroundings
+    #[serde(default)]
+    pub emissive: Option<EmissiveSettings>,
+    // Resolved from `material` when the profile is registered
+    #[serde(skip)]
+    pub material_id: MaterialId,
+}
+
+fn default_color() -> [f32; 4] {
+    [1.0, 1.0, 1.0, 1.0]
+}
+
+/// The point light placed at the center of every voxel of an emissive profile.
+#[derive(Deserialize, Copy, Clone, Debug)]
+pub struct EmissiveSettings {
+    #[serde(default = "default_light_color")]
+    pub color: [f32; 3],
+    // In voxels
+    pub radius: f32,
+    #[serde(default = "default_light_intensity")]
+    pub intensity: f32,
+}
+
+fn default_light_color() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_light_intensity() -> f32 {
+    1.0
+}
+
+pub struct VoxelProfiles {
+    profiles: Vec<VoxelProfile>,
+    ids: HashMap<String, VoxelProfileId>,
+}
+
+impl VoxelProfiles {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            profiles: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, name: &str, mut profile: VoxelProfile) -> VoxelProfileId {
+        profile.material_id = MATERIALS.id_of(&profile.material).unwrap_or_else(|| {
+            eprintln!(
+                "Voxel profile '{}' uses unknown material '{}', using the default",
+                name, profile.material
+            );
+            0
+        });
+
+        let id = self.profiles.len() as VoxelProfileId;
+        self.profiles.push(profile);
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, id: VoxelProfileId) -> &VoxelProfile {
+        &self.profiles[id as usize]
+    }
+
+    pub fn id_of(&self, name: &str) -> Option<VoxelProfileId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name_of(&self, id: VoxelProfileId) -> Option<&str> {
+        self.ids
+            .iter()
+            .find(|(_, &other)| other == id)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Every profile, ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = &VoxelProfile> {
+        self.profiles.iter()
+    }
+
+    fn load_builtin() -> Self {
+        // The first entry is the default profile (id 0)
+        let sources = [
+            (
+                "dirt",
+                include_str!("../resources/voxel_profiles/dirt.json"),
+            ),
+            (
+                "glass",
+                include_str!("../resources/voxel_profiles/glass.json"),
+            ),
+            (
+                "water",
+                include_str!("../resources/voxel_profiles/water.json"),
+            ),
+            (
+                "leaves",
+                include_str!("../resources/voxel_profiles/leaves.json"),
+            ),
+            (
+                "lamp",
+                include_str!("../resources/voxel_profiles/lamp.json"),
+            ),
+        ];
+
+        let mut profiles = Self::new();
+        for (name, source) in sources {
+            let profile = serde_json::from_str(source)
+                .unwrap_or_else(|e| panic!("Invalid voxel profile '{}': {}", name, e));
+            profiles.register(name, profile);
+        }
+        profiles
+    }
+}
+
+pub static VOXEL_PROFILES: Lazy<VoxelProfiles> = Lazy::new(VoxelProfiles::load_builtin);
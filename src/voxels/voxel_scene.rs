@@ -1,28 +1,127 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use glam::{IVec3, UVec3};
+use glam::{IVec3, UVec3, Vec3};
 use noise::{NoiseFn, Perlin};
 use rayon::prelude::*;
 
 use crate::rendering::mesh::Mesh;
 use crate::rendering::vertex::Vertex;
-use crate::voxels::voxel_data::{voxel_shapes, VoxelData, VoxelShape};
-
-pub const CHUNK_SIZE: u32 = 8;
+use crate::voxels::chunk_storage::{PaletteStorage, VoxelStorage};
+use crate::voxels::fluid_simulation::is_fluid;
+use crate::voxels::lod::{lod_group_min, lod_level_for_chunk, DownsampledChunk};
+use crate::voxels::shape_faces::shape_faces;
+use crate::voxels::voxel_data::{voxel_shapes, VoxelData, VoxelShape, MAX_FLUID_LEVEL};
+use crate::voxels::voxel_edit::{EditHistory, EditTransaction, VoxelEdit, DEFAULT_HISTORY_DEPTH};
+use crate::voxels::voxel_profile::{VoxelProfileId, VOXEL_PROFILES};
+
+// Edge length of a chunk in voxels, unless the scene is created with a different one
+pub const DEFAULT_CHUNK_SIZE: u32 = 8;
+
+const NEIGHBOUR_OFFSETS: [IVec3; 6] = [
+    IVec3::X,
+    glam::const_ivec3!([-1, 0, 0]),
+    IVec3::Y,
+    glam::const_ivec3!([0, -1, 0]),
+    IVec3::Z,
+    glam::const_ivec3!([0, 0, -1]),
+];
+
+/// The voxel a ray ran into, see `VoxelScene::raycast`.
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub position: IVec3,
+    pub voxel: VoxelData,
+    pub distance: f32,
+}
 
 pub struct VoxelScene {
     pub chunks: HashMap<IVec3, VoxelChunk>,
+    chunk_size: u32,
+    pub history: EditHistory,
     chunk_initialize_queue: VecDeque<IVec3>,
+    // Chunks whose voxels were edited since the last remesh
+    dirty_chunks: HashSet<IVec3>,
+    // Chunk the camera was in when the levels of detail were last picked
+    lod_eye_chunk: Option<IVec3>,
 }
 
 impl VoxelScene {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
+        Self::with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    pub fn with_chunk_size(chunk_size: u32) -> Self {
+        assert!(chunk_size > 0, "chunks need at least one voxel");
         Self {
             chunks: HashMap::default(),
+            chunk_size,
+            history: EditHistory::new(DEFAULT_HISTORY_DEPTH),
             chunk_initialize_queue: VecDeque::new(),
+            dirty_chunks: HashSet::new(),
+            lod_eye_chunk: None,
         }
     }
 
+    /// Starts a batch of voxel changes that is recorded for undo once committed.
+    pub fn begin_edit(&mut self) -> VoxelEdit<'_> {
+        VoxelEdit::new(self)
+    }
+
+    pub fn resume_edit(&mut self, transaction: EditTransaction) -> VoxelEdit<'_> {
+        VoxelEdit::resume(self, transaction)
+    }
+
+    /// Writes the `after` state of every change, without touching the history.
+    pub fn apply_transaction(&mut self, transaction: &EditTransaction) {
+        for change in transaction.changes() {
+            if let Some(voxel) = self.voxel_at_mut(&change.position) {
+                *voxel = change.after;
+                self.mark_dirty(&change.position);
+            }
+        }
+    }
+
+    /// Reverts the last committed transaction and returns the changes that were applied.
+    /// Fluid voxels are left alone, see `without_fluids`.
+    pub fn undo(&mut self) -> Option<EditTransaction> {
+        let inverse = self.history.undo()?;
+        let inverse = self.without_fluids(&inverse);
+        self.apply_transaction(&inverse);
+        Some(inverse)
+    }
+
+    /// Re-applies the last undone transaction and returns the changes that were applied.
+    /// Fluid voxels are left alone, see `without_fluids`.
+    pub fn redo(&mut self) -> Option<EditTransaction> {
+        let transaction = self.history.redo()?;
+        let transaction = self.without_fluids(&transaction);
+        self.apply_transaction(&transaction);
+        Some(transaction)
+    }
+
+    /// The changes of `transaction` that neither overwrite fluid nor write it. Fluids keep moving
+    /// outside of transactions, so what was recorded for them is most likely stale by now and
+    /// restoring it would create or destroy fluid.
+    fn without_fluids(&self, transaction: &EditTransaction) -> EditTransaction {
+        let mut filtered = EditTransaction::new();
+        for change in transaction.changes() {
+            let overwrites_fluid = self.voxel_at(&change.position).is_some_and(is_fluid);
+            if !overwrites_fluid && !is_fluid(&change.after) {
+                filtered.record(change.position, change.before, change.after);
+            }
+        }
+        filtered
+    }
+
+    pub fn mark_dirty(&mut self, position: &IVec3) {
+        self.dirty_chunks.insert(self.chunk_position_of(position));
+    }
+
+    pub fn take_dirty_chunks(&mut self) -> HashSet<IVec3> {
+        std::mem::take(&mut self.dirty_chunks)
+    }
+
     pub fn voxel_at(&self, position: &IVec3) -> Option<&VoxelData> {
         self.chunk_at(position)
             .map(|chunk| chunk.voxel_scenespace_at(position).unwrap())
@@ -33,26 +132,172 @@ impl VoxelScene {
             .map(|chunk| chunk.voxel_scenespace_at_mut(position).unwrap())
     }
 
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    pub fn chunk_position_of(&self, position: &IVec3) -> IVec3 {
+        let chunk_size = self.chunk_size as i32;
+        IVec3::new(
+            position.x.div_floor(chunk_size),
+            position.y.div_floor(chunk_size),
+            position.z.div_floor(chunk_size),
+        )
+    }
+
     pub fn chunk_at(&self, position: &IVec3) -> Option<&VoxelChunk> {
-        let chunk_pos = IVec3::new(
-            position.x.div_floor(CHUNK_SIZE as i32),
-            position.y.div_floor(CHUNK_SIZE as i32),
-            position.z.div_floor(CHUNK_SIZE as i32),
-        );
-        self.chunks.get(&chunk_pos)
+        self.chunks.get(&self.chunk_position_of(position))
     }
 
     pub fn chunk_at_mut(&mut self, position: &IVec3) -> Option<&mut VoxelChunk> {
-        let chunk_pos = IVec3::new(
-            position.x.div_floor(CHUNK_SIZE as i32),
-            position.y.div_floor(CHUNK_SIZE as i32),
-            position.z.div_floor(CHUNK_SIZE as i32),
-        );
-        self.chunks.get_mut(&chunk_pos)
+        self.chunks.get_mut(&self.chunk_position_of(position))
+    }
+
+    /// The first voxel with any corners set along the ray, if one is within `max_distance`.
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<RayHit> {
+        let direction = direction.normalize();
+        let mut position = origin.floor().as_ivec3();
+        let step = direction.signum().as_ivec3();
+        // Distance along the ray between two voxel boundaries on each axis, and to the next one
+        let mut delta = Vec3::splat(f32::INFINITY);
+        let mut next = Vec3::splat(f32::INFINITY);
+        for axis in 0..3 {
+            if direction[axis] != 0.0 {
+                delta[axis] = direction[axis].recip().abs();
+                let boundary = (position[axis] + step[axis].max(0)) as f32;
+                next[axis] = (boundary - origin[axis]) / direction[axis];
+            }
+        }
+
+        let mut distance = 0.0;
+        while distance <= max_distance {
+            if let Some(voxel) = self.voxel_at(&position) {
+                if voxel.shape != voxel_shapes::EMPTY {
+                    return Some(RayHit {
+                        position,
+                        voxel: *voxel,
+                        distance,
+                    });
+                }
+            }
+
+            let axis = if next.x < next.y && next.x < next.z {
+                0
+            } else if next.y < next.z {
+                1
+            } else {
+                2
+            };
+            distance = next[axis];
+            next[axis] += delta[axis];
+            position[axis] += step[axis];
+        }
+        None
     }
 
     fn register_chunk(&mut self, chunk: VoxelChunk) {
         self.chunks.insert(chunk.position, chunk);
+        // New chunks start out at full detail until the levels are picked again
+        self.lod_eye_chunk = None;
+    }
+
+    /// Remeshes the chunks at `positions` and returns every chunk whose meshes changed.
+    /// Chunks at a lower level of detail are remeshed with their whole group, whose mesh
+    /// belongs to the first chunk of the group, see `lod_group`.
+    pub fn remesh_chunks(&mut self, positions: &HashSet<IVec3>) -> HashSet<IVec3> {
+        self.chunks
+            .par_iter_mut()
+            .filter(|(position, _)| positions.contains(position))
+            .for_each(|(_, chunk)| {
+                chunk.compact_storage();
+                chunk.generate_mesh();
+            });
+
+        let groups = positions
+            .iter()
+            .filter(|position| {
+                self.chunks
+                    .get(position)
+                    .is_some_and(|chunk| chunk.lod_level() > 0)
+            })
+            .map(|position| self.lod_group(position))
+            .collect::<HashSet<(IVec3, i32)>>();
+        let meshes = groups
+            .into_par_iter()
+            .map(|(min, size)| {
+                let level = self.chunks[&min].lod_level();
+                (
+                    min,
+                    DownsampledChunk::new(self, min, size, level).generate_meshes(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut remeshed = positions.clone();
+        for (min, (mesh, transparent_mesh)) in meshes {
+            let chunk = self.chunks.get_mut(&min).unwrap();
+            chunk.mesh = mesh;
+            chunk.transparent_mesh = transparent_mesh;
+            remeshed.insert(min);
+        }
+        remeshed
+    }
+
+    /// The first chunk and the edge length in chunks of the group that the chunk at `position`
+    /// is meshed with. A group whose first chunk isn't loaded falls back to meshing every chunk
+    /// on its own.
+    pub fn lod_group(&self, position: &IVec3) -> (IVec3, i32) {
+        let level = self
+            .chunks
+            .get(position)
+            .map_or(0, |chunk| chunk.lod_level());
+        let min = lod_group_min(*position, level);
+        if self.chunks.contains_key(&min) {
+            (min, 1 << level)
+        } else {
+            (*position, 1)
+        }
+    }
+
+    /// Picks the level of detail of every chunk by its distance to `eye`, and returns the chunks
+    /// that need to be remeshed because of it. Levels only change when `eye` enters another chunk.
+    pub fn update_lod_levels(&mut self, eye: Vec3) -> HashSet<IVec3> {
+        let eye_chunk = self.chunk_position_of(&eye.floor().as_ivec3());
+        if self.lod_eye_chunk == Some(eye_chunk) {
+            return HashSet::new();
+        }
+        self.lod_eye_chunk = Some(eye_chunk);
+
+        let eye = eye_chunk.as_vec3() + 0.5;
+        let mut changed = self
+            .chunks
+            .par_iter_mut()
+            .filter_map(|(position, chunk)| {
+                let level = lod_level_for_chunk(*position, eye);
+                chunk.set_lod_level(level).then_some(*position)
+            })
+            .collect::<HashSet<IVec3>>();
+
+        // Skirts depend on the levels of the chunks around a group
+        let neighbours = changed
+            .iter()
+            .flat_map(|position| NEIGHBOUR_OFFSETS.map(|offset| *position + offset))
+            .filter(|position| {
+                self.chunks
+                    .get(position)
+                    .is_some_and(|chunk| chunk.lod_level() > 0)
+            })
+            .collect::<Vec<IVec3>>();
+        changed.extend(neighbours);
+        changed
+    }
+
+    /// Total bytes used by voxel storage across all chunks.
+    pub fn storage_memory_usage(&self) -> usize {
+        self.chunks
+            .values()
+            .map(|chunk| chunk.storage_memory_usage())
+            .sum()
     }
 
     pub fn initialize_chunk(&mut self, position: &IVec3) {
@@ -63,7 +308,7 @@ impl VoxelScene {
         // Build and register chunk
         while self.chunk_initialize_queue.len() > 0 {
             let chunk_pos = self.chunk_initialize_queue.pop_front().unwrap();
-            let chunk = VoxelChunk::new(chunk_pos);
+            let chunk = VoxelChunk::new(chunk_pos, self.chunk_size);
             self.register_chunk(chunk);
         }
 
@@ -71,30 +316,30 @@ impl VoxelScene {
         let noise = Perlin::new();
         self.chunks.par_iter_mut().for_each(|(_chunk_pos, chunk)| {
             let chunk_pos_scenespace = chunk.scenespace_pos();
-            chunk
-                .voxels
-                .iter_mut()
-                .enumerate()
-                .for_each(|(x, arr0)| {
-                    arr0.iter_mut().enumerate().for_each(|(y, arr1)| {
-                        arr1.iter_mut().enumerate().for_each(|(z, voxel)| {
-                            voxel.shape = if get_density(
-                                IVec3::new(
-                                    chunk_pos_scenespace.x + x as i32,
-                                    chunk_pos_scenespace.y + y as i32,
-                                    chunk_pos_scenespace.z + z as i32,
-                                ),
-                                &noise,
-                            ) < 0.5
-                            {
-                                voxel_shapes::ALL
-                            } else {
-                                voxel_shapes::EMPTY
+            for x in 0..chunk.size {
+                for y in 0..chunk.size {
+                    for z in 0..chunk.size {
+                        let local = UVec3::new(x, y, z);
+                        let position = chunk_pos_scenespace + local.as_ivec3();
+
+                        *chunk.voxel_at_mut(&local) = if get_density(position, &noise) < 0.5 {
+                            VoxelData {
+                                shape: voxel_shapes::ALL,
+                                profile: 0,
+                                fluid_level: 0,
                             }
-                        });
-                    });
-                });
+                        } else {
+                            VoxelData {
+                                shape: voxel_shapes::EMPTY,
+                                profile: 0,
+                                fluid_level: 0,
+                            }
+                        }
+                    }
+                }
+            }
 
+            chunk.compact_storage();
             chunk.generate_mesh();
         });
     }
@@ -112,85 +357,151 @@ pub fn get_density(position: IVec3, noise: &Perlin) -> f64 {
 pub struct VoxelChunk {
     pub position: IVec3,
     pub mesh: Mesh,
-    voxels: [[[VoxelData; CHUNK_SIZE as usize]; CHUNK_SIZE as usize]; CHUNK_SIZE as usize],
+    pub transparent_mesh: Mesh,
+    // Scene space position and profile of every voxel with an emissive profile, found when meshing
+    pub emitters: Vec<(IVec3, VoxelProfileId)>,
+    // Edge length in voxels
+    size: u32,
+    // 0 is meshed at full resolution, see `lod::LOD_DISTANCES`
+    lod_level: u32,
+    voxels: Box<dyn VoxelStorage>,
 }
 
 impl VoxelChunk {
-    pub fn new(position: IVec3) -> Self {
+    pub fn new(position: IVec3, size: u32) -> Self {
         Self {
             position,
             mesh: Mesh::new(),
-            voxels: [[[VoxelData {
-                shape: voxel_shapes::EMPTY,
-            }; CHUNK_SIZE as usize]; CHUNK_SIZE as usize]; CHUNK_SIZE as usize],
+            transparent_mesh: Mesh::new(),
+            emitters: Vec::new(),
+            size,
+            lod_level: 0,
+            voxels: Box::new(PaletteStorage::new(
+                (size * size * size) as usize,
+                VoxelData {
+                    shape: voxel_shapes::EMPTY,
+                    profile: 0,
+                    fluid_level: 0,
+                },
+            )),
         }
     }
 
     pub fn voxel_scenespace_at_mut(&mut self, position: &IVec3) -> Option<&mut VoxelData> {
-        let localized_pos = *position - (self.position * CHUNK_SIZE as i32);
-        if localized_pos.x >= CHUNK_SIZE as i32
-            || localized_pos.y >= CHUNK_SIZE as i32
-            || localized_pos.z >= CHUNK_SIZE as i32
-            || localized_pos.x < 0
-            || localized_pos.y < 0
-            || localized_pos.z < 0
-        {
-            return None;
-        }
+        let localized_pos = self.localize(position)?;
         Some(self.voxel_at_mut(&localized_pos.as_uvec3()))
     }
 
     pub fn voxel_scenespace_at(&self, position: &IVec3) -> Option<&VoxelData> {
-        let localized_pos = *position - (self.position * CHUNK_SIZE as i32);
-        if localized_pos.x >= CHUNK_SIZE as i32
-            || localized_pos.y >= CHUNK_SIZE as i32
-            || localized_pos.z >= CHUNK_SIZE as i32
-            || localized_pos.x < 0
-            || localized_pos.y < 0
-            || localized_pos.z < 0
-        {
+        let localized_pos = self.localize(position)?;
+        Some(self.voxel_at(&localized_pos.as_uvec3()))
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Converts a scene space position into a position inside this chunk, if it lies within it.
+    fn localize(&self, position: &IVec3) -> Option<IVec3> {
+        let localized_pos = *position - self.scenespace_pos();
+        let size = self.size as i32;
+        if localized_pos.cmplt(IVec3::ZERO).any() || localized_pos.cmpge(IVec3::splat(size)).any() {
             return None;
         }
-        Some(self.voxel_at(&localized_pos.as_uvec3()))
+        Some(localized_pos)
+    }
+
+    fn index_of(&self, position: &UVec3) -> usize {
+        ((position.x * self.size + position.y) * self.size + position.z) as usize
     }
 
     pub fn voxel_at(&self, position: &UVec3) -> &VoxelData {
-        &self.voxels[position.x as usize][position.y as usize][position.z as usize]
+        self.voxels.get(self.index_of(position))
     }
 
     pub fn voxel_at_mut(&mut self, position: &UVec3) -> &mut VoxelData {
-        &mut self.voxels[position.x as usize][position.y as usize][position.z as usize]
+        let index = self.index_of(position);
+        self.voxels.get_mut(index)
+    }
+
+    pub fn compact_storage(&mut self) {
+        self.voxels.compact();
+    }
+
+    pub fn storage_memory_usage(&self) -> usize {
+        self.voxels.memory_usage()
     }
 
     pub fn set_voxel_shape(&mut self, position: &UVec3, shape: VoxelShape) {
         self.voxel_at_mut(position).shape = shape
     }
 
+    pub fn lod_level(&self) -> u32 {
+        self.lod_level
+    }
+
+    /// Returns true if the level changed, in which case the chunk needs to be remeshed.
+    pub fn set_lod_level(&mut self, level: u32) -> bool {
+        let changed = self.lod_level != level;
+        self.lod_level = level;
+        changed
+    }
+
     pub fn generate_mesh(&mut self) {
-        let mut vertices = vec![];
-        let mut indices = vec![];
+        self.emitters = self.find_emitters();
+
+        // Lower levels of detail are meshed for a whole group at once, see `remesh_chunks`
+        if self.lod_level > 0 {
+            self.mesh = Mesh::new();
+            self.transparent_mesh = Mesh::new();
+            return;
+        }
+
+        let mut mesh = Mesh::new();
+        let mut transparent_mesh = Mesh::new();
 
-        for x in 0..CHUNK_SIZE {
-            for y in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
+        for x in 0..self.size {
+            for y in 0..self.size {
+                for z in 0..self.size {
                     let pos = UVec3::new(x, y, z);
-                    if self.voxel_at(&pos).shape != voxel_shapes::EMPTY {
-                        generate_faces(self, &pos, &mut vertices, &mut indices);
+                    let voxel = self.voxel_at(&pos);
+                    if voxel.shape != voxel_shapes::EMPTY {
+                        // Transparent voxels go into their own bucket so they can be sorted and blended
+                        let target = if VOXEL_PROFILES.get(voxel.profile).transparent {
+                            &mut transparent_mesh
+                        } else {
+                            &mut mesh
+                        };
+                        generate_faces(self, &pos, &mut target.vertices, &mut target.indices);
                     }
                 }
             }
         }
 
-        let mut mesh = Mesh::new();
-
-        mesh.vertices.append(&mut vertices);
-        mesh.indices.append(&mut indices);
-
         self.mesh = mesh;
+        self.transparent_mesh = transparent_mesh;
     }
 
     pub fn scenespace_pos(&self) -> IVec3 {
-        self.position * CHUNK_SIZE as i32
+        self.position * self.size as i32
+    }
+
+    fn find_emitters(&self) -> Vec<(IVec3, VoxelProfileId)> {
+        let mut emitters = Vec::new();
+        for x in 0..self.size {
+            for y in 0..self.size {
+                for z in 0..self.size {
+                    let pos = UVec3::new(x, y, z);
+                    let voxel = self.voxel_at(&pos);
+                    if voxel.shape != voxel_shapes::EMPTY
+                        && VOXEL_PROFILES.get(voxel.profile).emissive.is_some()
+                    {
+                        emitters.push((pos.as_ivec3() + self.scenespace_pos(), voxel.profile));
+                    }
+                }
+            }
+        }
+        emitters
     }
 }
 
@@ -203,14 +514,66 @@ fn generate_faces(
 ) {
     let position = position.as_ivec3();
     let f_position = position.as_vec3();
-    let global_position = position + (chunk.position * CHUNK_SIZE as i32);
+    let global_position = position + chunk.scenespace_pos();
+    let voxel = chunk.voxel_at(&position.as_uvec3());
+    let profile = VOXEL_PROFILES.get(voxel.profile);
+    let color = profile.color;
+    let material = profile.material_id as u32;
+
+    // Fluids are only as tall as their fill level
+    let height = if profile.fluid {
+        voxel.fluid_level as f32 / MAX_FLUID_LEVEL as f32
+    } else {
+        1.0
+    };
 
     let face_check = |offset: IVec3, space_requirement: VoxelShape| {
         chunk
             .voxel_scenespace_at(&(global_position + offset))
-            .map_or(true, |voxel| !voxel.shape.contains(space_requirement))
+            .map_or(true, |neighbour| {
+                if !neighbour.shape.contains(space_requirement) {
+                    return true;
+                }
+
+                // A transparent neighbour only hides faces of the same profile, so that
+                // adjacent glass voxels don't show their internal faces
+                if neighbour.profile == voxel.profile && profile.fluid {
+                    // Lower fluid beside us leaves part of this face exposed. Fluid above or
+                    // below is part of the same body, so there is no surface between them
+                    return offset.y == 0 && neighbour.fluid_level < voxel.fluid_level;
+                }
+                VOXEL_PROFILES.get(neighbour.profile).transparent
+                    && neighbour.profile != voxel.profile
+            })
     };
 
+    // Partially filled voxels are meshed as the solid spanned by their filled corners
+    if voxel.shape != voxel_shapes::ALL {
+        for face in shape_faces(voxel.shape) {
+            if let Some((offset, requirement)) = face.side {
+                if !face_check(offset, requirement) {
+                    continue;
+                }
+            }
+
+            let offset = vertices.len() as u32;
+            for i in 1..face.corners.len() as u32 - 1 {
+                indices.extend_from_slice(&[offset, offset + i, offset + i + 1]);
+            }
+            for (corner, uv) in &face.corners {
+                vertices.push(Vertex {
+                    position: (Vec3::from(*corner) + f_position).into(),
+                    color,
+                    normal: face.normal,
+                    uv: *uv,
+                    material,
+                    tangent: face.tangent,
+                });
+            }
+        }
+        return;
+    }
+
     let mut build_quad = |quad_verts: &mut [[f32; 3]; 4], normal: [f32; 3]| {
         let offset = vertices.len() as u32;
         indices.append(&mut vec![
@@ -221,53 +584,62 @@ fn generate_faces(
             offset + 2,
             offset + 3,
         ]);
+        let tangent = Vertex::quad_tangent(quad_verts, normal);
 
         // v0
         vertices.push(Vertex {
             position: [
                 quad_verts[0][0] + f_position.x as f32,
-                quad_verts[0][1] + f_position.y as f32,
+                quad_verts[0][1] * height + f_position.y as f32,
                 quad_verts[0][2] + f_position.z as f32,
             ],
-            color: [1.0, 1.0, 1.0],
+            color,
             normal,
             uv: [0.0, 0.0],
+            material,
+            tangent,
         });
 
         // v1
         vertices.push(Vertex {
             position: [
                 quad_verts[1][0] + f_position.x as f32,
-                quad_verts[1][1] + f_position.y as f32,
+                quad_verts[1][1] * height + f_position.y as f32,
                 quad_verts[1][2] + f_position.z as f32,
             ],
-            color: [1.0, 1.0, 1.0],
+            color,
             normal,
             uv: [1.0, 0.0],
+            material,
+            tangent,
         });
 
         // v2
         vertices.push(Vertex {
             position: [
                 quad_verts[2][0] + f_position.x as f32,
-                quad_verts[2][1] + f_position.y as f32,
+                quad_verts[2][1] * height + f_position.y as f32,
                 quad_verts[2][2] + f_position.z as f32,
             ],
-            color: [1.0, 1.0, 1.0],
+            color,
             normal,
             uv: [0.0, 1.0],
+            material,
+            tangent,
         });
 
         // v3
         vertices.push(Vertex {
             position: [
                 quad_verts[3][0] + f_position.x as f32,
-                quad_verts[3][1] + f_position.y as f32,
+                quad_verts[3][1] * height + f_position.y as f32,
                 quad_verts[3][2] + f_position.z as f32,
             ],
-            color: [1.0, 1.0, 1.0],
+            color,
             normal,
             uv: [1.0, 1.0],
+            material,
+            tangent,
         });
     };
 
@@ -349,3 +721,105 @@ fn generate_faces(
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHUNK_SIZE: u32 = 4;
+
+    /// A single chunk with a floor at y = 0 and a voxel at (3, 2, 2) above it.
+    fn scene() -> VoxelScene {
+        let mut scene = VoxelScene::with_chunk_size(CHUNK_SIZE);
+        scene
+            .chunks
+            .insert(IVec3::ZERO, VoxelChunk::new(IVec3::ZERO, CHUNK_SIZE));
+
+        let solid = VoxelData {
+            shape: voxel_shapes::ALL,
+            profile: 0,
+            fluid_level: 0,
+        };
+        for x in 0..CHUNK_SIZE as i32 {
+            for z in 0..CHUNK_SIZE as i32 {
+                *scene.voxel_at_mut(&IVec3::new(x, 0, z)).unwrap() = solid;
+            }
+        }
+        *scene.voxel_at_mut(&IVec3::new(3, 2, 2)).unwrap() = solid;
+        scene
+    }
+
+    #[test]
+    fn raycast_hits_the_first_voxel() {
+        let scene = scene();
+        let hit = scene
+            .raycast(Vec3::new(0.5, 2.5, 2.5), Vec3::new(1.0, 0.0, 0.0), 16.0)
+            .unwrap();
+        assert_eq!(hit.position, IVec3::new(3, 2, 2));
+        assert!((hit.distance - 2.5).abs() < 1e-4);
+
+        let hit = scene
+            .raycast(Vec3::new(0.5, 3.5, 0.5), Vec3::new(1.0, -1.0, 1.0), 16.0)
+            .unwrap();
+        assert_eq!(hit.position.y, 0);
+    }
+
+    #[test]
+    fn axis_aligned_rays_from_voxel_boundaries_hit() {
+        let scene = scene();
+
+        // Starts on the y = 2 and z = 2 planes, where the direction is zero
+        let hit = scene
+            .raycast(Vec3::new(0.5, 2.0, 2.0), Vec3::X, 16.0)
+            .unwrap();
+        assert_eq!(hit.position, IVec3::new(3, 2, 2));
+        assert!((hit.distance - 2.5).abs() < 1e-4);
+
+        // Straight down onto the floor from the corner of a voxel
+        let hit = scene
+            .raycast(Vec3::new(1.0, 3.0, 1.0), -Vec3::Y, 16.0)
+            .unwrap();
+        assert_eq!(hit.position, IVec3::new(1, 0, 1));
+        assert!((hit.distance - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fluid_only_shows_faces_towards_lower_fluid_beside_it() {
+        let mut scene = scene();
+        let water = |fluid_level| VoxelData {
+            shape: voxel_shapes::ALL,
+            profile: VOXEL_PROFILES.id_of("water").unwrap(),
+            fluid_level,
+        };
+        *scene.voxel_at_mut(&IVec3::new(1, 1, 1)).unwrap() = water(MAX_FLUID_LEVEL);
+        *scene.voxel_at_mut(&IVec3::new(1, 2, 1)).unwrap() = water(3);
+        *scene.voxel_at_mut(&IVec3::new(2, 1, 1)).unwrap() = water(3);
+
+        let chunk = scene.chunks.get_mut(&IVec3::ZERO).unwrap();
+        chunk.generate_mesh();
+        let vertices = |normal: [f32; 3], position: fn([f32; 3]) -> bool| {
+            [&chunk.mesh, &chunk.transparent_mesh]
+                .iter()
+                .flat_map(|mesh| mesh.vertices.iter())
+                .filter(|vertex| vertex.normal == normal && position(vertex.position))
+                .count()
+        };
+
+        // No surface between the full voxel and the fluid resting on it
+        assert_eq!(vertices([0.0, 1.0, 0.0], |p| p[1] == 2.0), 0);
+        assert_eq!(vertices([0.0, -1.0, 0.0], |p| p[0] < 3.0 && p[1] == 2.0), 0);
+        // But the full voxel shows above the lower fluid beside it
+        assert_eq!(vertices([1.0, 0.0, 0.0], |p| p[0] == 2.0 && p[1] == 1.0), 2);
+    }
+
+    #[test]
+    fn raycast_stops_at_max_distance() {
+        let scene = scene();
+        assert!(scene
+            .raycast(Vec3::new(0.5, 2.5, 2.5), Vec3::X, 2.0)
+            .is_none());
+        assert!(scene
+            .raycast(Vec3::new(0.5, 2.5, 2.5), Vec3::Y, 16.0)
+            .is_none());
+    }
+}